@@ -0,0 +1,10 @@
+CREATE TABLE sessions (
+    token_hash TEXT PRIMARY KEY,
+    session_type TEXT NOT NULL,
+    subject_id INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX sessions_subject_idx ON sessions (session_type, subject_id);
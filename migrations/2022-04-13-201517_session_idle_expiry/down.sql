@@ -0,0 +1,3 @@
+DROP INDEX sessions_idle_expires_at_idx;
+ALTER TABLE sessions DROP COLUMN idle_expires_at;
+ALTER TABLE sessions DROP COLUMN idle_timeout;
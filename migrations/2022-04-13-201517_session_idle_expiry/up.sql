@@ -0,0 +1,9 @@
+-- Existing sessions keep the 30 day lifetime they were issued with
+ALTER TABLE sessions ADD COLUMN idle_timeout INTEGER NOT NULL DEFAULT 2592000;
+ALTER TABLE sessions ALTER COLUMN idle_timeout DROP DEFAULT;
+
+ALTER TABLE sessions ADD COLUMN idle_expires_at TIMESTAMPTZ;
+UPDATE sessions SET idle_expires_at = expires_at;
+ALTER TABLE sessions ALTER COLUMN idle_expires_at SET NOT NULL;
+
+CREATE INDEX sessions_idle_expires_at_idx ON sessions (idle_expires_at);
@@ -0,0 +1,3 @@
+DROP TABLE one_time_tokens;
+
+ALTER TABLE professors DROP COLUMN email;
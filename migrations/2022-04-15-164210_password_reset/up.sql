@@ -0,0 +1,11 @@
+ALTER TABLE professors ADD COLUMN email TEXT;
+
+CREATE TABLE one_time_tokens (
+    token_hash TEXT PRIMARY KEY,
+    purpose TEXT NOT NULL,
+    session_type TEXT NOT NULL,
+    subject_id INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
@@ -0,0 +1,8 @@
+CREATE TABLE login_lockouts (
+    kind TEXT NOT NULL,
+    value TEXT NOT NULL,
+    failures INTEGER NOT NULL,
+    last_failure_at TIMESTAMPTZ NOT NULL,
+    locked_until TIMESTAMPTZ,
+    PRIMARY KEY (kind, value)
+);
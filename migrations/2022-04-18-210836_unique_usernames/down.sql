@@ -0,0 +1,7 @@
+DROP TRIGGER admin_logins_username ON admin_logins;
+DROP TRIGGER professor_logins_username ON professor_logins;
+DROP TRIGGER applicant_logins_username ON applicant_logins;
+DROP FUNCTION register_account_username();
+DROP TABLE account_usernames;
+ALTER TABLE professor_logins DROP CONSTRAINT professor_logins_username_key;
+ALTER TABLE applicant_logins DROP CONSTRAINT applicant_logins_username_key;
@@ -0,0 +1,71 @@
+-- Refuse to migrate while a username belongs to more than one account, listing the
+-- usernames so they can be renamed by hand first.
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(format('%s (%s)', username, kinds), ', ' ORDER BY username)
+    INTO duplicates
+    FROM (
+        SELECT username, string_agg(account_kind, ', ' ORDER BY account_kind) AS kinds
+        FROM (
+            SELECT username, 'APPLICANT' AS account_kind FROM applicant_logins
+            UNION ALL
+            SELECT username, 'PROFESSOR' FROM professor_logins
+            UNION ALL
+            SELECT username, 'ADMINISTRATOR' FROM admin_logins
+        ) accounts
+        GROUP BY username
+        HAVING count(*) > 1
+    ) duplicate_usernames;
+
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'usernames are used by more than one account: %', duplicates
+            USING HINT = 'Rename the duplicate accounts before running this migration.';
+    END IF;
+END
+$$;
+
+ALTER TABLE applicant_logins ADD CONSTRAINT applicant_logins_username_key UNIQUE (username);
+ALTER TABLE professor_logins ADD CONSTRAINT professor_logins_username_key UNIQUE (username);
+
+-- Every username in use by any kind of account, kept up to date by triggers so the
+-- primary key enforces uniqueness across all of them.
+CREATE TABLE account_usernames (
+    username TEXT PRIMARY KEY,
+    account_kind TEXT NOT NULL
+);
+
+INSERT INTO account_usernames (username, account_kind)
+    SELECT username, 'APPLICANT' FROM applicant_logins
+    UNION ALL
+    SELECT username, 'PROFESSOR' FROM professor_logins
+    UNION ALL
+    SELECT username, 'ADMINISTRATOR' FROM admin_logins;
+
+CREATE FUNCTION register_account_username() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        DELETE FROM account_usernames WHERE username = OLD.username;
+    END IF;
+
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        INSERT INTO account_usernames (username, account_kind) VALUES (NEW.username, TG_ARGV[0]);
+        RETURN NEW;
+    END IF;
+
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER applicant_logins_username
+    AFTER INSERT OR DELETE OR UPDATE OF username ON applicant_logins
+    FOR EACH ROW EXECUTE FUNCTION register_account_username('APPLICANT');
+
+CREATE TRIGGER professor_logins_username
+    AFTER INSERT OR DELETE OR UPDATE OF username ON professor_logins
+    FOR EACH ROW EXECUTE FUNCTION register_account_username('PROFESSOR');
+
+CREATE TRIGGER admin_logins_username
+    AFTER INSERT OR DELETE OR UPDATE OF username ON admin_logins
+    FOR EACH ROW EXECUTE FUNCTION register_account_username('ADMINISTRATOR');
@@ -0,0 +1,5 @@
+DELETE FROM sessions WHERE session_type = 'ADMINISTRATOR';
+
+ALTER TABLE admin_logins DROP CONSTRAINT admin_logins_username_key;
+ALTER TABLE admin_logins DROP COLUMN id;
+ALTER TABLE admin_logins ADD PRIMARY KEY (username);
@@ -0,0 +1,6 @@
+ALTER TABLE admin_logins DROP CONSTRAINT admin_logins_pkey;
+ALTER TABLE admin_logins ADD COLUMN id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY;
+ALTER TABLE admin_logins ADD CONSTRAINT admin_logins_username_key UNIQUE (username);
+
+-- Administrator sessions without an id can no longer be resolved to an account.
+DELETE FROM sessions WHERE session_type = 'ADMINISTRATOR';
@@ -0,0 +1,6 @@
+CREATE TABLE session_generations (
+    session_type TEXT NOT NULL,
+    subject_id INTEGER NOT NULL,
+    generation INTEGER NOT NULL,
+    PRIMARY KEY (session_type, subject_id)
+);
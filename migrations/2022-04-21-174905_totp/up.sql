@@ -0,0 +1,18 @@
+CREATE TABLE totp_enrollments (
+    session_type TEXT NOT NULL,
+    subject_id INTEGER NOT NULL,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    confirmed_at TIMESTAMPTZ,
+    last_used_step BIGINT,
+    PRIMARY KEY (session_type, subject_id)
+);
+
+CREATE TABLE totp_recovery_codes (
+    code_hash TEXT PRIMARY KEY,
+    session_type TEXT NOT NULL,
+    subject_id INTEGER NOT NULL,
+    used_at TIMESTAMPTZ,
+    FOREIGN KEY (session_type, subject_id)
+        REFERENCES totp_enrollments (session_type, subject_id) ON DELETE CASCADE
+);
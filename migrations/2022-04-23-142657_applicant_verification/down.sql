@@ -0,0 +1 @@
+ALTER TABLE applicant_logins DROP COLUMN verified;
@@ -0,0 +1,3 @@
+-- Accounts made by administrators are trusted, only self-registered ones need verifying.
+ALTER TABLE applicant_logins ADD COLUMN verified BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE applicant_logins SET verified = TRUE;
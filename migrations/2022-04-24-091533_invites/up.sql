@@ -0,0 +1,11 @@
+CREATE TABLE invites (
+    id SERIAL PRIMARY KEY,
+    token_hash TEXT UNIQUE NOT NULL,
+    session_type TEXT NOT NULL,
+    subject_id INTEGER NOT NULL,
+    created_by INTEGER REFERENCES admin_logins(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    accepted_at TIMESTAMPTZ,
+    cancelled_at TIMESTAMPTZ
+);
@@ -0,0 +1 @@
+ALTER TABLE sessions DROP COLUMN ip, DROP COLUMN user_agent;
@@ -0,0 +1 @@
+ALTER TABLE sessions ADD COLUMN ip TEXT, ADD COLUMN user_agent TEXT;
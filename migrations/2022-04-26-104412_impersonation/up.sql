@@ -0,0 +1,10 @@
+ALTER TABLE sessions ADD COLUMN impersonator INTEGER REFERENCES admin_logins(id) ON DELETE CASCADE;
+
+CREATE TABLE audit_log (
+    id SERIAL PRIMARY KEY,
+    administrator_id INTEGER REFERENCES admin_logins(id) ON DELETE SET NULL,
+    action TEXT NOT NULL,
+    session_type TEXT,
+    subject_id INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1 @@
+ALTER TABLE professor_logins DROP COLUMN oidc_subject;
@@ -0,0 +1,2 @@
+-- The identity provider subject a professor signs in with through single sign-on.
+ALTER TABLE professor_logins ADD COLUMN oidc_subject TEXT UNIQUE;
@@ -0,0 +1 @@
+ALTER TABLE admin_logins DROP COLUMN active;
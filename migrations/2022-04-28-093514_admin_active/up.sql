@@ -0,0 +1,2 @@
+-- Disabled administrators keep their login but cannot log in with it.
+ALTER TABLE admin_logins ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,2 @@
+ALTER TABLE professor_logins DROP COLUMN active;
+ALTER TABLE applicant_logins DROP COLUMN active;
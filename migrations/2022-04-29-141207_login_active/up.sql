@@ -0,0 +1,3 @@
+-- Suspended applicants and professors keep their login and data but cannot log in.
+ALTER TABLE applicant_logins ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE professor_logins ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,12 @@
+ALTER TABLE student_applied_to ALTER COLUMN status TYPE TEXT USING (
+    CASE status
+        WHEN 'offer' THEN 'ACCEPTED'
+        WHEN 'accepted' THEN 'ACCEPTED'
+        WHEN 'declined' THEN 'DENIED'
+        WHEN 'withdrawn' THEN 'DENIED'
+        WHEN 'rejected' THEN 'DENIED'
+        ELSE 'PENDING'
+    END
+);
+
+DROP TYPE application_status;
@@ -0,0 +1,40 @@
+-- Refuse to migrate while an application has a status other than the three the
+-- application used to set, listing them so they can be fixed by hand first.
+DO $$
+DECLARE
+    unknown TEXT;
+BEGIN
+    SELECT string_agg(DISTINCT format('%L', status), ', ')
+    INTO unknown
+    FROM student_applied_to
+    WHERE status NOT IN ('PENDING', 'ACCEPTED', 'DENIED');
+
+    IF unknown IS NOT NULL THEN
+        RAISE EXCEPTION 'applications have unknown statuses: %', unknown
+            USING HINT = 'Set them to PENDING, ACCEPTED or DENIED before running this migration.';
+    END IF;
+END
+$$;
+
+CREATE TYPE application_status AS ENUM (
+    'draft',
+    'submitted',
+    'under_review',
+    'shortlisted',
+    'interview',
+    'offer',
+    'accepted',
+    'declined',
+    'withdrawn',
+    'rejected'
+);
+
+-- A professor accepting an application used to be final, but now only makes an offer
+-- that the applicant still has to accept.
+ALTER TABLE student_applied_to ALTER COLUMN status TYPE application_status USING (
+    CASE status
+        WHEN 'PENDING' THEN 'submitted'
+        WHEN 'ACCEPTED' THEN 'offer'
+        WHEN 'DENIED' THEN 'rejected'
+    END
+)::application_status;
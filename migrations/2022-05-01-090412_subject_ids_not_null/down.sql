@@ -0,0 +1,2 @@
+ALTER TABLE one_time_tokens ALTER COLUMN subject_id DROP NOT NULL;
+ALTER TABLE sessions ALTER COLUMN subject_id DROP NOT NULL;
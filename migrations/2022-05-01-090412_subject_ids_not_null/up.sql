@@ -0,0 +1,6 @@
+-- Every session type has had a subject id since administrators got ids, so rows
+-- without one can no longer be used.
+DELETE FROM sessions WHERE subject_id IS NULL;
+ALTER TABLE sessions ALTER COLUMN subject_id SET NOT NULL;
+DELETE FROM one_time_tokens WHERE subject_id IS NULL;
+ALTER TABLE one_time_tokens ALTER COLUMN subject_id SET NOT NULL;
@@ -0,0 +1,7 @@
+-- Single sign-on logins that were sent to the identity provider and have not come back.
+CREATE TABLE oidc_logins (
+    state TEXT PRIMARY KEY,
+    nonce TEXT NOT NULL,
+    pkce_verifier TEXT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL
+);
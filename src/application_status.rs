@@ -0,0 +1,188 @@
+//! The statuses an application moves through, stored as the `application_status`
+//! Postgres enum:
+//!
+//! ```text
+//! draft -> submitted -> under_review -> shortlisted -> interview -> offer -> accepted
+//!                                                                        -> declined
+//! ```
+//!
+//! A professor can skip ahead to `offer` or `rejected` from any status between
+//! `submitted` and `interview`, and the applicant can withdraw until an offer is made.
+//! `accepted`, `declined`, `withdrawn` and `rejected` are final.
+
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// The Diesel SQL type of the `application_status` Postgres enum.
+#[derive(SqlType, QueryId, Debug, Clone, Copy)]
+#[postgres(type_name = "application_status")]
+pub struct ApplicationStatusType;
+
+#[derive(
+    AsExpression, FromSqlRow, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash,
+)]
+#[sql_type = "ApplicationStatusType"]
+#[serde(rename_all = "snake_case")]
+pub enum ApplicationStatus {
+    /// Started by the applicant, but not sent to the professor yet.
+    Draft,
+    Submitted,
+    UnderReview,
+    Shortlisted,
+    Interview,
+    Offer,
+    Accepted,
+    Declined,
+    Withdrawn,
+    Rejected,
+}
+
+use ApplicationStatus::*;
+
+impl ApplicationStatus {
+    pub const ALL: [ApplicationStatus; 10] = [
+        Draft,
+        Submitted,
+        UnderReview,
+        Shortlisted,
+        Interview,
+        Offer,
+        Accepted,
+        Declined,
+        Withdrawn,
+        Rejected,
+    ];
+
+    /// Gets the name of the status in the database and the API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Draft => "draft",
+            Submitted => "submitted",
+            UnderReview => "under_review",
+            Shortlisted => "shortlisted",
+            Interview => "interview",
+            Offer => "offer",
+            Accepted => "accepted",
+            Declined => "declined",
+            Withdrawn => "withdrawn",
+            Rejected => "rejected",
+        }
+    }
+
+    /// Parses a status name. The uppercase `PENDING`, `ACCEPTED` and `DENIED` statuses
+    /// from before there were more than three are still understood, as `submitted`,
+    /// `offer` and `rejected`, since a professor accepting an application only makes an
+    /// offer now.
+    pub fn parse(name: &str) -> Option<ApplicationStatus> {
+        match name {
+            "PENDING" => Some(Submitted),
+            "ACCEPTED" => Some(Offer),
+            "DENIED" => Some(Rejected),
+            _ => ApplicationStatus::ALL
+                .iter()
+                .copied()
+                .find(|status| status.as_str() == name),
+        }
+    }
+
+    /// Checks whether an application with this status can move to another.
+    pub fn can_become(self, next: ApplicationStatus) -> bool {
+        matches!(
+            (self, next),
+            (Draft, Submitted | Withdrawn)
+                | (
+                    Submitted,
+                    UnderReview | Shortlisted | Interview | Offer | Rejected | Withdrawn
+                )
+                | (
+                    UnderReview,
+                    Shortlisted | Interview | Offer | Rejected | Withdrawn
+                )
+                | (Shortlisted, Interview | Offer | Rejected | Withdrawn)
+                | (Interview, Offer | Rejected | Withdrawn)
+                | (Offer, Accepted | Declined)
+        )
+    }
+
+    /// Whether the applicant moves an application to this status, rather than the
+    /// professor it was sent to.
+    pub fn set_by_applicant(self) -> bool {
+        matches!(self, Draft | Submitted | Withdrawn | Accepted | Declined)
+    }
+}
+
+impl std::fmt::Display for ApplicationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let label = match self {
+            Draft => "Draft",
+            Submitted => "Submitted",
+            UnderReview => "Under Review",
+            Shortlisted => "Shortlisted",
+            Interview => "Interview",
+            Offer => "Offer",
+            Accepted => "Accepted",
+            Declined => "Declined",
+            Withdrawn => "Withdrawn",
+            Rejected => "Rejected",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+impl ToSql<ApplicationStatusType, Pg> for ApplicationStatus {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<ApplicationStatusType, Pg> for ApplicationStatus {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let name = std::str::from_utf8(not_none!(bytes))?;
+        ApplicationStatus::ALL
+            .iter()
+            .copied()
+            .find(|status| status.as_str() == name)
+            .ok_or_else(|| format!("unknown application status {}", name).into())
+    }
+}
+
+/// Application status tests.
+#[cfg(test)]
+mod test {
+    use super::ApplicationStatus::{self, *};
+
+    // Tests that final statuses cannot change and decisions cannot be flipped.
+    #[test]
+    fn decisions_are_final() {
+        for status in [Accepted, Declined, Withdrawn, Rejected] {
+            assert!(ApplicationStatus::ALL
+                .iter()
+                .all(|next| !status.can_become(*next)));
+        }
+
+        assert!(Submitted.can_become(Offer));
+        assert!(!Offer.can_become(Rejected));
+        assert!(!Rejected.can_become(Offer));
+        assert!(!Interview.can_become(UnderReview));
+        assert!(!Offer.can_become(Withdrawn));
+    }
+
+    // Tests that current names and the old uppercase statuses are both understood, and
+    // that the old `ACCEPTED` is not mistaken for `accepted`.
+    #[test]
+    fn parses_names_and_legacy_statuses() {
+        for status in ApplicationStatus::ALL {
+            assert_eq!(ApplicationStatus::parse(status.as_str()), Some(status));
+        }
+
+        assert_eq!(ApplicationStatus::parse("PENDING"), Some(Submitted));
+        assert_eq!(ApplicationStatus::parse("ACCEPTED"), Some(Offer));
+        assert_eq!(ApplicationStatus::parse("DENIED"), Some(Rejected));
+        assert_eq!(ApplicationStatus::parse("accepted"), Some(Accepted));
+        assert_eq!(ApplicationStatus::parse("approved"), None);
+    }
+}
@@ -1,11 +1,14 @@
+use crate::application_status::ApplicationStatus;
+use crate::ldap::{LdapDirectory, ProfessorColumn};
 use crate::models::*;
 use crate::request_guards::state::SessionType;
 use crate::rest::Login;
 use crate::schema;
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use rocket_sync_db_pools::{database, diesel};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[database("db")]
 pub struct DbConn(diesel::PgConnection);
@@ -60,15 +63,11 @@ pub async fn delete_research_field(conn: &DbConn, research_field_id: ID) -> Quer
     Ok(())
 }
 
-/// This function takes in a name of a professor that can be converted to a string that is then
-/// adds it to the database after generating a professor ID.
-pub async fn create_professor<T: AsRef<str>>(conn: &DbConn, name: T) -> QueryResult<ID> {
+/// This function takes in a professor which is then inserted into the professor
+/// table in the database
+pub async fn create_professor(conn: &DbConn, new_professor: NewProfessor) -> QueryResult<ID> {
     use schema::professors;
 
-    let new_professor = NewProfessor {
-        name: name.as_ref().to_owned(),
-    };
-
     conn.run(move |c| {
         diesel::insert_into(professors::table)
             .values(&new_professor)
@@ -91,7 +90,7 @@ pub async fn get_professor(conn: &DbConn, professor_id: ID) -> QueryResult<Optio
 pub async fn get_professors(conn: &DbConn) -> QueryResult<Vec<Professor>> {
     use schema::professors::dsl::*;
 
-    conn.run(|c| professors.select((id, name)).load::<Professor>(c))
+    conn.run(|c| professors.select((id, name, email)).load::<Professor>(c))
         .await
 }
 
@@ -235,6 +234,14 @@ pub async fn edit_professor(
             .execute(c)
     })
     .await?;
+    if let Some(v) = prof_data.email {
+        conn.run(move |c| {
+            diesel::update(professors.find(prof_id))
+                .set(email.eq(v))
+                .execute(c)
+        })
+        .await?;
+    }
 
     Ok(())
 }
@@ -265,23 +272,20 @@ pub async fn delete_applicant(conn: &DbConn, applicant_id: ID) -> QueryResult<()
     Ok(())
 }
 
-pub const APPLICATION_ACCEPTED: &'static str = "ACCEPTED";
-pub const APPLICATION_DENIED: &'static str = "DENIED";
-pub const APPLICATION_PENDING: &'static str = "PENDING";
-
 /// This function takes in an applicant ID and proffesor ID which are then added to a new table showing
 /// specifiying that the applicant has applied to this professor.
 pub async fn add_application_to_applicant(
     conn: &DbConn,
     applicant_id: ID,
     professor_id: ID,
+    status: ApplicationStatus,
 ) -> QueryResult<()> {
     use schema::student_applied_to;
 
     let new_student_applied_to = StudentAppliedTo {
         applicant_id: applicant_id.to_owned(),
         prof_id: professor_id.to_owned(),
-        status: APPLICATION_PENDING.to_string(),
+        status,
     };
 
     conn.run(move |c| {
@@ -299,7 +303,7 @@ pub async fn get_profs_applicant_applied_to(
     conn: &DbConn,
     applicant_id: ID,
 ) -> QueryResult<Vec<Professor>> {
-    use dsl_professors::{id, name, professors};
+    use dsl_professors::{email, id, name, professors};
     use dsl_student_applied_to::{applicant_id as dsl_applicant_id, prof_id, student_applied_to};
     use schema::professors::dsl as dsl_professors;
     use schema::student_applied_to::dsl as dsl_student_applied_to;
@@ -308,49 +312,54 @@ pub async fn get_profs_applicant_applied_to(
         student_applied_to
             .filter(dsl_applicant_id.eq(applicant_id))
             .inner_join(professors.on(id.eq(prof_id)))
-            .select((id, name))
+            .select((id, name, email))
             .load::<Professor>(c)
     })
     .await
 }
 
-/// Sets an applicant's application status.
-async fn set_applicant_application_status(
+/// The outcome of moving an application to a new status.
+#[derive(Debug, PartialEq)]
+pub enum ApplicationChange {
+    Done,
+    NotFound,
+    /// The application's current status cannot move to the new one.
+    IllegalTransition,
+}
+
+/// Moves an applicant's application to a new status, if its current status allows it.
+pub async fn set_application_status(
     conn: &DbConn,
     app_id: ID,
     professor_id: ID,
-    new_status: String,
-) -> QueryResult<()> {
+    next_status: ApplicationStatus,
+) -> QueryResult<ApplicationChange> {
     use schema::student_applied_to::dsl::*;
 
     conn.run(move |c| {
-        diesel::update(student_applied_to.find((app_id, professor_id)))
-            .set(status.eq(new_status))
-            .execute(c)
+        c.transaction(|| {
+            let current = student_applied_to
+                .find((app_id, professor_id))
+                .select(status)
+                .for_update()
+                .first::<ApplicationStatus>(c)
+                .optional()?;
+
+            match current {
+                None => Ok(ApplicationChange::NotFound),
+                Some(current) if !current.can_become(next_status) => {
+                    Ok(ApplicationChange::IllegalTransition)
+                }
+                Some(_) => {
+                    diesel::update(student_applied_to.find((app_id, professor_id)))
+                        .set(status.eq(next_status))
+                        .execute(c)?;
+                    Ok(ApplicationChange::Done)
+                }
+            }
+        })
     })
-    .await?;
-
-    Ok(())
-}
-
-/// Accepts an applicant's application.
-pub async fn accept_applicant_application(
-    conn: &DbConn,
-    app_id: ID,
-    professor_id: ID,
-) -> QueryResult<()> {
-    set_applicant_application_status(conn, app_id, professor_id, APPLICATION_ACCEPTED.to_string())
-        .await
-}
-
-/// Denies an applicant's application.
-pub async fn deny_applicant_application(
-    conn: &DbConn,
-    app_id: ID,
-    professor_id: ID,
-) -> QueryResult<()> {
-    set_applicant_application_status(conn, app_id, professor_id, APPLICATION_DENIED.to_string())
-        .await
+    .await
 }
 
 /// Uploads a file blob for an applicant.
@@ -494,7 +503,7 @@ pub struct ApplicantIDNameField {
 pub async fn get_applications_for_professor_with_status(
     conn: &DbConn,
     professor_id: ID,
-    status: String,
+    status: ApplicationStatus,
 ) -> QueryResult<Vec<ApplicantIDNameField>> {
     use schema::applicants::dsl::{
         applicants, desired_field_id as app_desired_field_id, email, id as app_id, name as app_name,
@@ -520,6 +529,7 @@ pub async fn get_applications_for_professor_with_status(
 pub enum LoginError {
     CredentialError,
     DatabaseError,
+    DirectoryError,
 }
 
 #[derive(Queryable)]
@@ -528,21 +538,44 @@ pub struct UserIdHash {
     pub password_hash: String,
 }
 
+/// Rehashes a password that was just verified if its hash was made with a lower cost than
+/// the configured one, so that old hashes are upgraded as their owners log in.
+async fn upgrade_password_hash(
+    conn: &DbConn,
+    account: SessionType,
+    password: String,
+    password_hash: &str,
+    bcrypt_cost: u32,
+) {
+    let outdated = password_hash
+        .parse::<bcrypt::HashParts>()
+        .is_ok_and(|parts| parts.get_cost() < bcrypt_cost);
+
+    if outdated {
+        if let Err(e) = set_account_password(conn, account, password, bcrypt_cost).await {
+            eprintln!("Error occured while trying to upgrade password hash: {}", e);
+        }
+    }
+}
+
 pub async fn validate_login(
     conn: &DbConn,
     username: String,
     password: String,
+    bcrypt_cost: u32,
+    ldap: &LdapDirectory,
 ) -> Result<SessionType, LoginError> {
     use schema::admin_logins::dsl::{
-        admin_logins, bcrypt_hash as admin_password_hash, username as admin_username,
+        active as admin_active, admin_logins, bcrypt_hash as admin_password_hash,
+        id as db_admin_id, username as admin_username,
     };
     use schema::applicant_logins::dsl::{
-        applicant_logins, bcrypt_hash as applicant_password_hash, id as db_applicant_id,
-        username as applicant_username,
+        active as applicant_active, applicant_logins, bcrypt_hash as applicant_password_hash,
+        id as db_applicant_id, username as applicant_username,
     };
     use schema::professor_logins::dsl::{
-        bcrypt_hash as professor_password_hash, id as db_professor_id, professor_logins,
-        username as professor_username,
+        active as professor_active, bcrypt_hash as professor_password_hash, id as db_professor_id,
+        professor_logins, username as professor_username,
     };
 
     let username = username.to_owned();
@@ -554,6 +587,7 @@ pub async fn validate_login(
             .run(move |c| {
                 applicant_logins
                     .filter(applicant_username.eq(username))
+                    .filter(applicant_active.eq(true))
                     .select((db_applicant_id, applicant_password_hash))
                     .first::<UserIdHash>(c)
             })
@@ -566,10 +600,13 @@ pub async fn validate_login(
                 let applicant_id = applicant.id;
                 let password_hash = applicant.password_hash;
 
-                if bcrypt::verify(password, password_hash.as_str())
+                if bcrypt::verify(&password, password_hash.as_str())
                     .map_err(|_| LoginError::CredentialError)?
                 {
-                    return Ok(SessionType::Applicant(applicant_id));
+                    let account = SessionType::Applicant(applicant_id);
+                    upgrade_password_hash(conn, account, password, &password_hash, bcrypt_cost)
+                        .await;
+                    return Ok(account);
                 }
             }
             None => {}
@@ -583,6 +620,7 @@ pub async fn validate_login(
             .run(move |c| {
                 professor_logins
                     .filter(professor_username.eq(username))
+                    .filter(professor_active.eq(true))
                     .select((db_professor_id, professor_password_hash))
                     .first::<UserIdHash>(c)
             })
@@ -595,33 +633,41 @@ pub async fn validate_login(
                 let professor_id = professor.id;
                 let password_hash = professor.password_hash;
 
-                if bcrypt::verify(password, password_hash.as_str())
+                if bcrypt::verify(&password, password_hash.as_str())
                     .map_err(|_| LoginError::CredentialError)?
                 {
-                    return Ok(SessionType::Professor(professor_id));
+                    let account = SessionType::Professor(professor_id);
+                    upgrade_password_hash(conn, account, password, &password_hash, bcrypt_cost)
+                        .await;
+                    return Ok(account);
                 }
             }
             None => {}
         };
     }
 
+    let ldap_username = username.clone();
     let administrator = conn
         .run(move |c| {
             admin_logins
                 .filter(admin_username.eq(username))
-                .select(admin_password_hash)
-                .first::<String>(c)
+                .filter(admin_active.eq(true))
+                .select((db_admin_id, admin_password_hash))
+                .first::<UserIdHash>(c)
         })
         .await
         .optional()
         .map_err(|_| LoginError::DatabaseError)?;
 
     match administrator {
-        Some(password_hash) => {
-            if bcrypt::verify(password, password_hash.as_str())
+        Some(administrator) => {
+            if bcrypt::verify(&password, administrator.password_hash.as_str())
                 .map_err(|_| LoginError::CredentialError)?
             {
-                return Ok(SessionType::Administrator);
+                let account = SessionType::Administrator(administrator.id);
+                let password_hash = administrator.password_hash;
+                upgrade_password_hash(conn, account, password, &password_hash, bcrypt_cost).await;
+                return Ok(account);
             } else {
                 return Err(LoginError::CredentialError);
             }
@@ -629,13 +675,151 @@ pub async fn validate_login(
         None => {}
     };
 
+    // Directory accounts have no local login, so only usernames that no local account
+    // has are looked up in the directory.
+    if ldap.is_enabled()
+        && !username_taken(conn, ldap_username.clone())
+            .await
+            .map_err(|_| LoginError::DatabaseError)?
+    {
+        let attribute = match ldap.authenticate(&ldap_username, &password).await {
+            Ok(attribute) => attribute,
+            Err(e) => {
+                eprintln!(
+                    "Error occured while trying to authenticate with LDAP: {}",
+                    e
+                );
+                return Err(LoginError::DirectoryError);
+            }
+        };
+
+        if let Some(attribute) = attribute {
+            let professor =
+                get_directory_professor(conn, ldap.config().professor_column, attribute)
+                    .await
+                    .map_err(|_| LoginError::DatabaseError)?;
+
+            if let Some(professor_id) = professor {
+                let account = SessionType::Professor(professor_id);
+                if account_active(conn, account)
+                    .await
+                    .map_err(|_| LoginError::DatabaseError)?
+                {
+                    return Ok(account);
+                }
+            }
+        }
+    }
+
     Err(LoginError::CredentialError)
 }
 
-pub async fn create_admin_account(conn: &DbConn, login_data: Login) -> QueryResult<()> {
+/// Finds the professor a directory entry's professor attribute refers to, if exactly one
+/// professor matches it.
+async fn get_directory_professor(
+    conn: &DbConn,
+    column: ProfessorColumn,
+    attribute: String,
+) -> QueryResult<Option<ID>> {
+    use schema::professors::dsl::*;
+
+    conn.run(move |c| {
+        let matches = match column {
+            ProfessorColumn::Id => match attribute.trim().parse::<ID>() {
+                Ok(professor_id) => professors.find(professor_id).select(id).load::<ID>(c)?,
+                Err(_) => Vec::new(),
+            },
+            ProfessorColumn::Email => professors
+                .filter(email.eq(attribute))
+                .select(id)
+                .load::<ID>(c)?,
+        };
+
+        Ok(match matches.as_slice() {
+            [professor_id] => Some(*professor_id),
+            _ => None,
+        })
+    })
+    .await
+}
+
+#[derive(Debug)]
+pub enum AccountCreationError {
+    UsernameTaken,
+    /// The account already has a login.
+    LoginExists,
+    DatabaseError(diesel::result::Error),
+    HashError(bcrypt::BcryptError),
+}
+
+impl std::fmt::Display for AccountCreationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AccountCreationError::UsernameTaken => write!(f, "username is already taken"),
+            AccountCreationError::LoginExists => write!(f, "account already has a login"),
+            AccountCreationError::DatabaseError(e) => e.fmt(f),
+            AccountCreationError::HashError(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for AccountCreationError {
+    fn from(e: diesel::result::Error) -> Self {
+        use diesel::result::{DatabaseErrorKind, Error};
+
+        match &e {
+            // Username collisions within a table are caught by its own unique constraint
+            // and collisions across tables by the primary key of account_usernames.
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info)
+                if info.constraint_name().is_some_and(|constraint| {
+                    constraint == "account_usernames_pkey" || constraint.ends_with("_username_key")
+                }) =>
+            {
+                AccountCreationError::UsernameTaken
+            }
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info)
+                if info.constraint_name().is_some_and(|constraint| {
+                    constraint == "applicant_logins_pkey" || constraint == "professor_logins_pkey"
+                }) =>
+            {
+                AccountCreationError::LoginExists
+            }
+            _ => AccountCreationError::DatabaseError(e),
+        }
+    }
+}
+
+impl From<bcrypt::BcryptError> for AccountCreationError {
+    fn from(e: bcrypt::BcryptError) -> Self {
+        AccountCreationError::HashError(e)
+    }
+}
+
+/// Checks whether a username is used by an applicant, professor or administrator account.
+pub async fn username_taken(conn: &DbConn, name: String) -> QueryResult<bool> {
+    use schema::account_usernames::dsl::*;
+
+    conn.run(move |c| {
+        diesel::select(diesel::dsl::exists(
+            account_usernames.filter(username.eq(name)),
+        ))
+        .get_result(c)
+    })
+    .await
+}
+
+pub async fn create_admin_account(
+    conn: &DbConn,
+    login_data: Login,
+    bcrypt_cost: u32,
+) -> Result<(), AccountCreationError> {
     use schema::admin_logins::dsl::admin_logins;
 
-    let bcrypt_hash = bcrypt::hash(login_data.password.as_str(), bcrypt::DEFAULT_COST).unwrap();
+    if username_taken(conn, login_data.username.clone()).await? {
+        return Err(AccountCreationError::UsernameTaken);
+    }
+
+    let bcrypt_hash = bcrypt::hash(login_data.password.as_str(), bcrypt_cost)?;
 
     conn.run(move |c| {
         diesel::insert_into(admin_logins)
@@ -653,10 +837,15 @@ pub async fn create_applicant_account(
     conn: &DbConn,
     applicant_id: i32,
     login_data: Login,
-) -> QueryResult<()> {
+    bcrypt_cost: u32,
+) -> Result<(), AccountCreationError> {
     use schema::applicant_logins::dsl::applicant_logins;
 
-    let bcrypt_hash = bcrypt::hash(login_data.password.as_str(), bcrypt::DEFAULT_COST).unwrap();
+    if username_taken(conn, login_data.username.clone()).await? {
+        return Err(AccountCreationError::UsernameTaken);
+    }
+
+    let bcrypt_hash = bcrypt::hash(login_data.password.as_str(), bcrypt_cost)?;
 
     conn.run(move |c| {
         diesel::insert_into(applicant_logins)
@@ -664,6 +853,7 @@ pub async fn create_applicant_account(
                 id: applicant_id,
                 username: login_data.username,
                 bcrypt_hash,
+                verified: true,
             })
             .execute(c)
     })
@@ -671,14 +861,88 @@ pub async fn create_applicant_account(
     Ok(())
 }
 
+/// Creates an applicant and their login together for an applicant registering themself.
+/// The account is unverified until the applicant confirms their email address.
+pub async fn register_applicant(
+    conn: &DbConn,
+    applicant: NewApplicant,
+    login_data: Login,
+    bcrypt_cost: u32,
+) -> Result<ID, AccountCreationError> {
+    use schema::{applicant_logins, applicants};
+
+    if username_taken(conn, login_data.username.clone()).await? {
+        return Err(AccountCreationError::UsernameTaken);
+    }
+
+    let bcrypt_hash = bcrypt::hash(login_data.password.as_str(), bcrypt_cost)?;
+
+    let applicant_id = conn
+        .run(move |c| {
+            c.transaction::<_, diesel::result::Error, _>(|| {
+                let applicant_id = diesel::insert_into(applicants::table)
+                    .values(&applicant)
+                    .returning(applicants::id)
+                    .get_result(c)?;
+
+                diesel::insert_into(applicant_logins::table)
+                    .values(NewApplicantLogin {
+                        id: applicant_id,
+                        username: login_data.username,
+                        bcrypt_hash,
+                        verified: false,
+                    })
+                    .execute(c)?;
+
+                Ok(applicant_id)
+            })
+        })
+        .await?;
+    Ok(applicant_id)
+}
+
+/// Marks an applicant's email address as verified.
+pub async fn verify_applicant(conn: &DbConn, applicant_id: ID) -> QueryResult<()> {
+    use schema::applicant_logins::dsl::*;
+
+    conn.run(move |c| {
+        diesel::update(applicant_logins.find(applicant_id))
+            .set(verified.eq(true))
+            .execute(c)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Checks whether an applicant registered themself and has not verified their email
+/// address yet.
+pub async fn applicant_unverified(conn: &DbConn, applicant_id: ID) -> QueryResult<bool> {
+    use schema::applicant_logins::dsl::*;
+
+    conn.run(move |c| {
+        diesel::select(diesel::dsl::exists(
+            applicant_logins
+                .find(applicant_id)
+                .filter(verified.eq(false)),
+        ))
+        .get_result(c)
+    })
+    .await
+}
+
 pub async fn create_professor_account(
     conn: &DbConn,
     professor_id: i32,
     login_data: Login,
-) -> QueryResult<()> {
+    bcrypt_cost: u32,
+) -> Result<(), AccountCreationError> {
     use schema::professor_logins::dsl::professor_logins;
 
-    let bcrypt_hash = bcrypt::hash(login_data.password.as_str(), bcrypt::DEFAULT_COST).unwrap();
+    if username_taken(conn, login_data.username.clone()).await? {
+        return Err(AccountCreationError::UsernameTaken);
+    }
+
+    let bcrypt_hash = bcrypt::hash(login_data.password.as_str(), bcrypt_cost)?;
 
     conn.run(move |c| {
         diesel::insert_into(professor_logins)
@@ -693,6 +957,147 @@ pub async fn create_professor_account(
     Ok(())
 }
 
+/// Finds the professor that signs in with an identity provider subject.
+pub async fn get_professor_by_oidc_subject(
+    conn: &DbConn,
+    subject: String,
+) -> QueryResult<Option<ID>> {
+    use schema::professor_logins::dsl::*;
+
+    conn.run(move |c| {
+        professor_logins
+            .filter(oidc_subject.eq(subject))
+            .select(id)
+            .first(c)
+            .optional()
+    })
+    .await
+}
+
+/// Links an identity provider subject to the professor login with the given email
+/// address. Nothing is linked unless exactly one login without a subject matches.
+pub async fn link_professor_oidc_subject(
+    conn: &DbConn,
+    professor_email: String,
+    subject: String,
+) -> QueryResult<Option<ID>> {
+    use schema::professor_logins::dsl::*;
+    use schema::professors;
+
+    conn.run(move |c| {
+        c.transaction(|| {
+            let matches = professor_logins
+                .inner_join(professors::table)
+                .filter(professors::email.eq(professor_email))
+                .filter(oidc_subject.is_null())
+                .select(id)
+                .load::<ID>(c)?;
+
+            match matches.as_slice() {
+                [professor_id] => {
+                    diesel::update(professor_logins.find(professor_id))
+                        .set(oidc_subject.eq(subject))
+                        .execute(c)?;
+                    Ok(Some(*professor_id))
+                }
+                _ => Ok(None),
+            }
+        })
+    })
+    .await
+}
+
+/// Creates a professor and their login for an identity provider subject.
+pub async fn provision_oidc_professor(
+    conn: &DbConn,
+    professor: NewProfessor,
+    login_data: Login,
+    subject: String,
+    bcrypt_cost: u32,
+) -> Result<ID, AccountCreationError> {
+    use schema::{professor_logins, professors};
+
+    if username_taken(conn, login_data.username.clone()).await? {
+        return Err(AccountCreationError::UsernameTaken);
+    }
+
+    let bcrypt_hash = bcrypt::hash(login_data.password.as_str(), bcrypt_cost)?;
+
+    let professor_id = conn
+        .run(move |c| {
+            c.transaction::<_, diesel::result::Error, _>(|| {
+                let professor_id = diesel::insert_into(professors::table)
+                    .values(&professor)
+                    .returning(professors::id)
+                    .get_result(c)?;
+
+                diesel::insert_into(professor_logins::table)
+                    .values(NewProfessorLogin {
+                        id: professor_id,
+                        username: login_data.username,
+                        bcrypt_hash,
+                    })
+                    .execute(c)?;
+
+                diesel::update(professor_logins::table.find(professor_id))
+                    .set(professor_logins::oidc_subject.eq(subject))
+                    .execute(c)?;
+
+                Ok(professor_id)
+            })
+        })
+        .await?;
+    Ok(professor_id)
+}
+
+/// Saves a single sign-on login that was sent to the identity provider, after dropping
+/// logins started before `started_after`. Returns false without saving it if there are
+/// already `max_logins` pending.
+pub async fn add_oidc_login(
+    conn: &DbConn,
+    login: OidcLogin,
+    started_after: DateTime<Utc>,
+    max_logins: i64,
+) -> QueryResult<bool> {
+    use schema::oidc_logins::dsl::*;
+
+    conn.run(move |c| {
+        c.transaction(|| {
+            diesel::delete(oidc_logins.filter(started_at.lt(started_after))).execute(c)?;
+
+            let pending: i64 = oidc_logins.count().get_result(c)?;
+            if pending >= max_logins {
+                return Ok(false);
+            }
+
+            diesel::insert_into(oidc_logins).values(&login).execute(c)?;
+            Ok(true)
+        })
+    })
+    .await
+}
+
+/// Removes and returns the pending single sign-on login with a state, if it was started
+/// after `started_after`.
+pub async fn take_oidc_login(
+    conn: &DbConn,
+    login_state: String,
+    started_after: DateTime<Utc>,
+) -> QueryResult<Option<OidcLogin>> {
+    use schema::oidc_logins::dsl::*;
+
+    conn.run(move |c| {
+        diesel::delete(
+            oidc_logins
+                .filter(state.eq(login_state))
+                .filter(started_at.ge(started_after)),
+        )
+        .get_result(c)
+        .optional()
+    })
+    .await
+}
+
 pub async fn admin_exists(conn: &DbConn) -> QueryResult<bool> {
     use schema::admin_logins::dsl::admin_logins;
 
@@ -705,3 +1110,1129 @@ pub async fn admin_exists(conn: &DbConn) -> QueryResult<bool> {
         Err(_) => Err(diesel::result::Error::NotFound),
     }
 }
+
+/// Gets every administrator, in the order they were created.
+pub async fn get_admins(conn: &DbConn) -> QueryResult<Vec<Administrator>> {
+    use schema::admin_logins::dsl::*;
+
+    conn.run(|c| {
+        admin_logins
+            .select((id, username, active))
+            .order(id)
+            .load::<Administrator>(c)
+    })
+    .await
+}
+
+/// Checks whether an administrator exists and has not been disabled.
+pub async fn admin_active(conn: &DbConn, admin_id: ID) -> QueryResult<bool> {
+    use schema::admin_logins::dsl::*;
+
+    conn.run(move |c| {
+        diesel::select(diesel::dsl::exists(
+            admin_logins.find(admin_id).filter(active.eq(true)),
+        ))
+        .get_result(c)
+    })
+    .await
+}
+
+/// Checks whether an account may be used. Applicants and professors are active unless
+/// their login has been suspended, so directory professors without a login always are,
+/// while administrators also have to still exist.
+pub async fn account_active(conn: &DbConn, account: SessionType) -> QueryResult<bool> {
+    use schema::{applicant_logins, professor_logins};
+
+    match account {
+        SessionType::Applicant(applicant_id) => {
+            conn.run(move |c| {
+                diesel::select(diesel::dsl::not(diesel::dsl::exists(
+                    applicant_logins::table
+                        .find(applicant_id)
+                        .filter(applicant_logins::active.eq(false)),
+                )))
+                .get_result(c)
+            })
+            .await
+        }
+        SessionType::Professor(professor_id) => {
+            conn.run(move |c| {
+                diesel::select(diesel::dsl::not(diesel::dsl::exists(
+                    professor_logins::table
+                        .find(professor_id)
+                        .filter(professor_logins::active.eq(false)),
+                )))
+                .get_result(c)
+            })
+            .await
+        }
+        SessionType::Administrator(admin_id) => admin_active(conn, admin_id).await,
+    }
+}
+
+/// Suspends or reinstates the login of an applicant or professor, returning whether the
+/// account has a login. Administrators are changed with `set_admin_active` instead.
+pub async fn set_login_active(
+    conn: &DbConn,
+    account: SessionType,
+    login_active: bool,
+) -> QueryResult<bool> {
+    use schema::{applicant_logins, professor_logins};
+
+    let updated = conn
+        .run(move |c| match account {
+            SessionType::Applicant(applicant_id) => {
+                diesel::update(applicant_logins::table.find(applicant_id))
+                    .set(applicant_logins::active.eq(login_active))
+                    .execute(c)
+            }
+            SessionType::Professor(professor_id) => {
+                diesel::update(professor_logins::table.find(professor_id))
+                    .set(professor_logins::active.eq(login_active))
+                    .execute(c)
+            }
+            SessionType::Administrator(_) => Ok(0),
+        })
+        .await?;
+    Ok(updated > 0)
+}
+
+/// A login found in the user directory, with the applicant or professor it belongs to.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DirectoryEntry {
+    /// The kind of account and the id of its `applicants`, `professors` or `admin_logins`
+    /// row.
+    pub account: SessionType,
+    pub username: String,
+    /// The applicant's or professor's name, administrators have none.
+    pub name: Option<String>,
+    pub active: bool,
+}
+
+/// Escapes the wildcards of a `LIKE` pattern so that text is matched literally.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Searches every kind of login for a username or applicant or professor name containing
+/// the query, ignoring case. Returns at most `limit` entries, ordered by username.
+pub async fn search_accounts(
+    conn: &DbConn,
+    query: String,
+    limit: i64,
+) -> QueryResult<Vec<DirectoryEntry>> {
+    use schema::{admin_logins, applicant_logins, applicants, professor_logins, professors};
+
+    let pattern = format!("%{}%", escape_like(&query));
+
+    conn.run(move |c| {
+        let found_applicants = applicant_logins::table
+            .inner_join(applicants::table)
+            .filter(
+                applicant_logins::username
+                    .ilike(&pattern)
+                    .or(applicants::name.ilike(&pattern)),
+            )
+            .select((
+                applicant_logins::id,
+                applicant_logins::username,
+                applicants::name,
+                applicant_logins::active,
+            ))
+            .order(applicant_logins::username)
+            .limit(limit)
+            .load::<(i32, String, String, bool)>(c)?;
+
+        let found_professors = professor_logins::table
+            .inner_join(professors::table)
+            .filter(
+                professor_logins::username
+                    .ilike(&pattern)
+                    .or(professors::name.ilike(&pattern)),
+            )
+            .select((
+                professor_logins::id,
+                professor_logins::username,
+                professors::name,
+                professor_logins::active,
+            ))
+            .order(professor_logins::username)
+            .limit(limit)
+            .load::<(i32, String, String, bool)>(c)?;
+
+        let found_admins = admin_logins::table
+            .filter(admin_logins::username.ilike(&pattern))
+            .select((
+                admin_logins::id,
+                admin_logins::username,
+                admin_logins::active,
+            ))
+            .order(admin_logins::username)
+            .limit(limit)
+            .load::<(i32, String, bool)>(c)?;
+
+        let mut entries: Vec<DirectoryEntry> = found_applicants
+            .into_iter()
+            .map(|(id, username, name, active)| DirectoryEntry {
+                account: SessionType::Applicant(id),
+                username,
+                name: Some(name),
+                active,
+            })
+            .chain(
+                found_professors
+                    .into_iter()
+                    .map(|(id, username, name, active)| DirectoryEntry {
+                        account: SessionType::Professor(id),
+                        username,
+                        name: Some(name),
+                        active,
+                    }),
+            )
+            .chain(
+                found_admins
+                    .into_iter()
+                    .map(|(id, username, active)| DirectoryEntry {
+                        account: SessionType::Administrator(id),
+                        username,
+                        name: None,
+                        active,
+                    }),
+            )
+            .collect();
+        entries.sort_by(|a, b| a.username.cmp(&b.username));
+        entries.truncate(limit.max(0) as usize);
+        Ok(entries)
+    })
+    .await
+}
+
+/// The outcome of disabling, enabling or deleting an administrator.
+#[derive(Debug, PartialEq)]
+pub enum AdminChange {
+    Done,
+    NotFound,
+    /// The administrator is the only active one, and would leave no one to manage
+    /// accounts.
+    LastActiveAdmin,
+}
+
+/// Locks the active administrators until the end of the transaction, and checks whether
+/// an administrator is the only one of them.
+fn is_last_active_admin(c: &diesel::PgConnection, admin_id: ID) -> QueryResult<bool> {
+    use schema::admin_logins::dsl::*;
+
+    let active_admins = admin_logins
+        .filter(active.eq(true))
+        .select(id)
+        .for_update()
+        .load::<i32>(c)?;
+    Ok(active_admins == [admin_id])
+}
+
+/// Disables or enables an administrator, refusing to disable the last active one.
+pub async fn set_admin_active(
+    conn: &DbConn,
+    admin_id: ID,
+    admin_active: bool,
+) -> QueryResult<AdminChange> {
+    use schema::admin_logins::dsl::*;
+
+    conn.run(move |c| {
+        c.transaction(|| {
+            if !admin_active && is_last_active_admin(c, admin_id)? {
+                return Ok(AdminChange::LastActiveAdmin);
+            }
+
+            let updated = diesel::update(admin_logins.find(admin_id))
+                .set(active.eq(admin_active))
+                .execute(c)?;
+            Ok(match updated {
+                0 => AdminChange::NotFound,
+                _ => AdminChange::Done,
+            })
+        })
+    })
+    .await
+}
+
+/// Deletes an administrator along with their two-factor enrollment and unused tokens,
+/// refusing to delete the last active one.
+pub async fn delete_admin(conn: &DbConn, admin_id: ID) -> QueryResult<AdminChange> {
+    use schema::{admin_logins, one_time_tokens, totp_enrollments, totp_recovery_codes};
+
+    let (kind, subject) = SessionType::Administrator(admin_id).to_db();
+
+    conn.run(move |c| {
+        c.transaction(|| {
+            if is_last_active_admin(c, admin_id)? {
+                return Ok(AdminChange::LastActiveAdmin);
+            }
+
+            let deleted = diesel::delete(admin_logins::table.find(admin_id)).execute(c)?;
+            if deleted == 0 {
+                return Ok(AdminChange::NotFound);
+            }
+
+            diesel::delete(totp_enrollments::table.find((kind, admin_id))).execute(c)?;
+            diesel::delete(
+                totp_recovery_codes::table
+                    .filter(totp_recovery_codes::session_type.eq(kind))
+                    .filter(totp_recovery_codes::subject_id.eq(admin_id)),
+            )
+            .execute(c)?;
+            diesel::delete(
+                one_time_tokens::table
+                    .filter(one_time_tokens::session_type.eq(kind))
+                    .filter(one_time_tokens::subject_id.eq(subject)),
+            )
+            .execute(c)?;
+
+            Ok(AdminChange::Done)
+        })
+    })
+    .await
+}
+
+pub const TOKEN_PASSWORD_RESET: &str = "PASSWORD_RESET";
+pub const TOKEN_TOTP_LOGIN: &str = "TOTP_LOGIN";
+pub const TOKEN_EMAIL_VERIFICATION: &str = "EMAIL_VERIFICATION";
+pub const TOKEN_LOGIN_LINK: &str = "LOGIN_LINK";
+
+/// Stores the hash of a single-use token issued to an account for the given purpose.
+pub async fn create_one_time_token(
+    conn: &DbConn,
+    token_hash: String,
+    purpose: &'static str,
+    account: SessionType,
+    expires_at: DateTime<Utc>,
+) -> QueryResult<()> {
+    use schema::one_time_tokens;
+
+    let (kind, subject_id) = account.to_db();
+    let new_token = NewOneTimeToken {
+        token_hash,
+        purpose: purpose.to_string(),
+        session_type: kind.to_string(),
+        subject_id,
+        expires_at,
+    };
+
+    conn.run(move |c| {
+        diesel::insert_into(one_time_tokens::table)
+            .values(&new_token)
+            .execute(c)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Finds the account an unused and unexpired single-use token was issued to, without
+/// using it up.
+pub async fn find_one_time_token(
+    conn: &DbConn,
+    hash: String,
+    token_purpose: &'static str,
+) -> QueryResult<Option<SessionType>> {
+    use schema::one_time_tokens::dsl::*;
+
+    let account = conn
+        .run(move |c| {
+            one_time_tokens
+                .find(hash)
+                .filter(purpose.eq(token_purpose))
+                .filter(used_at.is_null())
+                .filter(expires_at.gt(Utc::now()))
+                .select((session_type, subject_id))
+                .first::<(String, i32)>(c)
+                .optional()
+        })
+        .await?;
+
+    Ok(account.and_then(|(kind, subject)| SessionType::from_db(&kind, subject)))
+}
+
+/// Marks an unused and unexpired single-use token as used, returning the account it
+/// was issued to. Returns `None` if the token cannot be used.
+pub async fn use_one_time_token(
+    conn: &DbConn,
+    hash: String,
+    token_purpose: &'static str,
+) -> QueryResult<Option<SessionType>> {
+    use schema::one_time_tokens::dsl::*;
+
+    let account = conn
+        .run(move |c| {
+            let now = Utc::now();
+
+            diesel::update(
+                one_time_tokens
+                    .find(hash)
+                    .filter(purpose.eq(token_purpose))
+                    .filter(used_at.is_null())
+                    .filter(expires_at.gt(now)),
+            )
+            .set(used_at.eq(now))
+            .returning((session_type, subject_id))
+            .get_result::<(String, i32)>(c)
+            .optional()
+        })
+        .await?;
+
+    Ok(account.and_then(|(kind, subject)| SessionType::from_db(&kind, subject)))
+}
+
+/// Finds the applicant with an active login whose email address matches, ignoring case,
+/// returning their id and name. Nothing is found unless exactly one applicant matches.
+pub async fn get_applicant_login_by_email(
+    conn: &DbConn,
+    applicant_email: String,
+) -> QueryResult<Option<(ID, String)>> {
+    use schema::{applicant_logins, applicants};
+
+    let pattern = escape_like(applicant_email.trim());
+
+    conn.run(move |c| {
+        let matches = applicants::table
+            .inner_join(applicant_logins::table)
+            .filter(applicants::email.ilike(pattern))
+            .filter(applicant_logins::active.eq(true))
+            .select((applicants::id, applicants::name))
+            .load::<(ID, String)>(c)?;
+
+        let mut matches = matches.into_iter();
+        Ok(match (matches.next(), matches.next()) {
+            (Some(applicant), None) => Some(applicant),
+            _ => None,
+        })
+    })
+    .await
+}
+
+/// Finds the applicant or professor account with the given username.
+pub async fn get_account_by_username(
+    conn: &DbConn,
+    username: String,
+) -> QueryResult<Option<SessionType>> {
+    use schema::applicant_logins::dsl::{
+        applicant_logins, id as applicant_id, username as applicant_username,
+    };
+    use schema::professor_logins::dsl::{
+        id as professor_id, professor_logins, username as professor_username,
+    };
+
+    conn.run(move |c| {
+        let applicant = applicant_logins
+            .filter(applicant_username.eq(&username))
+            .select(applicant_id)
+            .first::<ID>(c)
+            .optional()?;
+        if let Some(id) = applicant {
+            return Ok(Some(SessionType::Applicant(id)));
+        }
+
+        let professor = professor_logins
+            .filter(professor_username.eq(&username))
+            .select(professor_id)
+            .first::<ID>(c)
+            .optional()?;
+        Ok(professor.map(SessionType::Professor))
+    })
+    .await
+}
+
+/// Gets the name and email address on file for an applicant or professor account.
+pub async fn get_account_contact(
+    conn: &DbConn,
+    account: SessionType,
+) -> QueryResult<Option<(String, String)>> {
+    match account {
+        SessionType::Applicant(applicant_id) => Ok(get_applicant(conn, applicant_id)
+            .await?
+            .map(|applicant| (applicant.name, applicant.email))),
+        SessionType::Professor(professor_id) => Ok(get_professor(conn, professor_id)
+            .await?
+            .and_then(|professor| professor.email.map(|email| (professor.name, email)))),
+        SessionType::Administrator(_) => Ok(None),
+    }
+}
+
+/// Replaces the password of an account, returning whether the account has a login.
+pub async fn set_account_password(
+    conn: &DbConn,
+    account: SessionType,
+    password: String,
+    bcrypt_cost: u32,
+) -> anyhow::Result<bool> {
+    use schema::admin_logins::dsl::{admin_logins, bcrypt_hash as admin_hash};
+    use schema::applicant_logins::dsl::{applicant_logins, bcrypt_hash as applicant_hash};
+    use schema::professor_logins::dsl::{bcrypt_hash as professor_hash, professor_logins};
+
+    let bcrypt_hash = bcrypt::hash(password.as_str(), bcrypt_cost)?;
+
+    let updated = match account {
+        SessionType::Applicant(applicant_id) => {
+            conn.run(move |c| {
+                diesel::update(applicant_logins.find(applicant_id))
+                    .set(applicant_hash.eq(bcrypt_hash))
+                    .execute(c)
+            })
+            .await?
+        }
+        SessionType::Professor(professor_id) => {
+            conn.run(move |c| {
+                diesel::update(professor_logins.find(professor_id))
+                    .set(professor_hash.eq(bcrypt_hash))
+                    .execute(c)
+            })
+            .await?
+        }
+        SessionType::Administrator(admin_id) => {
+            conn.run(move |c| {
+                diesel::update(admin_logins.find(admin_id))
+                    .set(admin_hash.eq(bcrypt_hash))
+                    .execute(c)
+            })
+            .await?
+        }
+    };
+
+    Ok(updated > 0)
+}
+
+/// Checks a password against the stored hash of an account.
+pub async fn verify_account_password(
+    conn: &DbConn,
+    account: SessionType,
+    password: String,
+) -> anyhow::Result<bool> {
+    use schema::admin_logins::dsl::{admin_logins, bcrypt_hash as admin_hash};
+    use schema::applicant_logins::dsl::{applicant_logins, bcrypt_hash as applicant_hash};
+    use schema::professor_logins::dsl::{bcrypt_hash as professor_hash, professor_logins};
+
+    let password_hash = match account {
+        SessionType::Applicant(applicant_id) => {
+            conn.run(move |c| {
+                applicant_logins
+                    .find(applicant_id)
+                    .select(applicant_hash)
+                    .first::<String>(c)
+                    .optional()
+            })
+            .await?
+        }
+        SessionType::Professor(professor_id) => {
+            conn.run(move |c| {
+                professor_logins
+                    .find(professor_id)
+                    .select(professor_hash)
+                    .first::<String>(c)
+                    .optional()
+            })
+            .await?
+        }
+        SessionType::Administrator(admin_id) => {
+            conn.run(move |c| {
+                admin_logins
+                    .find(admin_id)
+                    .select(admin_hash)
+                    .first::<String>(c)
+                    .optional()
+            })
+            .await?
+        }
+    };
+
+    match password_hash {
+        Some(password_hash) => Ok(bcrypt::verify(password, password_hash.as_str())?),
+        None => Err(anyhow!("Account {:?} does not have a login", account)),
+    }
+}
+
+/// Gets the failed login record for a username or client IP.
+pub async fn get_login_lockout(
+    conn: &DbConn,
+    lockout_kind: &'static str,
+    lockout_value: String,
+) -> QueryResult<Option<LoginLockout>> {
+    use schema::login_lockouts::dsl::*;
+
+    conn.run(move |c| {
+        login_lockouts
+            .find((lockout_kind, lockout_value))
+            .first(c)
+            .optional()
+    })
+    .await
+}
+
+/// Updates the failed login record for a username or client IP, which starts with no
+/// failures if there is none yet. `update` is given the current record and returns the new
+/// failure count, last failure and lockout. The record is locked while it is updated, so
+/// concurrent failures are all counted.
+pub async fn update_login_lockout<F>(
+    conn: &DbConn,
+    lockout_kind: &'static str,
+    lockout_value: String,
+    update: F,
+) -> QueryResult<()>
+where
+    F: FnOnce(&LoginLockout) -> (i32, DateTime<Utc>, Option<DateTime<Utc>>) + Send + 'static,
+{
+    use schema::login_lockouts::dsl::*;
+
+    conn.run(move |c| {
+        c.transaction(|| {
+            // A concurrent first failure waits here for the other's row instead of
+            // overwriting it.
+            diesel::insert_into(login_lockouts)
+                .values((
+                    kind.eq(lockout_kind),
+                    value.eq(&lockout_value),
+                    failures.eq(0),
+                    last_failure_at.eq(Utc::now()),
+                ))
+                .on_conflict_do_nothing()
+                .execute(c)?;
+
+            let previous = login_lockouts
+                .find((lockout_kind, &lockout_value))
+                .for_update()
+                .first::<LoginLockout>(c)?;
+            let (new_failures, new_last_failure_at, new_locked_until) = update(&previous);
+
+            diesel::update(login_lockouts.find((lockout_kind, &lockout_value)))
+                .set((
+                    failures.eq(new_failures),
+                    last_failure_at.eq(new_last_failure_at),
+                    locked_until.eq(new_locked_until),
+                ))
+                .execute(c)
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// Gets every failed login record.
+pub async fn get_login_lockouts(conn: &DbConn) -> QueryResult<Vec<LoginLockout>> {
+    use schema::login_lockouts::dsl::*;
+
+    conn.run(|c| login_lockouts.order(last_failure_at.desc()).load(c))
+        .await
+}
+
+/// Deletes the failed login record for a username or client IP.
+pub async fn delete_login_lockout(
+    conn: &DbConn,
+    lockout_kind: String,
+    lockout_value: String,
+) -> QueryResult<()> {
+    use schema::login_lockouts::dsl::*;
+
+    conn.run(move |c| {
+        diesel::delete(login_lockouts.find((lockout_kind, lockout_value))).execute(c)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Gets the revocation generation of an account's stateless tokens.
+pub async fn get_session_generation(conn: &DbConn, account: SessionType) -> QueryResult<i32> {
+    use schema::session_generations::dsl::*;
+
+    let (kind, _) = account.to_db();
+    let subject = account.subject_id();
+
+    let current = conn
+        .run(move |c| {
+            session_generations
+                .find((kind, subject))
+                .select(generation)
+                .first::<i32>(c)
+                .optional()
+        })
+        .await?;
+    Ok(current.unwrap_or(0))
+}
+
+/// Increments the revocation generation of an account's stateless tokens, returning the
+/// new generation.
+pub async fn bump_session_generation(conn: &DbConn, account: SessionType) -> QueryResult<i32> {
+    use schema::session_generations::dsl::*;
+
+    let (kind, _) = account.to_db();
+    let subject = account.subject_id();
+
+    conn.run(move |c| {
+        diesel::insert_into(session_generations)
+            .values((
+                session_type.eq(kind),
+                subject_id.eq(subject),
+                generation.eq(1),
+            ))
+            .on_conflict((session_type, subject_id))
+            .do_update()
+            .set(generation.eq(generation + 1))
+            .returning(generation)
+            .get_result(c)
+    })
+    .await
+}
+
+/// Gets the username an account logs in with.
+pub async fn get_account_username(
+    conn: &DbConn,
+    account: SessionType,
+) -> QueryResult<Option<String>> {
+    use schema::admin_logins::dsl::{admin_logins, username as admin_username};
+    use schema::applicant_logins::dsl::{applicant_logins, username as applicant_username};
+    use schema::professor_logins::dsl::{professor_logins, username as professor_username};
+
+    conn.run(move |c| match account {
+        SessionType::Applicant(id) => applicant_logins
+            .find(id)
+            .select(applicant_username)
+            .first(c)
+            .optional(),
+        SessionType::Professor(id) => professor_logins
+            .find(id)
+            .select(professor_username)
+            .first(c)
+            .optional(),
+        SessionType::Administrator(id) => admin_logins
+            .find(id)
+            .select(admin_username)
+            .first(c)
+            .optional(),
+    })
+    .await
+}
+
+/// Gets the TOTP enrollment of an account, whether or not it has been confirmed.
+pub async fn get_totp_enrollment(
+    conn: &DbConn,
+    account: SessionType,
+) -> QueryResult<Option<TotpEnrollment>> {
+    use schema::totp_enrollments::dsl::*;
+
+    let (kind, _) = account.to_db();
+    let subject = account.subject_id();
+
+    conn.run(move |c| totp_enrollments.find((kind, subject)).first(c).optional())
+        .await
+}
+
+/// Replaces the TOTP enrollment of an account with a new unconfirmed one, along with the
+/// hashes of its recovery codes.
+pub async fn replace_totp_enrollment(
+    conn: &DbConn,
+    account: SessionType,
+    secret: String,
+    recovery_code_hashes: Vec<String>,
+) -> QueryResult<()> {
+    use schema::{totp_enrollments, totp_recovery_codes};
+
+    let (kind, _) = account.to_db();
+    let subject = account.subject_id();
+
+    conn.run(move |c| {
+        c.transaction(|| {
+            diesel::delete(totp_enrollments::table.find((kind, subject))).execute(c)?;
+
+            diesel::insert_into(totp_enrollments::table)
+                .values(NewTotpEnrollment {
+                    session_type: kind.to_string(),
+                    subject_id: subject,
+                    secret,
+                })
+                .execute(c)?;
+
+            let recovery_codes: Vec<NewTotpRecoveryCode> = recovery_code_hashes
+                .into_iter()
+                .map(|code_hash| NewTotpRecoveryCode {
+                    code_hash,
+                    session_type: kind.to_string(),
+                    subject_id: subject,
+                })
+                .collect();
+            diesel::insert_into(totp_recovery_codes::table)
+                .values(&recovery_codes)
+                .execute(c)?;
+
+            Ok(())
+        })
+    })
+    .await
+}
+
+/// Confirms an account's pending TOTP enrollment with the time step of a valid code.
+/// Returns `false` if there was no pending enrollment.
+pub async fn confirm_totp_enrollment(
+    conn: &DbConn,
+    account: SessionType,
+    step: i64,
+) -> QueryResult<bool> {
+    use schema::totp_enrollments::dsl::*;
+
+    let (kind, _) = account.to_db();
+    let subject = account.subject_id();
+
+    let updated = conn
+        .run(move |c| {
+            diesel::update(
+                totp_enrollments
+                    .find((kind, subject))
+                    .filter(confirmed_at.is_null()),
+            )
+            .set((confirmed_at.eq(Utc::now()), last_used_step.eq(Some(step))))
+            .execute(c)
+        })
+        .await?;
+    Ok(updated > 0)
+}
+
+/// Records the time step of a code used to log in, so that it cannot be replayed.
+/// Returns `false` if a code from the same or a later step was already used.
+pub async fn use_totp_step(conn: &DbConn, account: SessionType, step: i64) -> QueryResult<bool> {
+    use schema::totp_enrollments::dsl::*;
+
+    let (kind, _) = account.to_db();
+    let subject = account.subject_id();
+
+    let updated = conn
+        .run(move |c| {
+            diesel::update(
+                totp_enrollments
+                    .find((kind, subject))
+                    .filter(last_used_step.is_null().or(last_used_step.lt(step))),
+            )
+            .set(last_used_step.eq(Some(step)))
+            .execute(c)
+        })
+        .await?;
+    Ok(updated > 0)
+}
+
+/// Marks an unused recovery code of an account as used, returning whether it could be.
+pub async fn use_totp_recovery_code(
+    conn: &DbConn,
+    account: SessionType,
+    hash: String,
+) -> QueryResult<bool> {
+    use schema::totp_recovery_codes::dsl::*;
+
+    let (kind, _) = account.to_db();
+    let subject = account.subject_id();
+
+    let updated = conn
+        .run(move |c| {
+            diesel::update(
+                totp_recovery_codes
+                    .find(hash)
+                    .filter(session_type.eq(kind))
+                    .filter(subject_id.eq(subject))
+                    .filter(used_at.is_null()),
+            )
+            .set(used_at.eq(Some(Utc::now())))
+            .execute(c)
+        })
+        .await?;
+    Ok(updated > 0)
+}
+
+/// Removes the TOTP enrollment and recovery codes of an account.
+pub async fn delete_totp_enrollment(conn: &DbConn, account: SessionType) -> QueryResult<()> {
+    use schema::totp_enrollments::dsl::*;
+
+    let (kind, _) = account.to_db();
+    let subject = account.subject_id();
+
+    conn.run(move |c| diesel::delete(totp_enrollments.find((kind, subject))).execute(c))
+        .await?;
+    Ok(())
+}
+
+/// Creates an API key, returning its id.
+pub async fn create_api_key(conn: &DbConn, key: NewApiKey) -> QueryResult<i32> {
+    use schema::api_keys::dsl::*;
+
+    conn.run(move |c| {
+        diesel::insert_into(api_keys)
+            .values(key)
+            .returning(id)
+            .get_result(c)
+    })
+    .await
+}
+
+/// Gets every API key, including revoked and expired ones.
+pub async fn get_api_keys(conn: &DbConn) -> QueryResult<Vec<ApiKey>> {
+    use schema::api_keys::dsl::*;
+
+    conn.run(|c| {
+        api_keys
+            .select((
+                id,
+                name,
+                scopes,
+                created_by,
+                created_at,
+                expires_at,
+                last_used_at,
+                revoked_at,
+            ))
+            .order(id)
+            .load::<ApiKey>(c)
+    })
+    .await
+}
+
+/// Revokes an API key, returning whether there was an unrevoked key to revoke.
+pub async fn revoke_api_key(conn: &DbConn, key_id: i32) -> QueryResult<bool> {
+    use schema::api_keys::dsl::*;
+
+    let updated = conn
+        .run(move |c| {
+            diesel::update(api_keys.find(key_id).filter(revoked_at.is_null()))
+                .set(revoked_at.eq(Utc::now()))
+                .execute(c)
+        })
+        .await?;
+    Ok(updated > 0)
+}
+
+/// Records the use of an unrevoked and unexpired API key, returning its id and scopes.
+pub async fn use_api_key(conn: &DbConn, hash: String) -> QueryResult<Option<(i32, Vec<String>)>> {
+    use schema::api_keys::dsl::*;
+
+    conn.run(move |c| {
+        let now = Utc::now();
+        diesel::update(
+            api_keys
+                .filter(key_hash.eq(hash))
+                .filter(revoked_at.is_null())
+                .filter(expires_at.gt(now)),
+        )
+        .set(last_used_at.eq(now))
+        .returning((id, scopes))
+        .get_result(c)
+        .optional()
+    })
+    .await
+}
+
+/// Cancels the pending invites of an account.
+fn cancel_pending_invites(c: &diesel::PgConnection, kind: &str, subject: ID) -> QueryResult<usize> {
+    use schema::invites::dsl::*;
+
+    diesel::update(
+        invites
+            .filter(session_type.eq(kind))
+            .filter(subject_id.eq(subject))
+            .filter(accepted_at.is_null())
+            .filter(cancelled_at.is_null()),
+    )
+    .set(cancelled_at.eq(Utc::now()))
+    .execute(c)
+}
+
+/// Creates an invite, cancelling any others still pending for the account so that only
+/// the newest one works. Returns its id.
+pub async fn create_invite(conn: &DbConn, invite: NewInvite) -> QueryResult<ID> {
+    use schema::invites::dsl::*;
+
+    conn.run(move |c| {
+        c.transaction(|| {
+            cancel_pending_invites(c, &invite.session_type, invite.subject_id)?;
+
+            diesel::insert_into(invites)
+                .values(invite)
+                .returning(id)
+                .get_result(c)
+        })
+    })
+    .await
+}
+
+/// Gets every invite, newest first.
+pub async fn get_invites(conn: &DbConn) -> QueryResult<Vec<Invite>> {
+    use schema::invites::dsl::*;
+
+    conn.run(|c| {
+        invites
+            .select((
+                id,
+                session_type,
+                subject_id,
+                created_by,
+                created_at,
+                expires_at,
+                accepted_at,
+                cancelled_at,
+            ))
+            .order(id.desc())
+            .load::<Invite>(c)
+    })
+    .await
+}
+
+/// Replaces the token of an invite that has not been accepted or cancelled, so that only
+/// the newest link works. Returns the invited account if there was such an invite.
+pub async fn renew_invite(
+    conn: &DbConn,
+    invite_id: ID,
+    hash: String,
+    expiry: DateTime<Utc>,
+) -> QueryResult<Option<SessionType>> {
+    use schema::invites::dsl::*;
+
+    let account = conn
+        .run(move |c| {
+            diesel::update(
+                invites
+                    .find(invite_id)
+                    .filter(accepted_at.is_null())
+                    .filter(cancelled_at.is_null()),
+            )
+            .set((token_hash.eq(hash), expires_at.eq(expiry)))
+            .returning((session_type, subject_id))
+            .get_result::<(String, i32)>(c)
+            .optional()
+        })
+        .await?;
+
+    Ok(account.and_then(|(kind, subject)| SessionType::from_db(&kind, subject)))
+}
+
+/// Cancels an invite that has not been accepted, returning whether there was one.
+pub async fn cancel_invite(conn: &DbConn, invite_id: ID) -> QueryResult<bool> {
+    use schema::invites::dsl::*;
+
+    let updated = conn
+        .run(move |c| {
+            diesel::update(
+                invites
+                    .find(invite_id)
+                    .filter(accepted_at.is_null())
+                    .filter(cancelled_at.is_null()),
+            )
+            .set(cancelled_at.eq(Utc::now()))
+            .execute(c)
+        })
+        .await?;
+    Ok(updated > 0)
+}
+
+/// Accepts a pending invite and creates the invited account's login in one transaction,
+/// so that a taken username leaves the invite usable. Any other invites pending for the
+/// account are cancelled. Returns the account, or `None` if the token is not a pending
+/// invite.
+pub async fn accept_invite(
+    conn: &DbConn,
+    hash: String,
+    login_data: Login,
+    bcrypt_cost: u32,
+) -> Result<Option<SessionType>, AccountCreationError> {
+    use schema::invites::dsl::*;
+    use schema::{applicant_logins, professor_logins};
+
+    if username_taken(conn, login_data.username.clone()).await? {
+        return Err(AccountCreationError::UsernameTaken);
+    }
+
+    let bcrypt_hash = bcrypt::hash(login_data.password.as_str(), bcrypt_cost)?;
+
+    conn.run(move |c| {
+        c.transaction::<_, AccountCreationError, _>(|| {
+            let now = Utc::now();
+            let account = diesel::update(
+                invites
+                    .filter(token_hash.eq(hash))
+                    .filter(accepted_at.is_null())
+                    .filter(cancelled_at.is_null())
+                    .filter(expires_at.gt(now)),
+            )
+            .set(accepted_at.eq(now))
+            .returning((session_type, subject_id))
+            .get_result::<(String, i32)>(c)
+            .optional()?
+            .and_then(|(kind, subject)| SessionType::from_db(&kind, subject));
+
+            if let Some(account) = account {
+                let (kind, subject) = account.to_db();
+                cancel_pending_invites(c, kind, subject)?;
+            }
+
+            match account {
+                Some(SessionType::Applicant(applicant_id)) => {
+                    diesel::insert_into(applicant_logins::table)
+                        .values(NewApplicantLogin {
+                            id: applicant_id,
+                            username: login_data.username,
+                            bcrypt_hash,
+                            verified: true,
+                        })
+                        .execute(c)?;
+                }
+                Some(SessionType::Professor(professor_id)) => {
+                    diesel::insert_into(professor_logins::table)
+                        .values(NewProfessorLogin {
+                            id: professor_id,
+                            username: login_data.username,
+                            bcrypt_hash,
+                        })
+                        .execute(c)?;
+                }
+                _ => return Ok(None),
+            }
+
+            Ok(account)
+        })
+    })
+    .await
+}
+
+pub const AUDIT_IMPERSONATION_START: &str = "IMPERSONATION_START";
+pub const AUDIT_IMPERSONATION_END: &str = "IMPERSONATION_END";
+
+/// Records something an administrator did to an account in the audit log.
+pub async fn record_audit_event(
+    conn: &DbConn,
+    administrator: i32,
+    audit_action: &'static str,
+    account: SessionType,
+) -> QueryResult<()> {
+    use schema::audit_log;
+
+    let (kind, subject) = account.to_db();
+    let event = NewAuditEvent {
+        administrator_id: Some(administrator),
+        action: audit_action.to_string(),
+        session_type: Some(kind.to_string()),
+        subject_id: Some(subject),
+    };
+
+    conn.run(move |c| {
+        diesel::insert_into(audit_log::table)
+            .values(&event)
+            .execute(c)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Gets the most recent audit log events, newest first.
+pub async fn get_audit_log(conn: &DbConn, limit: i64) -> QueryResult<Vec<AuditEvent>> {
+    use schema::audit_log::dsl::*;
+
+    conn.run(move |c| {
+        audit_log
+            .order(id.desc())
+            .limit(limit)
+            .load::<AuditEvent>(c)
+    })
+    .await
+}
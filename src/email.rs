@@ -1,43 +1,147 @@
-use lettre::{SmtpTransport, Message, Transport, message::Mailbox};
-use lettre::transport::smtp::authentication::Credentials;
+use crate::application_status::ApplicationStatus;
 use crate::models::Applicant;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{message::Mailbox, Message, SmtpTransport, Transport};
 use std::env;
 
-pub enum ApplicationStatus {
-    Accepted,
-    Denied
-}
+/// Where links in emails point to when `FRONTEND_URL` is not set.
+const DEFAULT_FRONTEND_URL: &str = "https://sysc4806project-frontend.vercel.app";
 
-impl std::string::ToString for ApplicationStatus {
-    fn to_string(&self) -> String {
-        match self {
-            ApplicationStatus::Accepted => "Accepted".to_string(),
-            ApplicationStatus::Denied => "Denied".to_string()
-        }
-    }
+/// Gets the base URL of the frontend for building links.
+fn frontend_url() -> String {
+    env::var("FRONTEND_URL").unwrap_or_else(|_| DEFAULT_FRONTEND_URL.to_string())
 }
 
-pub fn send_email_to_applicant(applicant: Applicant, application_status: ApplicationStatus)
-    -> anyhow::Result<()> {
+/// Sends an email from the admissions department through the configured SMTP account.
+fn send_email(to: Mailbox, subject: &str, body: String) -> anyhow::Result<()> {
     let smtp_username = env::var("SMTP_USER")?;
     let smtp_password = env::var("SMTP_PASS")?;
 
-    let admissions_mailbox: Mailbox = format!("Admissions Department <{}", smtp_username).parse()?;
-    let applicant_mailbox: Mailbox = format!("{} <{}>", applicant.name, applicant.email).parse()?;
+    let admissions_mailbox: Mailbox =
+        format!("Admissions Department <{}>", smtp_username).parse()?;
 
     let email = Message::builder()
         .from(admissions_mailbox)
-        .to(applicant_mailbox)
-        .subject("Change in Application Status")
-        .body(String::from(format!("Your application status has been changed to: {}", application_status.to_string())))?;
+        .to(to)
+        .subject(subject)
+        .body(body)?;
 
     let credentials = Credentials::new(smtp_username, smtp_password);
 
     let mailer = SmtpTransport::relay("smtp.gmail.com")?
-        .credentials(credentials).build();
+        .credentials(credentials)
+        .build();
 
     mailer.send(&email)?;
 
     Ok(())
+}
+
+/// Sends an email on a blocking thread, logging it if it fails, so that requests do not
+/// wait on the SMTP server or take longer when they send an email.
+pub fn send_in_background<F>(description: &'static str, send: F)
+where
+    F: FnOnce() -> anyhow::Result<()> + Send + 'static,
+{
+    rocket::tokio::task::spawn_blocking(move || {
+        if let Err(e) = send() {
+            eprintln!("Error occured while trying to send {}: {}", description, e);
+        }
+    });
+}
+
+pub fn send_email_to_applicant(
+    applicant: Applicant,
+    application_status: ApplicationStatus,
+) -> anyhow::Result<()> {
+    let applicant_mailbox: Mailbox = format!("{} <{}>", applicant.name, applicant.email).parse()?;
+
+    send_email(
+        applicant_mailbox,
+        "Change in Application Status",
+        format!(
+            "Your application status has been changed to: {}",
+            application_status
+        ),
+    )
+}
 
-}
\ No newline at end of file
+/// Sends a link for resetting an account's password using the given reset token.
+pub fn send_password_reset_email(name: &str, email: &str, token: &str) -> anyhow::Result<()> {
+    let mailbox: Mailbox = format!("{} <{}>", name, email).parse()?;
+
+    send_email(
+        mailbox,
+        "Password Reset",
+        format!(
+            "A password reset was requested for your account. To choose a new password, visit:\n\n\
+             {}/reset-password?token={}\n\n\
+             This link can only be used once and expires in one hour. If you did not request \
+             a reset, you can ignore this email.",
+            frontend_url(),
+            urlencode(token)
+        ),
+    )
+}
+
+/// Sends a link for an applicant to log in without their password.
+pub fn send_login_link_email(name: &str, email: &str, token: &str) -> anyhow::Result<()> {
+    let mailbox: Mailbox = format!("{} <{}>", name, email).parse()?;
+
+    send_email(
+        mailbox,
+        "Your Login Link",
+        format!(
+            "A login link was requested for your account. To log in, visit:\n\n\
+             {}/login-link?token={}\n\n\
+             This link can only be used once and expires in 15 minutes. If you did not request \
+             it, you can ignore this email.",
+            frontend_url(),
+            urlencode(token)
+        ),
+    )
+}
+
+/// Sends a link for verifying the email address of a newly registered applicant.
+pub fn send_verification_email(name: &str, email: &str, token: &str) -> anyhow::Result<()> {
+    let mailbox: Mailbox = format!("{} <{}>", name, email).parse()?;
+
+    send_email(
+        mailbox,
+        "Verify Your Email Address",
+        format!(
+            "Thank you for registering. Before you can apply, please verify your email address \
+             by visiting:\n\n\
+             {}/verify-email?token={}\n\n\
+             This link expires in one day. If you did not register, you can ignore this email.",
+            frontend_url(),
+            urlencode(token)
+        ),
+    )
+}
+
+/// Sends a link for a professor or applicant to set up the login of their new account.
+pub fn send_invite_email(name: &str, email: &str, token: &str) -> anyhow::Result<()> {
+    let mailbox: Mailbox = format!("{} <{}>", name, email).parse()?;
+
+    send_email(
+        mailbox,
+        "Your Admissions Account",
+        format!(
+            "An account has been created for you. To choose your username and password, \
+             visit:\n\n\
+             {}/accept-invite?token={}\n\n\
+             This link can only be used once and expires in one week.",
+            frontend_url(),
+            urlencode(token)
+        ),
+    )
+}
+
+/// Percent-encodes the characters of a base64 token that are not URL safe.
+fn urlencode(token: &str) -> String {
+    token
+        .replace('+', "%2B")
+        .replace('/', "%2F")
+        .replace('=', "%3D")
+}
@@ -0,0 +1,320 @@
+//! Password login for professors whose accounts are kept in an LDAP directory rather than
+//! in `professor_logins`:
+//!
+//! ```toml
+//! [default.ldap]
+//! enabled = true
+//! url = "ldap://ldap.example.edu:389"
+//! starttls = true
+//! bind_dn = "cn=admissions,ou=services,dc=example,dc=edu"
+//! bind_password = "..."
+//! base_dn = "ou=people,dc=example,dc=edu"
+//! user_filter = "(uid={username})"
+//! professor_attribute = "employeeNumber"
+//! professor_column = "id"
+//! timeout = 5
+//! ```
+//!
+//! A login whose username matches no local account is looked up with `user_filter`, as
+//! `bind_dn` or anonymously, and the password is checked by binding as the entry found.
+//! The entry's `professor_attribute` is then matched against the `professor_column` of
+//! `professors`, either its `id` or its `email`.
+//!
+//! These professors have no row in `professor_logins`, so `POST /rest/user/suspend`
+//! cannot suspend them. Their entry has to be disabled in the directory instead.
+
+use anyhow::anyhow;
+use ldap3::result::LdapError;
+use ldap3::{ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use rocket::fairing::{AdHoc, Fairing};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// The LDAP result code of a bind with the wrong password.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// The `professors` column a directory entry's professor attribute is matched with.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfessorColumn {
+    #[default]
+    Id,
+    Email,
+}
+
+/// The `ldap` section of the Rocket config.
+#[derive(Deserialize, Debug)]
+pub struct LdapConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_url")]
+    pub url: String,
+    #[serde(default)]
+    pub starttls: bool,
+    /// Entry to search for users as, the search is anonymous when unset.
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    #[serde(default)]
+    pub bind_password: Option<String>,
+    #[serde(default)]
+    pub base_dn: String,
+    /// Filter finding the entry of a username, which replaces `{username}`.
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    #[serde(default = "default_professor_attribute")]
+    pub professor_attribute: String,
+    #[serde(default)]
+    pub professor_column: ProfessorColumn,
+    /// Seconds to wait for the directory before giving up.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_url() -> String {
+    "ldap://localhost:389".to_string()
+}
+
+fn default_user_filter() -> String {
+    "(uid={username})".to_string()
+}
+
+fn default_professor_attribute() -> String {
+    "employeeNumber".to_string()
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+impl LdapConfig {
+    /// Checks that an enabled config can find users.
+    fn validate(&self) -> anyhow::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        if self.base_dn.is_empty() {
+            return Err(anyhow!("base_dn must be set"));
+        }
+        if !self.user_filter.contains("{username}") {
+            return Err(anyhow!("user_filter must contain {{username}}"));
+        }
+        Ok(())
+    }
+}
+
+pub struct LdapDirectory {
+    config: LdapConfig,
+}
+
+impl LdapDirectory {
+    pub fn new(config: LdapConfig) -> LdapDirectory {
+        LdapDirectory { config }
+    }
+
+    pub fn config(&self) -> &LdapConfig {
+        &self.config
+    }
+
+    /// Whether professors can log in with directory accounts.
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Checks a username and password against the directory, returning the professor
+    /// attribute of the user's entry if the password is correct and the entry has one.
+    pub async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<Option<String>> {
+        // Binding with an empty password is an unauthenticated bind, which directories
+        // accept for any entry.
+        if !self.is_enabled() || password.is_empty() {
+            return Ok(None);
+        }
+
+        let timeout = Duration::from_secs(self.config.timeout);
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(timeout)
+            .set_starttls(self.config.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url).await?;
+        ldap3::drive!(conn);
+
+        if let Some(bind_dn) = &self.config.bind_dn {
+            ldap.with_timeout(timeout)
+                .simple_bind(bind_dn, self.config.bind_password.as_deref().unwrap_or(""))
+                .await?
+                .success()?;
+        }
+
+        let filter = self
+            .config
+            .user_filter
+            .replace("{username}", &ldap_escape(username));
+        let (entries, _) = ldap
+            .with_timeout(timeout)
+            .search(
+                &self.config.base_dn,
+                Scope::Subtree,
+                &filter,
+                vec![self.config.professor_attribute.as_str()],
+            )
+            .await?
+            .success()?;
+
+        // A username has to identify a single entry.
+        let mut entries = entries.into_iter();
+        let entry = match (entries.next(), entries.next()) {
+            (Some(entry), None) => SearchEntry::construct(entry),
+            _ => {
+                ldap.unbind().await?;
+                return Ok(None);
+            }
+        };
+
+        let result = ldap
+            .with_timeout(timeout)
+            .simple_bind(&entry.dn, password)
+            .await?;
+        let authenticated = match result.rc {
+            0 => true,
+            INVALID_CREDENTIALS => false,
+            _ => return Err(LdapError::from(result).into()),
+        };
+        ldap.unbind().await?;
+
+        // Attribute names are case insensitive, and directories may not return them in
+        // the case they were asked for in.
+        Ok(authenticated
+            .then(|| {
+                entry
+                    .attrs
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&self.config.professor_attribute))
+                    .and_then(|(_, values)| values.first().cloned())
+            })
+            .flatten())
+    }
+}
+
+/// Reads the `ldap` config and manages `LdapDirectory`.
+pub fn fairing() -> impl Fairing {
+    AdHoc::try_on_ignite("LDAP", |rocket| async move {
+        let config = match rocket.figment().focus("ldap").extract::<LdapConfig>() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Invalid LDAP config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        if let Err(e) = config.validate() {
+            eprintln!("Invalid LDAP config: {}", e);
+            return Err(rocket);
+        }
+
+        Ok(rocket.manage(LdapDirectory::new(config)))
+    })
+}
+
+/// LDAP tests, which need a directory such as an osixia/openldap container started with
+/// its defaults. `LDAP_URL` is its URL, and `LDAP_ADMIN_DN` and `LDAP_ADMIN_PASSWORD` its
+/// administrator, which default to `cn=admin,dc=example,dc=org` and `admin`.
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{LdapConfig, LdapDirectory, ProfessorColumn};
+    use ldap3::{Ldap, LdapConnAsync};
+    use std::collections::HashSet;
+
+    pub const TEST_BASE_DN: &str = "dc=example,dc=org";
+
+    pub fn admin() -> (String, String) {
+        (
+            std::env::var("LDAP_ADMIN_DN")
+                .unwrap_or_else(|_| "cn=admin,dc=example,dc=org".to_string()),
+            std::env::var("LDAP_ADMIN_PASSWORD").unwrap_or_else(|_| "admin".to_string()),
+        )
+    }
+
+    pub fn test_url() -> String {
+        std::env::var("LDAP_URL").expect("LDAP_URL must be set")
+    }
+
+    async fn connect_as_admin() -> Ldap {
+        let (conn, mut ldap) = LdapConnAsync::new(&test_url())
+            .await
+            .expect("could not connect to the directory");
+        ldap3::drive!(conn);
+
+        let (admin_dn, admin_password) = admin();
+        ldap.simple_bind(&admin_dn, &admin_password)
+            .await
+            .and_then(|result| result.success())
+            .expect("could not bind as the directory administrator");
+        ldap
+    }
+
+    /// Adds a person to the test directory, returning their entry's DN.
+    pub async fn add_test_user(uid: &str, password: &str, employee_number: &str) -> String {
+        let mut ldap = connect_as_admin().await;
+        let dn = format!("uid={},{}", uid, TEST_BASE_DN);
+
+        ldap.add(
+            &dn,
+            vec![
+                ("objectClass", HashSet::from(["inetOrgPerson"])),
+                ("uid", HashSet::from([uid])),
+                ("cn", HashSet::from([uid])),
+                ("sn", HashSet::from([uid])),
+                ("userPassword", HashSet::from([password])),
+                ("employeeNumber", HashSet::from([employee_number])),
+            ],
+        )
+        .await
+        .and_then(|result| result.success())
+        .expect("could not add test user");
+
+        ldap.unbind().await.ok();
+        dn
+    }
+
+    pub async fn delete_test_user(dn: &str) {
+        let mut ldap = connect_as_admin().await;
+        ldap.delete(dn).await.ok();
+        ldap.unbind().await.ok();
+    }
+
+    // Tests that only the right password for a single entry returns its attribute.
+    #[rocket::async_test]
+    #[ignore]
+    async fn authenticates_against_directory() {
+        let uid = format!("ada-{}", chrono::Utc::now().timestamp_millis());
+        let dn = add_test_user(&uid, "analytical engine", "42").await;
+
+        let (admin_dn, admin_password) = admin();
+        let directory = LdapDirectory::new(LdapConfig {
+            enabled: true,
+            url: test_url(),
+            starttls: false,
+            bind_dn: Some(admin_dn),
+            bind_password: Some(admin_password),
+            base_dn: TEST_BASE_DN.to_string(),
+            user_filter: "(uid={username})".to_string(),
+            professor_attribute: "employeeNumber".to_string(),
+            professor_column: ProfessorColumn::Id,
+            timeout: 5,
+        });
+
+        let correct = directory.authenticate(&uid, "analytical engine").await;
+        let wrong = directory.authenticate(&uid, "difference engine").await;
+        let empty = directory.authenticate(&uid, "").await;
+        let wildcard = directory.authenticate("*", "analytical engine").await;
+        delete_test_user(&dn).await;
+
+        assert_eq!(correct.expect("directory error"), Some("42".to_string()));
+        assert_eq!(wrong.expect("directory error"), None);
+        assert_eq!(empty.expect("directory error"), None);
+        assert_eq!(wildcard.expect("directory error"), None);
+    }
+}
@@ -0,0 +1,200 @@
+//! Limits password guessing against `POST /rest/login`. Failed logins are counted per
+//! username and per client IP, and once either passes its allowance further attempts
+//! are refused for a lockout period that doubles with every additional failure.
+//!
+//! Client IPs are only counted when `client_ip_header` names the header a trusted reverse
+//! proxy puts them in, since clients could otherwise claim to be anyone by setting it.
+//!
+//! Requests for emails, such as login links, are limited the same way, per email address
+//! or username and per client IP, so that they cannot be used to flood an inbox.
+//!
+//! ```toml
+//! [default.login_throttle]
+//! max_username_failures = 5
+//! max_ip_failures = 20
+//! max_email_requests = 3
+//! base_lockout = 30
+//! max_lockout = 3600
+//! failure_window = 3600
+//! client_ip_header = "X-Real-IP"
+//! ```
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::fairing::{AdHoc, Fairing};
+use serde::Deserialize;
+
+pub const LOCKOUT_USERNAME: &str = "USERNAME";
+pub const LOCKOUT_IP: &str = "IP";
+pub const LOCKOUT_EMAIL: &str = "EMAIL";
+pub const LOCKOUT_EMAIL_IP: &str = "EMAIL_IP";
+
+/// The `login_throttle` section of the Rocket config. Times are in seconds.
+#[derive(Deserialize, Clone, Debug)]
+pub struct LoginThrottleConfig {
+    /// Failed attempts allowed for a username before it is locked out.
+    #[serde(default = "default_max_username_failures")]
+    pub max_username_failures: i32,
+    /// Failed attempts allowed from an IP address before it is locked out.
+    #[serde(default = "default_max_ip_failures")]
+    pub max_ip_failures: i32,
+    /// Emails that can be requested for an address or username before it is locked out.
+    /// Email requests from an IP are counted apart from its failed logins, with the same
+    /// allowance.
+    #[serde(default = "default_max_email_requests")]
+    pub max_email_requests: i32,
+    /// Length of the first lockout.
+    #[serde(default = "default_base_lockout")]
+    pub base_lockout: u32,
+    /// Longest a lockout can last.
+    #[serde(default = "default_max_lockout")]
+    pub max_lockout: u32,
+    /// How long without a failure before the failure count starts over.
+    #[serde(default = "default_failure_window")]
+    pub failure_window: u32,
+    /// The header the reverse proxy in front of the server sets to the client's IP.
+    #[serde(default)]
+    pub client_ip_header: Option<String>,
+}
+
+fn default_max_username_failures() -> i32 {
+    5
+}
+
+fn default_max_ip_failures() -> i32 {
+    20
+}
+
+fn default_max_email_requests() -> i32 {
+    3
+}
+
+fn default_base_lockout() -> u32 {
+    30
+}
+
+fn default_max_lockout() -> u32 {
+    60 * 60
+}
+
+fn default_failure_window() -> u32 {
+    60 * 60
+}
+
+impl LoginThrottleConfig {
+    /// Gets the failure allowance for a kind of lockout.
+    pub fn max_failures(&self, kind: &str) -> i32 {
+        match kind {
+            LOCKOUT_IP | LOCKOUT_EMAIL_IP => self.max_ip_failures,
+            LOCKOUT_EMAIL => self.max_email_requests,
+            _ => self.max_username_failures,
+        }
+    }
+
+    /// Works out how many failures there have been, including one at `now`, given the
+    /// previous failure count and when the last one happened.
+    pub fn next_failure_count(
+        &self,
+        previous: Option<(i32, DateTime<Utc>)>,
+        now: DateTime<Utc>,
+    ) -> i32 {
+        match previous {
+            Some((failures, last_failure_at))
+                if now - last_failure_at < Duration::seconds(self.failure_window as i64) =>
+            {
+                failures.saturating_add(1)
+            }
+            _ => 1,
+        }
+    }
+
+    /// Gets how long to lock out after the given number of failures, if at all.
+    pub fn lockout_duration(&self, kind: &str, failures: i32) -> Option<Duration> {
+        let excess = failures - self.max_failures(kind);
+        if excess < 0 {
+            return None;
+        }
+
+        let lockout = (self.base_lockout as i64)
+            .saturating_mul(1i64 << excess.min(32))
+            .min(self.max_lockout as i64);
+        Some(Duration::seconds(lockout))
+    }
+}
+
+/// Reads the `login_throttle` config and manages it as `LoginThrottleConfig`.
+pub fn fairing() -> impl Fairing {
+    AdHoc::try_on_ignite("Login Throttle", |rocket| async move {
+        match rocket
+            .figment()
+            .focus("login_throttle")
+            .extract::<LoginThrottleConfig>()
+        {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(e) => {
+                eprintln!("Invalid login throttle config: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+/// Login throttle tests.
+#[cfg(test)]
+mod test {
+    use super::{LoginThrottleConfig, LOCKOUT_EMAIL, LOCKOUT_IP, LOCKOUT_USERNAME};
+    use chrono::{Duration, Utc};
+
+    fn config() -> LoginThrottleConfig {
+        LoginThrottleConfig {
+            max_username_failures: 3,
+            max_ip_failures: 10,
+            max_email_requests: 2,
+            base_lockout: 30,
+            max_lockout: 300,
+            failure_window: 600,
+            client_ip_header: None,
+        }
+    }
+
+    // Tests that lockouts start at the allowance and double up to the maximum.
+    #[test]
+    fn lockout_backs_off_exponentially() {
+        let config = config();
+
+        assert_eq!(config.lockout_duration(LOCKOUT_USERNAME, 2), None);
+        assert_eq!(
+            config.lockout_duration(LOCKOUT_USERNAME, 3),
+            Some(Duration::seconds(30))
+        );
+        assert_eq!(
+            config.lockout_duration(LOCKOUT_USERNAME, 5),
+            Some(Duration::seconds(120))
+        );
+        assert_eq!(
+            config.lockout_duration(LOCKOUT_USERNAME, 100),
+            Some(Duration::seconds(300))
+        );
+        assert_eq!(config.lockout_duration(LOCKOUT_IP, 5), None);
+        assert_eq!(
+            config.lockout_duration(LOCKOUT_EMAIL, 2),
+            Some(Duration::seconds(30))
+        );
+    }
+
+    // Tests that old failures stop counting once the failure window passes.
+    #[test]
+    fn failures_reset_after_window() {
+        let config = config();
+        let now = Utc::now();
+
+        assert_eq!(config.next_failure_count(None, now), 1);
+        assert_eq!(
+            config.next_failure_count(Some((4, now - Duration::seconds(60))), now),
+            5
+        );
+        assert_eq!(
+            config.next_failure_count(Some((4, now - Duration::seconds(601))), now),
+            1
+        );
+    }
+}
@@ -7,20 +7,32 @@ extern crate rocket;
 extern crate diesel;
 
 use db::DbConn;
-use request_guards::state::SessionTokens;
-use rocket::futures::lock::Mutex;
+use session_store::SessionStore;
 use std::sync::Arc;
 
-pub type SessionTokenState = Arc<Mutex<SessionTokens>>;
+pub type SessionStoreState = Arc<dyn SessionStore>;
 
+pub mod application_status;
 pub mod db;
+pub mod email;
+pub mod ldap;
+pub mod login_throttle;
 pub mod models;
+pub mod oidc;
+pub mod password_policy;
+pub mod permissions;
 pub mod request_guards;
 pub mod rest;
+// print-schema adds the imports in diesel.toml to every table, not just those using them.
+#[allow(unused_imports)]
 pub mod schema;
-pub mod email;
+pub mod session_cookies;
+pub mod session_store;
+pub mod stateless_tokens;
+pub mod totp;
 
 mod fairings {
+    use crate::session_cookies::SessionCookieConfig;
     use rocket::{
         fairing::{Fairing, Info, Kind},
         http::Header,
@@ -39,13 +51,38 @@ mod fairings {
 
         async fn on_response<'r>(
             &self,
-            _req: &'r rocket::Request<'_>,
+            req: &'r rocket::Request<'_>,
             res: &mut rocket::Response<'r>,
         ) {
+            // Only the configured frontend may send session cookies, and browsers refuse
+            // credentials with a wildcard origin, so its origin is echoed back by name.
+            let allowed_origin = req
+                .rocket()
+                .state::<SessionCookieConfig>()
+                .filter(|config| config.enabled)
+                .and_then(|config| config.allowed_origin.clone());
+
+            if let Some(allowed_origin) = allowed_origin {
+                res.set_header(Header::new("Vary", "Origin"));
+
+                if req.headers().get_one("Origin") == Some(allowed_origin.as_str()) {
+                    res.set_header(Header::new("Access-Control-Allow-Origin", allowed_origin));
+                    res.set_header(Header::new(
+                        "Access-Control-Allow-Methods",
+                        "GET, POST, PUT, DELETE, OPTIONS",
+                    ));
+                    res.set_header(Header::new(
+                        "Access-Control-Allow-Headers",
+                        "Content-Type, X-Session-Token, X-CSRF-Token, X-Api-Key",
+                    ));
+                    res.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+                    return;
+                }
+            }
+
             res.set_header(Header::new("Access-Control-Allow-Origin", "*"));
             res.set_header(Header::new("Access-Control-Allow-Methods", "*"));
             res.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-            res.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
         }
     }
 
@@ -67,7 +104,15 @@ fn rocket() -> _ {
     rocket::build()
         .mount("/rest", rest::routes())
         .mount("/rest", routes![wildcard_options])
-        .manage(SessionTokenState::new(Mutex::new(SessionTokens::new())))
         .attach(DbConn::fairing())
+        .attach(session_store::fairing())
+        .attach(session_store::sweeper())
+        .attach(session_cookies::fairing())
+        .attach(stateless_tokens::fairing())
+        .attach(login_throttle::fairing())
+        .attach(totp::fairing())
+        .attach(password_policy::fairing())
+        .attach(oidc::fairing())
+        .attach(ldap::fairing())
         .attach(CORS::fairing())
 }
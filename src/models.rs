@@ -3,7 +3,9 @@
 //! perform DB opertations with, but any clients of this module may want to define
 //! specific datatypes as joins of these primitives for better ease of use.
 
+use crate::application_status::ApplicationStatus;
 use crate::schema::*;
+use chrono::{DateTime, Utc};
 use rocket::serde::{Deserialize, Serialize};
 
 /// A research field defined by a name, research fields can share names as they
@@ -30,6 +32,7 @@ pub struct NewResearchField {
 pub struct Professor {
     pub id: i32,
     pub name: String,
+    pub email: Option<String>,
 }
 
 /// This type represents a request for a new professor. It does not include an ID
@@ -38,6 +41,8 @@ pub struct Professor {
 #[table_name = "professors"]
 pub struct NewProfessor {
     pub name: String,
+    #[serde(default)]
+    pub email: Option<String>,
 }
 
 /// This type represents the relationship between a professor and a field that they
@@ -103,6 +108,8 @@ pub struct ApplicantEdit {
 #[derive(Deserialize)]
 pub struct ProfessorEdit {
     pub name: String,
+    #[serde(default)]
+    pub email: Option<String>,
 }
 
 /// This type represents the relationship between an applicant and a professor that they
@@ -115,9 +122,16 @@ pub struct ProfessorEdit {
 pub struct StudentAppliedTo {
     pub applicant_id: i32,
     pub prof_id: i32,
-    // Diesel does not have good support for Postgres enums, so we use strings for
-    // the application status
-    pub status: String,
+    pub status: ApplicationStatus,
+}
+
+/// An administrator's login, without its password hash.
+#[derive(Queryable, PartialEq, Debug, Deserialize, Serialize)]
+pub struct Administrator {
+    pub id: i32,
+    pub username: String,
+    /// Whether the administrator can log in, disabled administrators keep their login.
+    pub active: bool,
 }
 
 /// This type represents a request for a new admin.
@@ -137,6 +151,8 @@ pub struct NewApplicantLogin {
     pub id: i32,
     pub username: String,
     pub bcrypt_hash: String,
+    /// Whether the applicant's email address is known to be theirs.
+    pub verified: bool,
 }
 
 /// This type represents a request for a new professor. It does not include an ID
@@ -149,3 +165,169 @@ pub struct NewProfessorLogin {
     pub username: String,
     pub bcrypt_hash: String,
 }
+
+/// A persisted login session. Only a hash of the session token is stored, the
+/// token itself is only ever seen by the client it was issued to.
+#[derive(Queryable, Identifiable, PartialEq, Debug)]
+#[primary_key(token_hash)]
+pub struct Session {
+    pub token_hash: String,
+    pub session_type: String,
+    pub subject_id: i32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub idle_timeout: i32,
+    pub idle_expires_at: DateTime<Utc>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub impersonator: Option<i32>,
+}
+
+/// This type represents a request for a new session.
+#[derive(Insertable)]
+#[table_name = "sessions"]
+pub struct NewSession {
+    pub token_hash: String,
+    pub session_type: String,
+    pub subject_id: i32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub idle_timeout: i32,
+    pub idle_expires_at: DateTime<Utc>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub impersonator: Option<i32>,
+}
+
+/// This type represents a request for a new single-use token, such as the ones
+/// emailed for password resets. Only a hash of the token is stored.
+#[derive(Insertable)]
+#[table_name = "one_time_tokens"]
+pub struct NewOneTimeToken {
+    pub token_hash: String,
+    pub purpose: String,
+    pub session_type: String,
+    pub subject_id: i32,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Failed login attempts for a username or client IP, and when it is locked out until.
+#[derive(Queryable, Insertable, Identifiable, AsChangeset, PartialEq, Debug, Serialize)]
+#[primary_key(kind, value)]
+pub struct LoginLockout {
+    pub kind: String,
+    pub value: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+/// A single sign-on login that was sent to the identity provider and has not come back
+/// yet, keyed by the state the provider sends back with it.
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "oidc_logins"]
+pub struct OidcLogin {
+    pub state: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// An account's TOTP shared secret, which only takes effect once confirmed with a code.
+#[derive(Queryable, Debug)]
+pub struct TotpEnrollment {
+    pub session_type: String,
+    pub subject_id: i32,
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Insertable)]
+#[table_name = "totp_enrollments"]
+pub struct NewTotpEnrollment {
+    pub session_type: String,
+    pub subject_id: i32,
+    pub secret: String,
+}
+
+/// A single-use code for logging in without an authenticator. Only a hash is stored.
+#[derive(Insertable)]
+#[table_name = "totp_recovery_codes"]
+pub struct NewTotpRecoveryCode {
+    pub code_hash: String,
+    pub session_type: String,
+    pub subject_id: i32,
+}
+
+/// A key that integrations authenticate with, allowed to take the actions in its scopes.
+/// The key itself is only shown when it is created, and only its hash is stored.
+#[derive(Queryable, Debug, Serialize)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_keys"]
+pub struct NewApiKey {
+    pub name: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub created_by: Option<i32>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// An emailed invitation for a professor or applicant to choose their own login. Only a
+/// hash of the invite's token is stored.
+#[derive(Queryable, Debug, Serialize)]
+pub struct Invite {
+    pub id: i32,
+    pub session_type: String,
+    pub subject_id: i32,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "invites"]
+pub struct NewInvite {
+    pub token_hash: String,
+    pub session_type: String,
+    pub subject_id: i32,
+    pub created_by: Option<i32>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Something an administrator did that is kept for later review, such as impersonating
+/// an account.
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub id: i32,
+    pub administrator_id: Option<i32>,
+    pub action: String,
+    pub session_type: Option<String>,
+    pub subject_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_log"]
+pub struct NewAuditEvent {
+    pub administrator_id: Option<i32>,
+    pub action: String,
+    pub session_type: Option<String>,
+    pub subject_id: Option<i32>,
+}
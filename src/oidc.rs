@@ -0,0 +1,336 @@
+//! Single sign-on for professors through an OpenID Connect identity provider, next to
+//! password login:
+//!
+//! ```toml
+//! [default.oidc]
+//! enabled = true
+//! issuer_url = "https://sso.example.edu"
+//! client_id = "admissions"
+//! client_secret = "..."
+//! redirect_url = "https://admissions.example.edu/sso-callback"
+//! scopes = ["email", "profile"]
+//! match_email = true
+//! provision = false
+//! login_timeout = 600
+//! max_pending_logins = 1000
+//! ```
+//!
+//! `GET /rest/login/oidc` redirects to the provider with an authorization code request.
+//! The provider sends the browser back to `redirect_url`, whose page passes the `code` and
+//! `state` it was given to `POST /rest/login/oidc/callback` for an ordinary session token.
+//!
+//! Started logins are kept in the database until they are finished or time out, so any
+//! instance can finish them, and at most `max_pending_logins` are kept at once. The state
+//! is also set in a private cookie that the callback has to be sent with, so a login can
+//! only be finished by the browser that started it. The cookie follows the `secure` and
+//! `same_site` settings of the `session_cookies` section.
+//!
+//! A professor login is found by the provider's subject. Failing that, when `match_email`
+//! is set, a professor login without a subject is linked by the provider's verified email
+//! address, and when `provision` is set, a new professor is created for the subject.
+//! The provider's metadata is discovered on first use, so it does not need to be up for
+//! the server to launch.
+
+use crate::db::{self, DbConn};
+use crate::models::OidcLogin;
+use crate::session_cookies::SessionCookieConfig;
+use anyhow::anyhow;
+use chrono::Utc;
+use openidconnect::core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::url::Url;
+use openidconnect::{
+    AccessTokenHash, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce,
+    OAuth2TokenResponse, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{Cookie, CookieJar};
+use rocket::tokio::sync::OnceCell;
+use serde::Deserialize;
+
+const STATE_COOKIE_NAME: &str = "oidc_state";
+
+/// The `oidc` section of the Rocket config.
+#[derive(Deserialize, Debug)]
+pub struct OidcConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub issuer_url: String,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// Where the provider sends the browser back to after the professor signs in.
+    #[serde(default)]
+    pub redirect_url: String,
+    /// Scopes requested along with `openid`.
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// Whether professor logins without a subject are linked by verified email address.
+    #[serde(default)]
+    pub match_email: bool,
+    /// Whether unknown subjects get a new professor and login.
+    #[serde(default)]
+    pub provision: bool,
+    /// Seconds a started login can be finished in.
+    #[serde(default = "default_login_timeout")]
+    pub login_timeout: u64,
+    /// How many started logins can be waiting to be finished at once.
+    #[serde(default = "default_max_pending_logins")]
+    pub max_pending_logins: i64,
+}
+
+impl OidcConfig {
+    /// Checks that an enabled config has everything needed to reach the provider.
+    fn validate(&self) -> anyhow::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        IssuerUrl::new(self.issuer_url.clone())?;
+        RedirectUrl::new(self.redirect_url.clone())?;
+        if self.client_id.is_empty() {
+            return Err(anyhow!("client_id must be set"));
+        }
+        Ok(())
+    }
+}
+
+fn default_scopes() -> Vec<String> {
+    vec!["email".to_string(), "profile".to_string()]
+}
+
+fn default_login_timeout() -> u64 {
+    60 * 10
+}
+
+fn default_max_pending_logins() -> i64 {
+    1000
+}
+
+/// Who the identity provider says signed in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OidcIdentity {
+    pub subject: String,
+    /// The email address of the account, only if the provider has verified it.
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub preferred_username: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum OidcError {
+    /// The state is unknown, already used, too old or from another browser.
+    UnknownLogin,
+    /// Too many logins have been started and not finished.
+    TooManyLogins,
+    /// The pending login could not be saved or read.
+    Database(diesel::result::Error),
+    /// The provider could not be reached or refused the request.
+    Provider(anyhow::Error),
+    /// The provider's ID token failed verification.
+    InvalidToken(anyhow::Error),
+}
+
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OidcError::UnknownLogin => write!(f, "unknown or expired login"),
+            OidcError::TooManyLogins => write!(f, "too many pending logins"),
+            OidcError::Database(e) => write!(f, "database error: {}", e),
+            OidcError::Provider(e) => write!(f, "identity provider error: {}", e),
+            OidcError::InvalidToken(e) => write!(f, "invalid ID token: {}", e),
+        }
+    }
+}
+
+pub struct Oidc {
+    config: OidcConfig,
+    client: OnceCell<CoreClient>,
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Oidc {
+        Oidc {
+            config,
+            client: OnceCell::new(),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// Whether single sign-on is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Gets the client for the provider, discovering its metadata if that has not
+    /// succeeded yet.
+    async fn client(&self) -> anyhow::Result<&CoreClient> {
+        self.client
+            .get_or_try_init(|| async {
+                let metadata = CoreProviderMetadata::discover_async(
+                    IssuerUrl::new(self.config.issuer_url.clone())?,
+                    async_http_client,
+                )
+                .await?;
+
+                Ok(CoreClient::from_provider_metadata(
+                    metadata,
+                    ClientId::new(self.config.client_id.clone()),
+                    self.config.client_secret.clone().map(ClientSecret::new),
+                )
+                .set_redirect_uri(RedirectUrl::new(self.config.redirect_url.clone())?))
+            })
+            .await
+    }
+
+    /// Gets the earliest time a login can have been started at and still be finished.
+    fn oldest_login(&self) -> chrono::DateTime<Utc> {
+        Utc::now() - chrono::Duration::seconds(self.config.login_timeout as i64)
+    }
+
+    /// Starts a login for the browser with the given cookies, returning the provider URL
+    /// to send it to.
+    pub async fn start_login(
+        &self,
+        conn: &DbConn,
+        cookies: &CookieJar<'_>,
+        cookie_config: &SessionCookieConfig,
+    ) -> Result<Url, OidcError> {
+        let client = self.client().await.map_err(OidcError::Provider)?;
+
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let mut request = client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .set_pkce_challenge(pkce_challenge);
+        for scope in self.config.scopes.iter() {
+            request = request.add_scope(Scope::new(scope.clone()));
+        }
+        let (url, state, nonce) = request.url();
+
+        let login = OidcLogin {
+            state: state.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+            started_at: Utc::now(),
+        };
+        if !db::add_oidc_login(
+            conn,
+            login,
+            self.oldest_login(),
+            self.config.max_pending_logins,
+        )
+        .await
+        .map_err(OidcError::Database)?
+        {
+            return Err(OidcError::TooManyLogins);
+        }
+
+        cookies.add_private(
+            Cookie::build(STATE_COOKIE_NAME, state.secret().clone())
+                .http_only(true)
+                .secure(cookie_config.secure)
+                .same_site(cookie_config.same_site())
+                .path("/rest/login/oidc")
+                .max_age(time::Duration::seconds(self.config.login_timeout as i64))
+                .finish(),
+        );
+        Ok(url)
+    }
+
+    /// Finishes a login with the code and state the provider sent back, returning the
+    /// verified identity. The state has to match the cookie set when the login was
+    /// started, and can only be used once.
+    pub async fn finish_login(
+        &self,
+        conn: &DbConn,
+        cookies: &CookieJar<'_>,
+        code: String,
+        state: String,
+    ) -> Result<OidcIdentity, OidcError> {
+        let cookie_state = cookies.get_private(STATE_COOKIE_NAME);
+        cookies.remove_private(
+            Cookie::build(STATE_COOKIE_NAME, "")
+                .path("/rest/login/oidc")
+                .finish(),
+        );
+        if cookie_state.map(|cookie| cookie.value().to_string()) != Some(state.clone()) {
+            return Err(OidcError::UnknownLogin);
+        }
+
+        let login = db::take_oidc_login(conn, state, self.oldest_login())
+            .await
+            .map_err(OidcError::Database)?
+            .ok_or(OidcError::UnknownLogin)?;
+
+        let client = self.client().await.map_err(OidcError::Provider)?;
+        let response = client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(PkceCodeVerifier::new(login.pkce_verifier))
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| OidcError::Provider(e.into()))?;
+
+        let id_token = response
+            .id_token()
+            .ok_or_else(|| OidcError::InvalidToken(anyhow!("no ID token was returned")))?;
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &Nonce::new(login.nonce))
+            .map_err(|e| OidcError::InvalidToken(e.into()))?;
+
+        if let Some(expected_hash) = claims.access_token_hash() {
+            let actual_hash = id_token
+                .signing_alg()
+                .ok()
+                .and_then(|alg| AccessTokenHash::from_token(response.access_token(), &alg).ok());
+            if actual_hash.as_ref() != Some(expected_hash) {
+                return Err(OidcError::InvalidToken(anyhow!(
+                    "access token does not match its hash"
+                )));
+            }
+        }
+
+        Ok(OidcIdentity {
+            subject: claims.subject().to_string(),
+            email: claims
+                .email()
+                .filter(|_| claims.email_verified() == Some(true))
+                .map(|email| email.to_string()),
+            name: claims
+                .name()
+                .and_then(|name| name.get(None))
+                .map(|name| name.to_string()),
+            preferred_username: claims
+                .preferred_username()
+                .map(|username| username.to_string()),
+        })
+    }
+}
+
+/// Reads the `oidc` config and manages `Oidc`.
+pub fn fairing() -> impl Fairing {
+    AdHoc::try_on_ignite("OpenID Connect", |rocket| async move {
+        let config = match rocket.figment().focus("oidc").extract::<OidcConfig>() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Invalid OpenID Connect config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        if let Err(e) = config.validate() {
+            eprintln!("Invalid OpenID Connect config: {}", e);
+            return Err(rocket);
+        }
+
+        Ok(rocket.manage(Oidc::new(config)))
+    })
+}
@@ -0,0 +1,184 @@
+//! The rules new passwords must follow, and the bcrypt cost they are hashed with:
+//!
+//! ```toml
+//! [default.password_policy]
+//! min_length = 10
+//! common_passwords = "common-passwords.txt"
+//! bcrypt_cost = 12
+//! ```
+//!
+//! `common_passwords` is a file of passwords that are too well known to use, one per line.
+//! Stored hashes with a lower cost than `bcrypt_cost` are rehashed when their owner next
+//! logs in.
+
+use rocket::fairing::{AdHoc, Fairing};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// The `password_policy` section of the Rocket config.
+#[derive(Deserialize, Debug)]
+pub struct PasswordPolicyConfig {
+    #[serde(default = "default_min_length")]
+    pub min_length: usize,
+    /// Path of the common password list, none disables the check.
+    #[serde(default = "default_common_passwords")]
+    pub common_passwords: Option<String>,
+    #[serde(default = "default_bcrypt_cost")]
+    pub bcrypt_cost: u32,
+}
+
+fn default_min_length() -> usize {
+    10
+}
+
+fn default_common_passwords() -> Option<String> {
+    Some("common-passwords.txt".to_string())
+}
+
+fn default_bcrypt_cost() -> u32 {
+    bcrypt::DEFAULT_COST
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordRule {
+    MinLength,
+    NotCommon,
+    NoUsername,
+}
+
+/// The rules a rejected password failed, sent back with a 422.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PolicyViolation {
+    pub failed_rules: Vec<PasswordRule>,
+}
+
+pub struct PasswordPolicy {
+    min_length: usize,
+    bcrypt_cost: u32,
+    common_passwords: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    pub fn new(config: &PasswordPolicyConfig, common_passwords: HashSet<String>) -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: config.min_length,
+            bcrypt_cost: config.bcrypt_cost,
+            common_passwords,
+        }
+    }
+
+    /// The cost new password hashes are made with.
+    pub fn bcrypt_cost(&self) -> u32 {
+        self.bcrypt_cost
+    }
+
+    /// Checks a new password for an account against every rule.
+    pub fn check(&self, username: &str, password: &str) -> Result<(), PolicyViolation> {
+        let lowercase = password.to_lowercase();
+        let mut failed_rules = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            failed_rules.push(PasswordRule::MinLength);
+        }
+        if self.common_passwords.contains(&lowercase) {
+            failed_rules.push(PasswordRule::NotCommon);
+        }
+        if !username.is_empty() && lowercase.contains(&username.to_lowercase()) {
+            failed_rules.push(PasswordRule::NoUsername);
+        }
+
+        if failed_rules.is_empty() {
+            Ok(())
+        } else {
+            Err(PolicyViolation { failed_rules })
+        }
+    }
+}
+
+/// Reads a common password list, skipping blank lines and `#` comments.
+fn load_common_passwords(path: &str) -> std::io::Result<HashSet<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect())
+}
+
+/// Reads the `password_policy` config and manages `PasswordPolicy`.
+pub fn fairing() -> impl Fairing {
+    AdHoc::try_on_ignite("Password Policy", |rocket| async move {
+        let config = match rocket
+            .figment()
+            .focus("password_policy")
+            .extract::<PasswordPolicyConfig>()
+        {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Invalid password policy config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        if !(4..=31).contains(&config.bcrypt_cost) {
+            eprintln!(
+                "Invalid password policy config: bcrypt_cost must be between 4 and 31, found {}",
+                config.bcrypt_cost
+            );
+            return Err(rocket);
+        }
+
+        let common_passwords = match &config.common_passwords {
+            Some(path) => match load_common_passwords(path) {
+                Ok(common_passwords) => common_passwords,
+                Err(e) => {
+                    eprintln!("Could not read common password list {}: {}", path, e);
+                    return Err(rocket);
+                }
+            },
+            None => HashSet::new(),
+        };
+
+        Ok(rocket.manage(PasswordPolicy::new(&config, common_passwords)))
+    })
+}
+
+/// Password policy tests.
+#[cfg(test)]
+mod test {
+    use super::{PasswordPolicy, PasswordPolicyConfig, PasswordRule, PolicyViolation};
+
+    // Tests that each rule is reported when it fails, and only then.
+    #[test]
+    fn check_reports_failed_rules() {
+        let policy = PasswordPolicy::new(
+            &PasswordPolicyConfig {
+                min_length: 10,
+                common_passwords: None,
+                bcrypt_cost: 4,
+            },
+            ["password123".to_string()].into_iter().collect(),
+        );
+
+        assert_eq!(policy.check("ada", "correct horse battery"), Ok(()));
+        assert_eq!(
+            policy.check("ada", "short"),
+            Err(PolicyViolation {
+                failed_rules: vec![PasswordRule::MinLength]
+            })
+        );
+        assert_eq!(
+            policy.check("ada", "PassWord123"),
+            Err(PolicyViolation {
+                failed_rules: vec![PasswordRule::NotCommon]
+            })
+        );
+        assert_eq!(
+            policy.check("Lovelace", "lovelace1"),
+            Err(PolicyViolation {
+                failed_rules: vec![PasswordRule::MinLength, PasswordRule::NoUsername]
+            })
+        );
+    }
+}
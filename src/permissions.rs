@@ -0,0 +1,191 @@
+//! The permission policy, mapping each role to the actions it may take and on which
+//! resources. Handlers take a `Principal` and check the permission they need with
+//! `Principal::can`.
+
+use crate::request_guards::state::SessionType;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Applicant,
+    Professor,
+    Administrator,
+}
+
+impl Role {
+    /// Gets the role a session acts with.
+    pub fn of(session_type: SessionType) -> Role {
+        match session_type {
+            SessionType::Applicant(_) => Role::Applicant,
+            SessionType::Professor(_) => Role::Professor,
+            SessionType::Administrator(_) => Role::Administrator,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Reading research fields, which anyone can do without logging in. It is kept so
+    /// that API keys with the scope stay valid.
+    ResearchFieldRead,
+    ResearchFieldManage,
+    ProfessorRead,
+    ProfessorManage,
+    ProfessorEdit,
+    ApplicantRead,
+    ApplicantManage,
+    ApplicantEdit,
+    ApplicationRead,
+    ApplicationSubmit,
+    ApplicationDecide,
+    AccountManage,
+}
+
+impl Action {
+    pub const ALL: [Action; 12] = [
+        Action::ResearchFieldRead,
+        Action::ResearchFieldManage,
+        Action::ProfessorRead,
+        Action::ProfessorManage,
+        Action::ProfessorEdit,
+        Action::ApplicantRead,
+        Action::ApplicantManage,
+        Action::ApplicantEdit,
+        Action::ApplicationRead,
+        Action::ApplicationSubmit,
+        Action::ApplicationDecide,
+        Action::AccountManage,
+    ];
+
+    /// Finds an action by its name.
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL
+            .iter()
+            .copied()
+            .find(|action| action.name() == name)
+    }
+
+    /// Gets the name of the action, such as `application:decide`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::ResearchFieldRead => "research_field:read",
+            Action::ResearchFieldManage => "research_field:manage",
+            Action::ProfessorRead => "professor:read",
+            Action::ProfessorManage => "professor:manage",
+            Action::ProfessorEdit => "professor:edit",
+            Action::ApplicantRead => "applicant:read",
+            Action::ApplicantManage => "applicant:manage",
+            Action::ApplicantEdit => "applicant:edit",
+            Action::ApplicationRead => "application:read",
+            Action::ApplicationSubmit => "application:submit",
+            Action::ApplicationDecide => "application:decide",
+            Action::AccountManage => "account:manage",
+        }
+    }
+}
+
+/// What an action is taken on. `Any` is for actions that are not about one applicant
+/// or professor, such as listing or creating them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+    Any,
+    Applicant(i32),
+    Professor(i32),
+}
+
+impl Resource {
+    /// Checks whether the resource belongs to the account behind a session.
+    fn is_owned_by(&self, session_type: SessionType) -> bool {
+        match (self, session_type) {
+            (&Resource::Applicant(id), SessionType::Applicant(owner)) => id == owner,
+            (&Resource::Professor(id), SessionType::Professor(owner)) => id == owner,
+            _ => false,
+        }
+    }
+}
+
+/// Which resources a role may take an action on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scope {
+    Any,
+    Own,
+}
+
+const POLICY: &[(Role, Action, Scope)] = &[
+    (Role::Administrator, Action::ResearchFieldRead, Scope::Any),
+    (Role::Administrator, Action::ResearchFieldManage, Scope::Any),
+    (Role::Administrator, Action::ProfessorRead, Scope::Any),
+    (Role::Administrator, Action::ProfessorManage, Scope::Any),
+    (Role::Administrator, Action::ProfessorEdit, Scope::Any),
+    (Role::Administrator, Action::ApplicantRead, Scope::Any),
+    (Role::Administrator, Action::ApplicantManage, Scope::Any),
+    (Role::Administrator, Action::ApplicantEdit, Scope::Any),
+    (Role::Administrator, Action::ApplicationRead, Scope::Any),
+    (Role::Administrator, Action::ApplicationSubmit, Scope::Any),
+    (Role::Administrator, Action::ApplicationDecide, Scope::Any),
+    (Role::Administrator, Action::AccountManage, Scope::Any),
+    (Role::Professor, Action::ResearchFieldRead, Scope::Any),
+    (Role::Professor, Action::ProfessorRead, Scope::Any),
+    (Role::Professor, Action::ProfessorEdit, Scope::Own),
+    (Role::Professor, Action::ApplicantRead, Scope::Any),
+    (Role::Professor, Action::ApplicationRead, Scope::Own),
+    (Role::Professor, Action::ApplicationDecide, Scope::Own),
+    (Role::Applicant, Action::ResearchFieldRead, Scope::Any),
+    (Role::Applicant, Action::ProfessorRead, Scope::Any),
+    (Role::Applicant, Action::ApplicantRead, Scope::Own),
+    (Role::Applicant, Action::ApplicantEdit, Scope::Own),
+    (Role::Applicant, Action::ApplicationRead, Scope::Own),
+    (Role::Applicant, Action::ApplicationSubmit, Scope::Own),
+];
+
+/// Checks whether a session may take an action on a resource.
+pub fn is_permitted(session_type: SessionType, action: Action, resource: Resource) -> bool {
+    let role = Role::of(session_type);
+
+    POLICY.iter().any(|&(policy_role, policy_action, scope)| {
+        policy_role == role
+            && policy_action == action
+            && match scope {
+                Scope::Any => true,
+                Scope::Own => resource.is_owned_by(session_type),
+            }
+    })
+}
+
+/// Permission policy tests.
+#[cfg(test)]
+mod test {
+    use super::{is_permitted, Action, Resource};
+    use crate::request_guards::state::SessionType;
+
+    // Tests that own scoped permissions only cover the session's own account.
+    #[test]
+    fn own_scope_is_limited_to_own_account() {
+        let professor = SessionType::Professor(1);
+
+        assert!(is_permitted(
+            professor,
+            Action::ApplicationDecide,
+            Resource::Professor(1)
+        ));
+        assert!(!is_permitted(
+            professor,
+            Action::ApplicationDecide,
+            Resource::Professor(2)
+        ));
+        assert!(!is_permitted(
+            professor,
+            Action::ApplicationDecide,
+            Resource::Any
+        ));
+        assert!(!is_permitted(
+            SessionType::Applicant(1),
+            Action::ApplicantEdit,
+            Resource::Professor(1)
+        ));
+        assert!(is_permitted(
+            SessionType::Administrator(1),
+            Action::ApplicationDecide,
+            Resource::Professor(2)
+        ));
+    }
+}
@@ -1,260 +1,342 @@
-use chrono::Local;
+use crate::db::{self, DbConn};
+use crate::login_throttle::LoginThrottleConfig;
+use crate::permissions::{self, Action, Resource};
+use crate::session_cookies::{
+    csrf_token, needs_csrf_token, SessionCookieConfig, CSRF_HEADER_NAME, SESSION_COOKIE_NAME,
+};
+use crate::stateless_tokens::{Claims, StatelessTokens};
+use chrono::{DateTime, Utc};
 use rocket::request::FromRequest;
 use rocket::{http::Status, outcome::Outcome};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
 
 pub mod state {
-    use chrono::Local;
+    use serde::{Deserialize, Serialize};
 
-    use chrono::DateTime;
-    use serde::Serialize;
-    use std::collections::HashMap;
-
-    #[derive(Serialize, Clone, Copy, Debug)]
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
     pub enum SessionType {
         Applicant(i32),
         Professor(i32),
-        Administrator,
+        Administrator(i32),
     }
 
-    pub type ExpirationTime = DateTime<Local>;
-    pub type SessionTokens = HashMap<String, (SessionType, ExpirationTime)>;
+    pub const SESSION_APPLICANT: &str = "APPLICANT";
+    pub const SESSION_PROFESSOR: &str = "PROFESSOR";
+    pub const SESSION_ADMINISTRATOR: &str = "ADMINISTRATOR";
+
+    impl SessionType {
+        /// Splits a session type into the kind and subject id it is persisted as.
+        pub fn to_db(&self) -> (&'static str, i32) {
+            match *self {
+                SessionType::Applicant(id) => (SESSION_APPLICANT, id),
+                SessionType::Professor(id) => (SESSION_PROFESSOR, id),
+                SessionType::Administrator(id) => (SESSION_ADMINISTRATOR, id),
+            }
+        }
+
+        /// Gets the id of the account the session belongs to.
+        pub fn subject_id(&self) -> i32 {
+            match *self {
+                SessionType::Applicant(id)
+                | SessionType::Professor(id)
+                | SessionType::Administrator(id) => id,
+            }
+        }
+
+        /// Rebuilds a session type from its persisted kind and subject id.
+        pub fn from_db(kind: &str, subject_id: i32) -> Option<SessionType> {
+            match kind {
+                SESSION_APPLICANT => Some(SessionType::Applicant(subject_id)),
+                SESSION_PROFESSOR => Some(SessionType::Professor(subject_id)),
+                SESSION_ADMINISTRATOR => Some(SessionType::Administrator(subject_id)),
+                _ => None,
+            }
+        }
+    }
 }
 
 pub const SESSION_TOKEN_HEADER_NAME: &'static str = "X-Session-Token";
+pub const API_KEY_HEADER_NAME: &str = "X-Api-Key";
+
+/// Hashes a bearer token for storage, so that a leaked row cannot be used to log in.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Finds the session token of a request, in the `X-Session-Token` header or, when cookie
+/// sessions are enabled, the session cookie. A cookie is only accepted for requests that
+/// may change state if they carry its CSRF token.
+fn request_session_token(request: &rocket::Request<'_>) -> Option<String> {
+    if let Some(token) = request.headers().get_one(SESSION_TOKEN_HEADER_NAME) {
+        return Some(token.to_string());
+    }
+
+    if !request
+        .rocket()
+        .state::<SessionCookieConfig>()
+        .is_some_and(|config| config.enabled)
+    {
+        return None;
+    }
 
-pub struct SessionTokenHeader {
+    let token = request
+        .cookies()
+        .get_private(SESSION_COOKIE_NAME)?
+        .value()
+        .to_string();
+    if needs_csrf_token(request.method())
+        && request.headers().get_one(CSRF_HEADER_NAME) != Some(csrf_token(&token).as_str())
+    {
+        return None;
+    }
+
+    Some(token)
+}
+
+/// Guard for the session token of a request, from the header or the session cookie.
+pub struct SessionToken {
     pub session_token: String,
 }
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for SessionTokenHeader {
+impl<'r> FromRequest<'r> for SessionToken {
     type Error = ();
 
     async fn from_request(
         request: &'r rocket::Request<'_>,
     ) -> rocket::request::Outcome<Self, Self::Error> {
-        match request.headers().get_one(SESSION_TOKEN_HEADER_NAME) {
-            Some(v) => Outcome::Success(SessionTokenHeader {
-                session_token: v.to_string(),
-            }),
+        match request_session_token(request) {
+            Some(session_token) => Outcome::Success(SessionToken { session_token }),
             None => Outcome::Failure((Status::Unauthorized, ())),
         }
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct Professor {
-    pub professor_id: i32,
+/// Longest user agent kept for a session, longer ones are truncated.
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// Guard for where a request came from, which is recorded with the sessions it starts.
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    /// The client's IP as reported by the trusted proxy, which failed logins can be
+    /// counted against. Unknown unless `login_throttle.client_ip_header` is set.
+    pub lockout_ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
 }
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for Professor {
+impl<'r> FromRequest<'r> for ClientInfo {
     type Error = ();
 
     async fn from_request(
         request: &'r rocket::Request<'_>,
     ) -> rocket::request::Outcome<Self, Self::Error> {
-        let result: Result<Professor, ()> = try {
-            let session_token_lock = request
-                .rocket()
-                .state::<crate::SessionTokenState>()
-                .ok_or(())?;
-            let mut session_tokens = session_token_lock.lock().await;
-            let token = request
+        // A proxy appending to a list of addresses puts the one it saw last.
+        let lockout_ip = request
+            .rocket()
+            .state::<LoginThrottleConfig>()
+            .and_then(|config| config.client_ip_header.as_deref())
+            .and_then(|header| request.headers().get_one(header))
+            .and_then(|addresses| addresses.rsplit(',').next())
+            .and_then(|address| address.trim().parse().ok());
+
+        Outcome::Success(ClientInfo {
+            ip: lockout_ip.or_else(|| request.remote().map(|remote| remote.ip())),
+            lockout_ip,
+            user_agent: request
                 .headers()
-                .get_one(SESSION_TOKEN_HEADER_NAME)
-                .ok_or(())?;
-
-            let (session_type, expiration_time) = session_tokens.get(token).ok_or(())?;
-            if Local::now() > expiration_time.clone() {
-                session_tokens.remove(token);
-                Err(())?
-            } else {
-                use state::SessionType;
-                match session_type {
-                    &SessionType::Professor(professor_id) => Professor { professor_id },
-                    &_ => Err(())?,
-                }
-            }
-        };
-        match result {
-            Ok(professor) => Outcome::Success(professor),
-            Err(_) => Outcome::Failure((Status::Forbidden, ())),
-        }
+                .get_one("User-Agent")
+                .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+        })
     }
 }
 
-pub struct Applicant {
-    pub applicant_id: i32,
+/// Guard for the active session belonging to the request's session token, which is
+/// either a stateless token or the token of a session in the session store.
+#[derive(Clone, Copy, Debug)]
+pub struct ActiveSession {
+    pub session_type: state::SessionType,
+    /// When the session expires unless it is used again.
+    pub expires_at: DateTime<Utc>,
+    pub stateless: bool,
+    /// The administrator acting as the account, if this is an impersonation session.
+    pub impersonator: Option<i32>,
 }
 
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for Applicant {
-    type Error = ();
+/// Checks a stateless token, only checking out a database connection when the account's
+/// revocation generation is not cached.
+async fn stateless_session(
+    request: &rocket::Request<'_>,
+    stateless_tokens: &StatelessTokens,
+    claims: Claims,
+) -> Result<ActiveSession, ()> {
+    if claims.expires_at <= Utc::now() {
+        return Err(());
+    }
 
-    async fn from_request(
-        request: &'r rocket::Request<'_>,
-    ) -> rocket::request::Outcome<Self, Self::Error> {
-        let result: Result<Applicant, ()> = try {
-            let session_token_lock = request
-                .rocket()
-                .state::<crate::SessionTokenState>()
-                .ok_or(())?;
-            let mut session_tokens = session_token_lock.lock().await;
-            let token = request
-                .headers()
-                .get_one(SESSION_TOKEN_HEADER_NAME)
-                .ok_or(())?;
-
-            let (session_type, expiration_time) = session_tokens.get(token).ok_or(())?;
-            if Local::now() > expiration_time.clone() {
-                session_tokens.remove(token);
-                Err(())?
-            } else {
-                use state::SessionType;
-                match session_type {
-                    &SessionType::Applicant(applicant_id) => Applicant { applicant_id },
-                    &_ => Err(())?,
-                }
-            }
-        };
-        match result {
-            Ok(applicant) => Outcome::Success(applicant),
-            Err(_) => Outcome::Failure((Status::Forbidden, ())),
+    let generation = match stateless_tokens.cached_generation(claims.session_type) {
+        Some(generation) => generation,
+        None => {
+            let conn = request.guard::<DbConn>().await.succeeded().ok_or(())?;
+            stateless_tokens
+                .generation(&conn, claims.session_type)
+                .await
+                .map_err(|e| eprintln!("Error occured while trying to get generation: {}", e))?
         }
+    };
+
+    if generation != claims.generation {
+        return Err(());
     }
-}
 
-pub struct Administrator {}
+    Ok(ActiveSession {
+        session_type: claims.session_type,
+        expires_at: claims.expires_at,
+        stateless: true,
+        impersonator: None,
+    })
+}
 
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for Administrator {
-    type Error = ();
+async fn request_session(request: &rocket::Request<'_>) -> Result<ActiveSession, ()> {
+    let token = request_session_token(request).ok_or(())?;
 
-    async fn from_request(
-        request: &'r rocket::Request<'_>,
-    ) -> rocket::request::Outcome<Self, Self::Error> {
-        let result: Result<Administrator, ()> = try {
-            let session_token_lock = request
-                .rocket()
-                .state::<crate::SessionTokenState>()
-                .ok_or(())?;
-            let mut session_tokens = session_token_lock.lock().await;
-            let token = request
-                .headers()
-                .get_one(SESSION_TOKEN_HEADER_NAME)
-                .ok_or(())?;
-
-            let (session_type, expiration_time) = session_tokens.get(token).ok_or(())?;
-            if Local::now() > expiration_time.clone() {
-                session_tokens.remove(token);
-                Err(())?
-            } else {
-                use state::SessionType;
-                match session_type {
-                    &SessionType::Administrator => Administrator {},
-                    &_ => Err(())?,
-                }
+    if let Some(stateless_tokens) = request.rocket().state::<StatelessTokens>() {
+        if stateless_tokens.is_enabled() {
+            if let Some(claims) = stateless_tokens.decode(&token) {
+                return stateless_session(request, stateless_tokens, claims).await;
             }
-        };
-        match result {
-            Ok(administrator) => Outcome::Success(administrator),
-            Err(_) => Outcome::Failure((Status::Forbidden, ())),
         }
     }
-}
 
-pub enum AdminOrApplicant {
-    Admin,
-    Applicant(i32),
-}
-
-impl AdminOrApplicant {
-    pub fn can_access_applicant(&self, id: i32) -> bool {
-        match self {
-            &AdminOrApplicant::Admin => true,
-            &AdminOrApplicant::Applicant(v) => v == id,
+    let session_store = request
+        .rocket()
+        .state::<crate::SessionStoreState>()
+        .ok_or(())?;
+
+    let session = session_store
+        .get(&hash_token(&token))
+        .await
+        .map_err(|e| eprintln!("Error occured while trying to get session: {}", e))?
+        .ok_or(())?;
+
+    // Sessions end as soon as their account is suspended, and impersonation as soon as
+    // the administrator behind it is disabled or deleted. Stateless tokens are revoked
+    // when their account is suspended instead, so that they keep not needing the database.
+    let conn = request.guard::<DbConn>().await.succeeded().ok_or(())?;
+    let accounts = std::iter::once(session.session_type)
+        .chain(session.impersonator.map(state::SessionType::Administrator));
+    for account in accounts {
+        let active = db::account_active(&conn, account)
+            .await
+            .map_err(|e| eprintln!("DB error occured while trying to check account: {}", e))?;
+        if !active {
+            return Err(());
         }
     }
+
+    Ok(ActiveSession {
+        session_type: session.session_type,
+        expires_at: session.idle_expires_at,
+        stateless: false,
+        impersonator: session.impersonator,
+    })
 }
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for AdminOrApplicant {
+impl<'r> FromRequest<'r> for ActiveSession {
     type Error = ();
 
     async fn from_request(
         request: &'r rocket::Request<'_>,
     ) -> rocket::request::Outcome<Self, Self::Error> {
-        match Administrator::from_request(&request).await {
-            Outcome::Success(_) => return Outcome::Success(AdminOrApplicant::Admin),
-            _ => {}
-        };
-
-        match Applicant::from_request(&request).await {
-            Outcome::Success(applicant) => {
-                Outcome::Success(AdminOrApplicant::Applicant(applicant.applicant_id))
-            }
-            _ => Outcome::Failure((Status::Forbidden, ())),
+        let session = request.local_cache_async(request_session(request)).await;
+        match session {
+            Ok(session) => Outcome::Success(*session),
+            Err(_) => Outcome::Failure((Status::Forbidden, ())),
         }
     }
 }
 
-pub enum AdminOrProfessor {
-    Admin,
-    Professor(i32),
+/// Guard for any logged in user or API key, used by handlers to check the permissions
+/// they need. A session token header is checked before an API key, and an API key before
+/// a session cookie.
+#[derive(Clone, Debug)]
+pub enum Principal {
+    User(state::SessionType),
+    /// An integration using an API key, which may take the actions in its scopes on any
+    /// resource.
+    ApiKey {
+        id: i32,
+        scopes: Vec<Action>,
+    },
 }
 
-impl AdminOrProfessor {
-    pub fn can_access_prof(&self, id: i32) -> bool {
+impl Principal {
+    /// Gets the session type of a logged in user.
+    pub fn session_type(&self) -> Option<state::SessionType> {
         match self {
-            &AdminOrProfessor::Admin => true,
-            &AdminOrProfessor::Professor(v) => v == id,
+            Principal::User(session_type) => Some(*session_type),
+            Principal::ApiKey { .. } => None,
         }
     }
-}
 
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for AdminOrProfessor {
-    type Error = ();
+    /// Gets the id of the administrator account, if the user is an administrator.
+    pub fn administrator_id(&self) -> Option<i32> {
+        match self.session_type() {
+            Some(state::SessionType::Administrator(id)) => Some(id),
+            _ => None,
+        }
+    }
 
-    async fn from_request(
-        request: &'r rocket::Request<'_>,
-    ) -> rocket::request::Outcome<Self, Self::Error> {
-        match Administrator::from_request(&request).await {
-            Outcome::Success(_) => return Outcome::Success(AdminOrProfessor::Admin),
-            _ => {}
-        };
-
-        match Professor::from_request(&request).await {
-            Outcome::Success(professor) => {
-                Outcome::Success(AdminOrProfessor::Professor(professor.professor_id))
+    /// Checks whether the user or API key may take an action on a resource.
+    pub fn can(&self, action: Action, resource: Resource) -> bool {
+        match self {
+            Principal::User(session_type) => {
+                permissions::is_permitted(*session_type, action, resource)
             }
-            _ => Outcome::Failure((Status::Forbidden, ())),
+            Principal::ApiKey { scopes, .. } => scopes.contains(&action),
         }
     }
 }
 
-pub struct LoggedIn {}
+/// Finds the unrevoked and unexpired API key sent with the request, recording its use.
+async fn request_api_key(request: &rocket::Request<'_>) -> Result<Principal, ()> {
+    let key = request.headers().get_one(API_KEY_HEADER_NAME).ok_or(())?;
+    let conn = request.guard::<DbConn>().await.succeeded().ok_or(())?;
+
+    let (id, scopes) = db::use_api_key(&conn, hash_token(key))
+        .await
+        .map_err(|e| eprintln!("DB error occured while trying to use API key: {}", e))?
+        .ok_or(())?;
+
+    Ok(Principal::ApiKey {
+        id,
+        scopes: scopes
+            .iter()
+            .filter_map(|scope| Action::from_name(scope))
+            .collect(),
+    })
+}
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for LoggedIn {
+impl<'r> FromRequest<'r> for Principal {
     type Error = ();
 
     async fn from_request(
         request: &'r rocket::Request<'_>,
     ) -> rocket::request::Outcome<Self, Self::Error> {
-        match Administrator::from_request(&request).await {
-            Outcome::Success(_) => return Outcome::Success(LoggedIn {}),
-            _ => {}
-        };
-
-        match Applicant::from_request(&request).await {
-            Outcome::Success(_) => return Outcome::Success(LoggedIn {}),
-            _ => {}
-        };
-
-        match Professor::from_request(&request).await {
-            Outcome::Success(_) => Outcome::Success(LoggedIn {}),
+        if request.headers().contains(API_KEY_HEADER_NAME)
+            && !request.headers().contains(SESSION_TOKEN_HEADER_NAME)
+        {
+            return match request_api_key(request).await {
+                Ok(principal) => Outcome::Success(principal),
+                Err(_) => Outcome::Failure((Status::Forbidden, ())),
+            };
+        }
+
+        match ActiveSession::from_request(request).await {
+            Outcome::Success(session) => Outcome::Success(Principal::User(session.session_type)),
             _ => Outcome::Failure((Status::Forbidden, ())),
         }
     }
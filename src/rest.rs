@@ -1,20 +1,35 @@
 //! Defines the REST endpoints for the Graduate Admissions Management System API.
 
+use crate::application_status::ApplicationStatus;
 use crate::db::validate_login;
-use crate::db::{self, APPLICATION_ACCEPTED, APPLICATION_DENIED, APPLICATION_PENDING, ID};
-use crate::db::{ApplicantIDNameField, DbConn};
-use crate::email::{send_email_to_applicant, ApplicationStatus};
+use crate::db::{self, ID};
+use crate::db::{AccountCreationError, ApplicantIDNameField, DbConn, LoginError};
+use crate::email::{
+    send_email_to_applicant, send_in_background, send_invite_email, send_login_link_email,
+    send_password_reset_email, send_verification_email,
+};
+use crate::ldap::LdapDirectory;
+use crate::login_throttle::{
+    LoginThrottleConfig, LOCKOUT_EMAIL, LOCKOUT_EMAIL_IP, LOCKOUT_IP, LOCKOUT_USERNAME,
+};
 use crate::models::*;
+use crate::oidc::{Oidc, OidcConfig, OidcError, OidcIdentity};
+use crate::password_policy::{PasswordPolicy, PolicyViolation};
+use crate::permissions::{Action, Resource};
 use crate::request_guards::state::SessionType;
-use crate::request_guards::{
-    AdminOrApplicant, AdminOrProfessor, Administrator, LoggedIn, SessionTokenHeader,
-};
-use crate::SessionTokenState;
-use chrono::{Duration, Local};
+use crate::request_guards::{hash_token, ActiveSession, ClientInfo, Principal, SessionToken};
+use crate::session_cookies::SessionCookieConfig;
+use crate::session_store::{Session, SessionConfig, SessionLifetime};
+use crate::stateless_tokens::StatelessTokens;
+use crate::totp::{self, TotpConfig};
+use crate::SessionStoreState;
+use chrono::{DateTime, Duration, Utc};
+use diesel::QueryResult;
 use rand_chacha::rand_core::RngCore;
 use rand_chacha::rand_core::SeedableRng;
 use rocket::data::ByteUnit;
-use rocket::http::Status;
+use rocket::http::{CookieJar, Status};
+use rocket::response::Redirect;
 use rocket::serde::json::Json;
 use rocket::State;
 use rocket::{Data, Route};
@@ -31,8 +46,12 @@ struct IdPayload {
 async fn create_research_field(
     conn: DbConn,
     research_field: Json<NewResearchField>,
-    _admin: Administrator,
+    principal: Principal,
 ) -> Result<Json<IdPayload>, Status> {
+    if !principal.can(Action::ResearchFieldManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
     match db::create_research_field(&conn, research_field.into_inner().name).await {
         Ok(id) => Ok(Json(IdPayload { id })),
         Err(e) => {
@@ -45,7 +64,8 @@ async fn create_research_field(
     }
 }
 
-/// Endpoint for getting a research field.
+/// Endpoint for getting a research field. Research fields are public, so that applicants
+/// can choose one when they register.
 #[get("/research-field?<id>")]
 async fn get_research_field(conn: DbConn, id: i32) -> Result<Json<ResearchField>, Status> {
     match db::get_research_field(&conn, id).await {
@@ -60,12 +80,9 @@ async fn get_research_field(conn: DbConn, id: i32) -> Result<Json<ResearchField>
     }
 }
 
-/// Endpoint for getting all research fields.
+/// Endpoint for getting all research fields, which are public.
 #[get("/research-fields")]
-async fn get_research_fields(
-    conn: DbConn,
-    _logged_in: LoggedIn,
-) -> Result<Json<Vec<ResearchField>>, Status> {
+async fn get_research_fields(conn: DbConn) -> Result<Json<Vec<ResearchField>>, Status> {
     match db::get_research_fields(&conn).await {
         Ok(research_fields) => Ok(Json(research_fields)),
         Err(e) => {
@@ -77,7 +94,11 @@ async fn get_research_fields(
 
 /// Endpoint for deleting a research field.
 #[delete("/research-field?<id>")]
-async fn delete_research_field(conn: DbConn, id: i32, _admin: Administrator) -> Status {
+async fn delete_research_field(conn: DbConn, id: i32, principal: Principal) -> Status {
+    if !principal.can(Action::ResearchFieldManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
     match db::delete_research_field(&conn, id).await {
         Ok(_) => Status::Ok,
         Err(e) => {
@@ -95,9 +116,13 @@ async fn delete_research_field(conn: DbConn, id: i32, _admin: Administrator) ->
 async fn create_professor(
     conn: DbConn,
     professor: Json<NewProfessor>,
-    _admin: Administrator,
+    principal: Principal,
 ) -> Result<Json<IdPayload>, Status> {
-    match db::create_professor(&conn, professor.into_inner().name).await {
+    if !principal.can(Action::ProfessorManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
+    match db::create_professor(&conn, professor.into_inner()).await {
         Ok(id) => Ok(Json(IdPayload { id })),
         Err(e) => {
             eprintln!("DB error occured while trying to create professor: {}", e);
@@ -111,8 +136,12 @@ async fn create_professor(
 async fn get_professor(
     conn: DbConn,
     id: i32,
-    _logged_in: LoggedIn,
+    principal: Principal,
 ) -> Result<Json<Professor>, Status> {
+    if !principal.can(Action::ProfessorRead, Resource::Professor(id)) {
+        return Err(Status::Forbidden);
+    }
+
     match db::get_professor(&conn, id).await {
         Ok(professor) => match professor {
             Some(professor) => Ok(Json(professor)),
@@ -129,8 +158,12 @@ async fn get_professor(
 #[get("/professors")]
 async fn get_professors(
     conn: DbConn,
-    _logged_in: LoggedIn,
+    principal: Principal,
 ) -> Result<Json<Vec<Professor>>, Status> {
+    if !principal.can(Action::ProfessorRead, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
     match db::get_professors(&conn).await {
         Ok(v) => Ok(Json(v)),
         Err(e) => {
@@ -142,7 +175,11 @@ async fn get_professors(
 
 /// Endpoint for deleting a professor.
 #[delete("/professor?<id>")]
-async fn delete_professor(conn: DbConn, id: i32, _admin: Administrator) -> Status {
+async fn delete_professor(conn: DbConn, id: i32, principal: Principal) -> Status {
+    if !principal.can(Action::ProfessorManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
     match db::delete_professor(&conn, id).await {
         Ok(_) => Status::Ok,
         Err(e) => {
@@ -158,9 +195,9 @@ async fn add_researched_field_to_professor(
     conn: DbConn,
     prof_id: i32,
     field_id: i32,
-    admin_or_professor: AdminOrProfessor,
+    principal: Principal,
 ) -> Status {
-    if !admin_or_professor.can_access_prof(prof_id) {
+    if !principal.can(Action::ProfessorEdit, Resource::Professor(prof_id)) {
         return Status::Forbidden;
     }
 
@@ -181,8 +218,12 @@ async fn add_researched_field_to_professor(
 async fn get_fields_professor_researches(
     conn: DbConn,
     prof_id: i32,
-    _logged_in: LoggedIn,
+    principal: Principal,
 ) -> Result<Json<Vec<ResearchField>>, Status> {
+    if !principal.can(Action::ProfessorRead, Resource::Professor(prof_id)) {
+        return Err(Status::Forbidden);
+    }
+
     match db::get_fields_professor_researches(&conn, prof_id).await {
         Ok(research_fields) => Ok(Json(research_fields)),
         Err(e) => {
@@ -200,13 +241,15 @@ async fn get_applicants_for_professor_with_status(
     conn: DbConn,
     id: i32,
     status: String,
-    _logged_in: LoggedIn,
+    principal: Principal,
 ) -> Result<Json<Vec<ApplicantIDNameField>>, Status> {
-    match status.as_str() {
-        APPLICATION_ACCEPTED => {}
-        APPLICATION_DENIED => {}
-        APPLICATION_PENDING => {}
-        _ => {
+    if !principal.can(Action::ApplicationRead, Resource::Professor(id)) {
+        return Err(Status::Forbidden);
+    }
+
+    let status = match ApplicationStatus::parse(&status) {
+        Some(status) => status,
+        None => {
             eprintln!(
                 "Client asked for bad status, no status known as: {}",
                 status
@@ -215,7 +258,7 @@ async fn get_applicants_for_professor_with_status(
         }
     };
 
-    match db::get_applications_for_professor_with_status(&conn, id, status.clone()).await {
+    match db::get_applications_for_professor_with_status(&conn, id, status).await {
         Ok(v) => Ok(Json(v)),
         Err(e) => {
             eprintln!(
@@ -233,9 +276,9 @@ async fn remove_researched_field_from_professor(
     conn: DbConn,
     prof_id: i32,
     field_id: i32,
-    admin_or_professor: AdminOrProfessor,
+    principal: Principal,
 ) -> Status {
-    if !admin_or_professor.can_access_prof(prof_id) {
+    if !principal.can(Action::ProfessorEdit, Resource::Professor(prof_id)) {
         return Status::Forbidden;
     }
 
@@ -251,78 +294,120 @@ async fn remove_researched_field_from_professor(
     }
 }
 
-#[post("/professor/application/accept?<applicant_id>&<professor_id>")]
-pub async fn accept_application(
-    conn: DbConn,
+/// Moves an application to a new status, emailing the applicant about the professor's
+/// decisions. The email is only a courtesy, so failing to send it is not an error once
+/// the status has changed.
+async fn change_application_status(
+    conn: &DbConn,
     applicant_id: i32,
     professor_id: i32,
-    admin_or_professor: AdminOrProfessor,
+    status: ApplicationStatus,
 ) -> Result<(), Status> {
-    if !admin_or_professor.can_access_prof(professor_id) {
-        return Err(Status::Forbidden);
-    }
-
-    if let Err(e) = db::accept_applicant_application(&conn, applicant_id, professor_id).await {
-        eprintln!("Error while accepting an applicant's application: {}", e);
-        Err(Status::InternalServerError)
-    } else {
-        let applicant = match db::get_applicant(&conn, applicant_id).await {
-            Ok(v) => match v {
-                Some(v) => v,
-                None => return Err(Status::NotFound),
-            },
-            Err(e) => {
-                eprintln!("Error while accepting an applicant's application: {}", e);
-                return Err(Status::InternalServerError);
-            }
-        };
-
-        if let Err(e) = send_email_to_applicant(applicant, ApplicationStatus::Accepted) {
+    match db::set_application_status(conn, applicant_id, professor_id, status).await {
+        Ok(db::ApplicationChange::Done) => {}
+        Ok(db::ApplicationChange::NotFound) => return Err(Status::NotFound),
+        Ok(db::ApplicationChange::IllegalTransition) => return Err(Status::Conflict),
+        Err(e) => {
             eprintln!(
-                "Error occured while trying to send an email to the applicant: {}",
+                "DB error occured while trying to change application status: {}",
                 e
             );
             return Err(Status::InternalServerError);
         }
-        Ok(())
     }
+
+    if status.set_by_applicant() {
+        return Ok(());
+    }
+
+    match db::get_applicant(conn, applicant_id).await {
+        Ok(Some(applicant)) => {
+            if let Err(e) = send_email_to_applicant(applicant, status) {
+                eprintln!(
+                    "Error occured while trying to send an email to the applicant: {}",
+                    e
+                );
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("DB error occured while trying to get applicant: {}", e),
+    }
+    Ok(())
+}
+
+/// Endpoint for a professor to make an offer on an application.
+#[post("/professor/application/accept?<applicant_id>&<professor_id>")]
+pub async fn accept_application(
+    conn: DbConn,
+    applicant_id: i32,
+    professor_id: i32,
+    principal: Principal,
+) -> Result<(), Status> {
+    if !principal.can(Action::ApplicationDecide, Resource::Professor(professor_id)) {
+        return Err(Status::Forbidden);
+    }
+
+    change_application_status(&conn, applicant_id, professor_id, ApplicationStatus::Offer).await
 }
 
+/// Endpoint for a professor to reject an application.
 #[post("/professor/application/deny?<applicant_id>&<professor_id>")]
 pub async fn deny_application(
     conn: DbConn,
     applicant_id: i32,
     professor_id: i32,
-    admin_or_professor: AdminOrProfessor,
+    principal: Principal,
 ) -> Result<(), Status> {
-    if !admin_or_professor.can_access_prof(professor_id) {
+    if !principal.can(Action::ApplicationDecide, Resource::Professor(professor_id)) {
         return Err(Status::Forbidden);
     }
 
-    if let Err(e) = db::deny_applicant_application(&conn, applicant_id, professor_id).await {
-        eprintln!("Error while denying an applicant's application: {}", e);
-        Err(Status::InternalServerError)
+    change_application_status(
+        &conn,
+        applicant_id,
+        professor_id,
+        ApplicationStatus::Rejected,
+    )
+    .await
+}
+
+/// Endpoint for moving an application to a new status. Applicants submit their drafts,
+/// withdraw, and accept or decline offers, while professors decide on everything else.
+/// Moves the current status does not allow are refused with a 409.
+#[put("/application/status?<applicant_id>&<professor_id>&<status>")]
+pub async fn set_application_status(
+    conn: DbConn,
+    applicant_id: i32,
+    professor_id: i32,
+    status: String,
+    principal: Principal,
+) -> Result<(), Status> {
+    let status = ApplicationStatus::parse(&status).ok_or(Status::BadRequest)?;
+
+    let permitted = if status.set_by_applicant() {
+        principal.can(Action::ApplicationSubmit, Resource::Applicant(applicant_id))
     } else {
-        let applicant = match db::get_applicant(&conn, applicant_id).await {
-            Ok(v) => match v {
-                Some(v) => v,
-                None => return Err(Status::NotFound),
-            },
+        principal.can(Action::ApplicationDecide, Resource::Professor(professor_id))
+    };
+    if !permitted {
+        return Err(Status::Forbidden);
+    }
+
+    if status == ApplicationStatus::Submitted {
+        match db::applicant_unverified(&conn, applicant_id).await {
+            Ok(false) => {}
+            Ok(true) => return Err(Status::Forbidden),
             Err(e) => {
-                eprintln!("Error while denying an applicant's application: {}", e);
+                eprintln!(
+                    "DB error occured while trying to check applicant verification: {}",
+                    e
+                );
                 return Err(Status::InternalServerError);
             }
-        };
-
-        if let Err(e) = send_email_to_applicant(applicant, ApplicationStatus::Denied) {
-            eprintln!(
-                "Error occured while trying to send an email to the applicant: {}",
-                e
-            );
-            return Err(Status::InternalServerError);
         }
-        Ok(())
     }
+
+    change_application_status(&conn, applicant_id, professor_id, status).await
 }
 
 /// Endpoint for creating a new applicant.
@@ -330,8 +415,12 @@ pub async fn deny_application(
 async fn create_applicant(
     conn: DbConn,
     applicant: Json<NewApplicant>,
-    _admin: Administrator,
+    principal: Principal,
 ) -> Result<Json<IdPayload>, Status> {
+    if !principal.can(Action::ApplicantManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
     match db::create_applicant(&conn, applicant.into_inner()).await {
         Ok(id) => Ok(Json(IdPayload { id })),
         Err(e) => {
@@ -347,9 +436,9 @@ async fn edit_applicant(
     conn: DbConn,
     app_id: i32,
     applicant: Json<ApplicantEdit>,
-    admin_or_applicant: AdminOrApplicant,
+    principal: Principal,
 ) -> Result<(), Status> {
-    if !admin_or_applicant.can_access_applicant(app_id) {
+    if !principal.can(Action::ApplicantEdit, Resource::Applicant(app_id)) {
         return Err(Status::Forbidden);
     }
 
@@ -367,9 +456,9 @@ async fn edit_professor(
     conn: DbConn,
     prof_id: i32,
     professor: Json<ProfessorEdit>,
-    admin_or_professor: AdminOrProfessor,
+    principal: Principal,
 ) -> Result<(), Status> {
-    if !admin_or_professor.can_access_prof(prof_id) {
+    if !principal.can(Action::ProfessorEdit, Resource::Professor(prof_id)) {
         return Err(Status::Forbidden);
     }
 
@@ -387,8 +476,12 @@ async fn edit_professor(
 async fn get_applicant(
     conn: DbConn,
     id: i32,
-    _logged_in: LoggedIn,
+    principal: Principal,
 ) -> Result<Json<Applicant>, Status> {
+    if !principal.can(Action::ApplicantRead, Resource::Applicant(id)) {
+        return Err(Status::Forbidden);
+    }
+
     match db::get_applicant(&conn, id).await {
         Ok(applicant) => match applicant {
             Some(applicant) => Ok(Json(applicant)),
@@ -405,8 +498,12 @@ async fn get_applicant(
 #[get("/applicants")]
 async fn get_applicants(
     conn: DbConn,
-    _logged_in: LoggedIn,
+    principal: Principal,
 ) -> Result<Json<Vec<Applicant>>, Status> {
+    if !principal.can(Action::ApplicantRead, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
     match db::get_applicants(&conn).await {
         Ok(applicants) => Ok(Json(applicants)),
         Err(e) => {
@@ -418,7 +515,11 @@ async fn get_applicants(
 
 /// Endpoint for deleting an applicant.
 #[delete("/applicant?<id>")]
-async fn delete_applicant(conn: DbConn, id: i32, _admin: Administrator) -> Status {
+async fn delete_applicant(conn: DbConn, id: i32, principal: Principal) -> Status {
+    if !principal.can(Action::ApplicantManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
     match db::delete_applicant(&conn, id).await {
         Ok(_) => Status::Ok,
         Err(e) => {
@@ -428,19 +529,39 @@ async fn delete_applicant(conn: DbConn, id: i32, _admin: Administrator) -> Statu
     }
 }
 
-/// Endpoint for adding an application to an applicant.
-#[post("/applicant/applications?<applicant_id>&<prof_id>")]
+/// Endpoint for adding an application to an applicant, which is submitted to the
+/// professor straight away unless it is a draft.
+#[post("/applicant/applications?<applicant_id>&<prof_id>&<draft>")]
 async fn add_application_to_applicant(
     conn: DbConn,
     applicant_id: i32,
     prof_id: i32,
-    admin_or_applicant: AdminOrApplicant,
+    draft: Option<bool>,
+    principal: Principal,
 ) -> Status {
-    if !admin_or_applicant.can_access_applicant(applicant_id) {
+    if !principal.can(Action::ApplicationSubmit, Resource::Applicant(applicant_id)) {
         return Status::Forbidden;
     }
 
-    match db::add_application_to_applicant(&conn, applicant_id, prof_id).await {
+    let status = match draft {
+        Some(true) => ApplicationStatus::Draft,
+        _ => ApplicationStatus::Submitted,
+    };
+
+    match db::applicant_unverified(&conn, applicant_id).await {
+        Ok(false) => {}
+        Ok(true) if status == ApplicationStatus::Draft => {}
+        Ok(true) => return Status::Forbidden,
+        Err(e) => {
+            eprintln!(
+                "DB error occured while trying to check applicant verification: {}",
+                e
+            );
+            return Status::InternalServerError;
+        }
+    }
+
+    match db::add_application_to_applicant(&conn, applicant_id, prof_id, status).await {
         Ok(_) => Status::Ok,
         Err(e) => {
             eprintln!(
@@ -462,9 +583,9 @@ async fn upload_applicant_cv(
     conn: DbConn,
     applicant_id: i32,
     file: Data<'_>,
-    admin_or_applicant: AdminOrApplicant,
+    principal: Principal,
 ) -> Result<(), Status> {
-    if !admin_or_applicant.can_access_applicant(applicant_id) {
+    if !principal.can(Action::ApplicantEdit, Resource::Applicant(applicant_id)) {
         return Err(Status::Forbidden);
     }
 
@@ -503,9 +624,9 @@ async fn upload_applicant_diploma(
     conn: DbConn,
     applicant_id: i32,
     file: Data<'_>,
-    admin_or_applicant: AdminOrApplicant,
+    principal: Principal,
 ) -> Result<(), Status> {
-    if !admin_or_applicant.can_access_applicant(applicant_id) {
+    if !principal.can(Action::ApplicantEdit, Resource::Applicant(applicant_id)) {
         return Err(Status::Forbidden);
     }
 
@@ -544,9 +665,9 @@ async fn upload_applicant_grade_audit(
     conn: DbConn,
     applicant_id: i32,
     file: Data<'_>,
-    admin_or_applicant: AdminOrApplicant,
+    principal: Principal,
 ) -> Result<(), Status> {
-    if !admin_or_applicant.can_access_applicant(applicant_id) {
+    if !principal.can(Action::ApplicantEdit, Resource::Applicant(applicant_id)) {
         return Err(Status::Forbidden);
     }
 
@@ -580,7 +701,15 @@ async fn upload_applicant_grade_audit(
 }
 
 #[get("/applicant/files/cv?<applicant_id>")]
-async fn get_applicant_cv(conn: DbConn, applicant_id: i32) -> Result<Vec<u8>, Status> {
+async fn get_applicant_cv(
+    conn: DbConn,
+    applicant_id: i32,
+    principal: Principal,
+) -> Result<Vec<u8>, Status> {
+    if !principal.can(Action::ApplicantRead, Resource::Applicant(applicant_id)) {
+        return Err(Status::Forbidden);
+    }
+
     let applicant = match db::get_applicant(&conn, applicant_id).await {
         Ok(v) => match v {
             Some(v) => v,
@@ -601,7 +730,15 @@ async fn get_applicant_cv(conn: DbConn, applicant_id: i32) -> Result<Vec<u8>, St
 }
 
 #[get("/applicant/files/diploma?<applicant_id>")]
-async fn get_applicant_diploma(conn: DbConn, applicant_id: i32) -> Result<Vec<u8>, Status> {
+async fn get_applicant_diploma(
+    conn: DbConn,
+    applicant_id: i32,
+    principal: Principal,
+) -> Result<Vec<u8>, Status> {
+    if !principal.can(Action::ApplicantRead, Resource::Applicant(applicant_id)) {
+        return Err(Status::Forbidden);
+    }
+
     let applicant = match db::get_applicant(&conn, applicant_id).await {
         Ok(v) => match v {
             Some(v) => v,
@@ -622,7 +759,15 @@ async fn get_applicant_diploma(conn: DbConn, applicant_id: i32) -> Result<Vec<u8
 }
 
 #[get("/applicant/files/grade-audit?<applicant_id>")]
-async fn get_applicant_grade_audit(conn: DbConn, applicant_id: i32) -> Result<Vec<u8>, Status> {
+async fn get_applicant_grade_audit(
+    conn: DbConn,
+    applicant_id: i32,
+    principal: Principal,
+) -> Result<Vec<u8>, Status> {
+    if !principal.can(Action::ApplicantRead, Resource::Applicant(applicant_id)) {
+        return Err(Status::Forbidden);
+    }
+
     let applicant = match db::get_applicant(&conn, applicant_id).await {
         Ok(v) => match v {
             Some(v) => v,
@@ -647,9 +792,9 @@ async fn get_applicant_grade_audit(conn: DbConn, applicant_id: i32) -> Result<Ve
 async fn get_profs_applicant_applied_to(
     conn: DbConn,
     applicant_id: i32,
-    admin_or_applicant: AdminOrApplicant,
+    principal: Principal,
 ) -> Result<Json<Vec<Professor>>, Status> {
-    if !admin_or_applicant.can_access_applicant(applicant_id) {
+    if !principal.can(Action::ApplicationRead, Resource::Applicant(applicant_id)) {
         return Err(Status::Forbidden);
     }
 
@@ -671,9 +816,9 @@ async fn remove_application_from_applicant(
     conn: DbConn,
     applicant_id: i32,
     prof_id: i32,
-    admin_or_applicant: AdminOrApplicant,
+    principal: Principal,
 ) -> Status {
-    if !admin_or_applicant.can_access_applicant(applicant_id) {
+    if !principal.can(Action::ApplicationSubmit, Resource::Applicant(applicant_id)) {
         return Status::Forbidden;
     }
 
@@ -709,26 +854,19 @@ fn create_session_token() -> String {
 #[derive(Serialize, Clone, Copy, Debug)]
 pub struct SessionTypeResponse {
     session_type: Option<SessionType>,
+    /// When the session expires unless it is used again.
+    expires_at: Option<DateTime<Utc>>,
+    /// The id of the administrator impersonating the account, if any.
+    impersonator: Option<i32>,
 }
 
 #[get("/login")]
-pub async fn get_login_type(
-    session_tokens: &State<SessionTokenState>,
-    session_token: Option<SessionTokenHeader>,
-) -> Json<SessionTypeResponse> {
-    let session_type = match session_token {
-        Some(session_token) => {
-            let session_tokens = session_tokens.lock().await;
-
-            match session_tokens.get(&session_token.session_token) {
-                Some((session_type, _)) => Some(*session_type),
-                None => None,
-            }
-        }
-        None => None,
-    };
-
-    Json(SessionTypeResponse { session_type })
+pub async fn get_login_type(session: Option<ActiveSession>) -> Json<SessionTypeResponse> {
+    Json(SessionTypeResponse {
+        session_type: session.map(|session| session.session_type),
+        expires_at: session.map(|session| session.expires_at),
+        impersonator: session.and_then(|session| session.impersonator),
+    })
 }
 
 #[derive(Deserialize, Serialize)]
@@ -736,314 +874,4274 @@ pub struct LoginResponse {
     session_token: String,
 }
 
+/// Response to a correct password for an account with two-factor authentication, to be
+/// exchanged for a session along with a TOTP code.
+#[derive(Deserialize, Serialize)]
+pub struct TotpChallenge {
+    pre_auth_token: String,
+}
+
+/// Response to a login when cookie sessions are enabled, where the session token is only
+/// in the session cookie. The CSRF token is also in the `csrf_token` cookie.
+#[derive(Deserialize, Serialize)]
+pub struct CookieLoginResponse {
+    csrf_token: String,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResult {
+    Session(LoginResponse),
+    Cookie(CookieLoginResponse),
+    TotpRequired(TotpChallenge),
+}
+
+/// Hands the token of a new session to the client, in cookies if cookie sessions are
+/// enabled.
+fn session_login_result(
+    session_token: String,
+    cookies: &CookieJar<'_>,
+    cookie_config: &SessionCookieConfig,
+) -> LoginResult {
+    if cookie_config.enabled {
+        LoginResult::Cookie(CookieLoginResponse {
+            csrf_token: cookie_config.add_session_cookies(cookies, &session_token),
+        })
+    } else {
+        LoginResult::Session(LoginResponse { session_token })
+    }
+}
+
+/// Starts a new session for an account, returning its token.
+async fn start_session(
+    conn: &DbConn,
+    session_type: SessionType,
+    client: &ClientInfo,
+    session_store: &SessionStoreState,
+    session_config: &SessionConfig,
+    stateless_tokens: &StatelessTokens,
+) -> anyhow::Result<String> {
+    let lifetime = session_config.lifetimes.for_session_type(session_type);
+
+    if stateless_tokens.is_enabled() {
+        stateless_tokens
+            .issue(
+                conn,
+                session_type,
+                Duration::seconds(lifetime.absolute_lifetime as i64),
+            )
+            .await
+    } else {
+        let token = create_session_token();
+        let mut session = Session::new(session_type, lifetime);
+        session.ip = client.ip.map(|ip| ip.to_string());
+        session.user_agent = client.user_agent.clone();
+
+        session_store.insert(&hash_token(&token), session).await?;
+        Ok(token)
+    }
+}
+
+/// Issues a pre-auth token if the account has confirmed two-factor authentication.
+async fn start_totp_challenge(
+    conn: &DbConn,
+    session_type: SessionType,
+    totp_config: &TotpConfig,
+) -> anyhow::Result<Option<TotpChallenge>> {
+    match db::get_totp_enrollment(conn, session_type).await? {
+        Some(enrollment) if enrollment.confirmed_at.is_some() => {}
+        _ => return Ok(None),
+    }
+
+    let token = create_session_token();
+    let expiry = Utc::now() + Duration::seconds(totp_config.pre_auth_lifetime as i64);
+    db::create_one_time_token(
+        conn,
+        hash_token(&token),
+        db::TOKEN_TOTP_LOGIN,
+        session_type,
+        expiry,
+    )
+    .await?;
+
+    Ok(Some(TotpChallenge {
+        pre_auth_token: token,
+    }))
+}
+
+/// Checks whether a username or client IP is currently locked out of logging in.
+async fn is_locked_out(conn: &DbConn, kind: &'static str, value: String) -> QueryResult<bool> {
+    let lockout = db::get_login_lockout(conn, kind, value).await?;
+
+    Ok(lockout
+        .and_then(|lockout| lockout.locked_until)
+        .is_some_and(|locked_until| locked_until > Utc::now()))
+}
+
+/// Counts a failed login against a username or client IP, locking it out once it has
+/// failed too many times.
+async fn record_login_failure(
+    conn: &DbConn,
+    config: &LoginThrottleConfig,
+    kind: &'static str,
+    value: String,
+) -> QueryResult<()> {
+    let config = config.clone();
+
+    db::update_login_lockout(conn, kind, value, move |previous| {
+        let now = Utc::now();
+        let failures =
+            config.next_failure_count(Some((previous.failures, previous.last_failure_at)), now);
+
+        (
+            failures,
+            now,
+            config
+                .lockout_duration(kind, failures)
+                .map(|lockout| now + lockout),
+        )
+    })
+    .await
+}
+
+/// Clears the failed logins of a username once it has been given a session.
+async fn clear_username_lockout(conn: &DbConn, username: String) {
+    if let Err(e) = db::delete_login_lockout(conn, LOCKOUT_USERNAME.to_string(), username).await {
+        eprintln!("DB error occured while trying to clear lockout: {}", e);
+    }
+}
+
+/// Counts a request for an email against the address or username it was requested for
+/// and the client IP, returning whether either has already requested too many. Requests
+/// count whether or not there is such an account, so that lockouts reveal nothing.
+async fn throttle_email_request(
+    conn: &DbConn,
+    config: &LoginThrottleConfig,
+    recipient: &str,
+    client: &ClientInfo,
+) -> QueryResult<bool> {
+    let mut throttle_keys = vec![(LOCKOUT_EMAIL, recipient.trim().to_lowercase())];
+    if let Some(client_ip) = client.lockout_ip {
+        throttle_keys.push((LOCKOUT_EMAIL_IP, client_ip.to_string()));
+    }
+
+    for (kind, value) in throttle_keys.iter() {
+        if is_locked_out(conn, kind, value.clone()).await? {
+            return Ok(true);
+        }
+    }
+
+    for (kind, value) in throttle_keys {
+        record_login_failure(conn, config, kind, value).await?;
+    }
+    Ok(false)
+}
+
 #[post("/login", data = "<login_data>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn login(
     conn: DbConn,
     login_data: Json<Login>,
-    session_tokens: &State<SessionTokenState>,
-) -> Result<Json<LoginResponse>, Status> {
+    client: ClientInfo,
+    session_store: &State<SessionStoreState>,
+    session_config: &State<SessionConfig>,
+    stateless_tokens: &State<StatelessTokens>,
+    throttle_config: &State<LoginThrottleConfig>,
+    totp_config: &State<TotpConfig>,
+    password_policy: &State<PasswordPolicy>,
+    ldap: &State<LdapDirectory>,
+    cookies: &CookieJar<'_>,
+    cookie_config: &State<SessionCookieConfig>,
+) -> Result<Json<LoginResult>, Status> {
+    let mut throttle_keys = vec![(LOCKOUT_USERNAME, login_data.username.clone())];
+    if let Some(client_ip) = client.lockout_ip {
+        throttle_keys.push((LOCKOUT_IP, client_ip.to_string()));
+    }
+
+    for (kind, value) in throttle_keys.iter() {
+        match is_locked_out(&conn, kind, value.clone()).await {
+            Ok(true) => return Err(Status::TooManyRequests),
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("DB error occured while trying to check lockout: {}", e);
+                return Err(Status::InternalServerError);
+            }
+        }
+    }
+
     match validate_login(
         &conn,
         login_data.username.clone(),
         login_data.password.clone(),
+        password_policy.bcrypt_cost(),
+        ldap,
     )
     .await
     {
         Ok(session_type) => {
-            let token = create_session_token();
-            let mut session_tokens = session_tokens.lock().await;
-            let expiry = Local::now() + Duration::days(30);
-
-            session_tokens.insert(token.clone(), (session_type, expiry));
-            Ok(Json(LoginResponse {
-                session_token: token,
-            }))
-        }
-        Err(e) => match e {
-            _ => Err(Status::Forbidden),
-        },
-    }
-}
+            // The username stays locked out of guessing TOTP codes until a session is
+            // actually given.
+            match start_totp_challenge(&conn, session_type, totp_config).await {
+                Ok(Some(challenge)) => return Ok(Json(LoginResult::TotpRequired(challenge))),
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Error occured while trying to start TOTP login: {}", e);
+                    return Err(Status::InternalServerError);
+                }
+            }
 
-#[post("/login/admin", data = "<login_data>")]
-pub async fn create_admin_login(
-    conn: DbConn,
-    login_data: Json<Login>,
-    administrator: Option<Administrator>,
-) -> Status {
-    match administrator {
-        Some(_) => match db::create_admin_account(&conn, login_data.into_inner()).await {
-            Ok(_) => Status::Ok,
-            Err(e) => {
-                eprintln!(
-                    "DB error occured while trying to create admin account: {}",
-                    e
-                );
-                Status::InternalServerError
+            match start_session(
+                &conn,
+                session_type,
+                &client,
+                session_store,
+                session_config,
+                stateless_tokens,
+            )
+            .await
+            {
+                Ok(token) => {
+                    clear_username_lockout(&conn, login_data.username.clone()).await;
+                    Ok(Json(session_login_result(token, cookies, cookie_config)))
+                }
+                Err(e) => {
+                    eprintln!("Error occured while trying to create session: {}", e);
+                    Err(Status::InternalServerError)
+                }
             }
-        },
-        None => match db::admin_exists(&conn).await {
-            Ok(result) => {
-                if !result {
-                    match db::create_admin_account(&conn, login_data.into_inner()).await {
-                        Ok(_) => Status::Ok,
-                        Err(e) => {
-                            eprintln!(
-                                "DB error occured while trying to create admin account: {}",
-                                e
-                            );
-                            Status::InternalServerError
-                        }
-                    }
-                } else {
-                    Status::Forbidden
+        }
+        Err(LoginError::CredentialError) => {
+            for (kind, value) in throttle_keys {
+                if let Err(e) = record_login_failure(&conn, throttle_config, kind, value).await {
+                    eprintln!(
+                        "DB error occured while trying to record login failure: {}",
+                        e
+                    );
                 }
             }
-            Err(_) => Status::Forbidden,
-        },
+            Err(Status::Forbidden)
+        }
+        Err(LoginError::DatabaseError) => Err(Status::InternalServerError),
+        Err(LoginError::DirectoryError) => Err(Status::ServiceUnavailable),
     }
 }
 
-#[post("/login/applicant?<applicant_id>", data = "<login_data>")]
-pub async fn create_applicant_login(
+/// Endpoint that starts a single sign-on login, redirecting to the identity provider.
+/// Too many unfinished logins make it unavailable until some time out.
+#[get("/login/oidc")]
+pub async fn start_oidc_login(
     conn: DbConn,
-    login_data: Json<Login>,
-    applicant_id: i32,
-    _administrator: Administrator,
-) -> Status {
-    match db::create_applicant_account(&conn, applicant_id, login_data.into_inner()).await {
-        Ok(_) => Status::Ok,
-        Err(e) => {
+    oidc: &State<Oidc>,
+    cookies: &CookieJar<'_>,
+    cookie_config: &State<SessionCookieConfig>,
+) -> Result<Redirect, Status> {
+    if !oidc.is_enabled() {
+        return Err(Status::NotFound);
+    }
+
+    match oidc.start_login(&conn, cookies, cookie_config).await {
+        Ok(url) => Ok(Redirect::to(url.to_string())),
+        Err(OidcError::TooManyLogins) => Err(Status::ServiceUnavailable),
+        Err(OidcError::Database(e)) => {
             eprintln!(
-                "DB error occured while trying to create admin account: {}",
+                "DB error occured while trying to start single sign-on: {}",
                 e
             );
-            Status::InternalServerError
+            Err(Status::InternalServerError)
+        }
+        Err(e) => {
+            eprintln!("Error occured while trying to start single sign-on: {}", e);
+            Err(Status::BadGateway)
         }
     }
 }
 
-#[post("/login/professor?<professor_id>", data = "<login_data>")]
-pub async fn create_professor_login(
-    conn: DbConn,
-    login_data: Json<Login>,
-    professor_id: i32,
-    _administrator: Administrator,
-) -> Status {
-    match db::create_professor_account(&conn, professor_id, login_data.into_inner()).await {
-        Ok(_) => Status::Ok,
+/// What the identity provider sent back to the `oidc.redirect_url` page.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OidcCallback {
+    code: String,
+    state: String,
+}
+
+/// Finds the professor an identity provider account signs in as, linking or creating a
+/// professor login if the config allows it.
+async fn oidc_professor(
+    conn: &DbConn,
+    config: &OidcConfig,
+    identity: OidcIdentity,
+    bcrypt_cost: u32,
+) -> Result<Option<ID>, Status> {
+    let result: QueryResult<Option<ID>> = try {
+        match db::get_professor_by_oidc_subject(conn, identity.subject.clone()).await? {
+            Some(professor_id) => Some(professor_id),
+            None => match identity.email.clone().filter(|_| config.match_email) {
+                Some(email) => {
+                    db::link_professor_oidc_subject(conn, email, identity.subject.clone()).await?
+                }
+                None => None,
+            },
+        }
+    };
+
+    match result {
+        Ok(Some(professor_id)) => return Ok(Some(professor_id)),
+        Ok(None) => {}
         Err(e) => {
             eprintln!(
-                "DB error occured while trying to create admin account: {}",
+                "DB error occured while trying to find professor login: {}",
                 e
             );
-            Status::InternalServerError
+            return Err(Status::InternalServerError);
         }
     }
-}
 
-#[derive(Serialize)]
-pub struct AdminExistsResult {
-    result: bool,
-}
+    if !config.provision {
+        return Ok(None);
+    }
 
-#[get("/admin-exists")]
-pub async fn get_admin_exists(conn: DbConn) -> Result<Json<AdminExistsResult>, Status> {
-    match db::admin_exists(&conn).await {
-        Ok(result) => Ok(Json(AdminExistsResult { result })),
-        Err(e) => {
-            eprintln!("DB error occured while trying to get admin exists: {}", e);
+    let username = identity
+        .preferred_username
+        .or_else(|| identity.email.clone())
+        .unwrap_or_else(|| identity.subject.clone());
+    let professor = NewProfessor {
+        name: identity.name.unwrap_or_else(|| username.clone()),
+        email: identity.email,
+    };
+    // Provisioned professors sign in through the identity provider, so their password
+    // is random until they reset it.
+    let login_data = Login {
+        username,
+        password: create_session_token(),
+    };
+
+    match db::provision_oidc_professor(conn, professor, login_data, identity.subject, bcrypt_cost)
+        .await
+    {
+        Ok(professor_id) => Ok(Some(professor_id)),
+        Err(AccountCreationError::UsernameTaken) => Err(Status::Conflict),
+        Err(e) => {
+            eprintln!("Error occured while trying to provision professor: {}", e);
             Err(Status::InternalServerError)
         }
     }
 }
 
-/// Declaration of REST request routes.
-pub fn routes() -> Vec<Route> {
-    routes![
-        create_research_field,
-        get_research_field,
-        get_research_fields,
-        delete_research_field,
-        create_professor,
-        get_professor,
-        edit_professor,
-        delete_professor,
-        add_researched_field_to_professor,
-        get_fields_professor_researches,
-        remove_researched_field_from_professor,
-        create_applicant,
-        get_applicant,
-        get_applicants,
-        edit_applicant,
-        delete_applicant,
-        add_application_to_applicant,
-        get_profs_applicant_applied_to,
-        remove_application_from_applicant,
-        upload_applicant_cv,
-        upload_applicant_diploma,
-        upload_applicant_grade_audit,
-        get_applicant_cv,
-        get_applicant_diploma,
-        get_applicant_grade_audit,
-        get_applicants_for_professor_with_status,
-        get_professors,
-        login,
-        create_admin_login,
-        create_applicant_login,
-        create_professor_login,
-        get_admin_exists,
-        get_login_type,
-        accept_application,
-        deny_application,
-    ]
+/// Endpoint that finishes a single sign-on login with the code and state the identity
+/// provider sent back, starting a professor session. It has to be sent with the cookie
+/// set when the login was started. Two-factor authentication is left to the identity
+/// provider.
+#[post("/login/oidc/callback", data = "<callback>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn finish_oidc_login(
+    conn: DbConn,
+    callback: Json<OidcCallback>,
+    client: ClientInfo,
+    oidc: &State<Oidc>,
+    session_store: &State<SessionStoreState>,
+    session_config: &State<SessionConfig>,
+    stateless_tokens: &State<StatelessTokens>,
+    password_policy: &State<PasswordPolicy>,
+    cookies: &CookieJar<'_>,
+    cookie_config: &State<SessionCookieConfig>,
+) -> Result<Json<LoginResult>, Status> {
+    if !oidc.is_enabled() {
+        return Err(Status::NotFound);
+    }
+
+    let callback = callback.into_inner();
+    let identity = match oidc
+        .finish_login(&conn, cookies, callback.code, callback.state)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(OidcError::UnknownLogin) => return Err(Status::BadRequest),
+        Err(OidcError::Database(e)) => {
+            eprintln!(
+                "DB error occured while trying to finish single sign-on: {}",
+                e
+            );
+            return Err(Status::InternalServerError);
+        }
+        Err(e @ OidcError::InvalidToken(_)) => {
+            eprintln!("Rejected single sign-on login: {}", e);
+            return Err(Status::Forbidden);
+        }
+        Err(e) => {
+            eprintln!("Error occured while trying to finish single sign-on: {}", e);
+            return Err(Status::BadGateway);
+        }
+    };
+
+    let professor_id = oidc_professor(
+        &conn,
+        oidc.config(),
+        identity,
+        password_policy.bcrypt_cost(),
+    )
+    .await?
+    .ok_or(Status::Forbidden)?;
+
+    match db::account_active(&conn, SessionType::Professor(professor_id)).await {
+        Ok(true) => {}
+        Ok(false) => return Err(Status::Forbidden),
+        Err(e) => {
+            eprintln!("DB error occured while trying to check account: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    match start_session(
+        &conn,
+        SessionType::Professor(professor_id),
+        &client,
+        session_store,
+        session_config,
+        stateless_tokens,
+    )
+    .await
+    {
+        Ok(token) => Ok(Json(session_login_result(token, cookies, cookie_config))),
+        Err(e) => {
+            eprintln!("Error occured while trying to create session: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
 }
 
-/// REST endpoint tests.
-#[cfg(test)]
-mod test {
-    use std::env::set_var;
+/// Endpoint for an administrator to list failed login records and lockouts.
+#[get("/login/lockouts")]
+pub async fn get_login_lockouts(
+    conn: DbConn,
+    principal: Principal,
+) -> Result<Json<Vec<LoginLockout>>, Status> {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
 
-    use crate::{
-        models::{NewResearchField, ResearchField},
-        rest::{IdPayload, Login, LoginResponse},
-        rocket,
+    match db::get_login_lockouts(&conn).await {
+        Ok(lockouts) => Ok(Json(lockouts)),
+        Err(e) => {
+            eprintln!("DB error occured while trying to get lockouts: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Endpoint for an administrator to clear the lockout of a username or client IP.
+#[delete("/login/lockout?<kind>&<value>")]
+pub async fn clear_login_lockout(
+    conn: DbConn,
+    kind: String,
+    value: String,
+    principal: Principal,
+) -> Status {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
+    match db::delete_login_lockout(&conn, kind, value).await {
+        Ok(_) => Status::Ok,
+        Err(e) => {
+            eprintln!("DB error occured while trying to clear lockout: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+fn default_api_key_expires_in_days() -> i64 {
+    90
+}
+
+#[derive(Deserialize)]
+pub struct ApiKeyRequest {
+    name: String,
+    /// Names of the actions the key may take, such as `application:read`.
+    scopes: Vec<String>,
+    #[serde(default = "default_api_key_expires_in_days")]
+    expires_in_days: i64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ApiKeyResponse {
+    id: i32,
+    /// The key itself, which is not stored and cannot be shown again.
+    key: String,
+}
+
+/// Endpoint for an administrator to create an API key for an integration. Keys may not
+/// manage accounts, so they cannot create other keys.
+#[post("/api-keys", data = "<request>")]
+pub async fn create_api_key(
+    conn: DbConn,
+    request: Json<ApiKeyRequest>,
+    principal: Principal,
+) -> Result<Json<ApiKeyResponse>, Status> {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
+    let request = request.into_inner();
+    if request.expires_in_days <= 0 {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let mut scopes = Vec::new();
+    for scope in &request.scopes {
+        match Action::from_name(scope) {
+            Some(Action::AccountManage) | None => return Err(Status::UnprocessableEntity),
+            Some(action) => scopes.push(action.name().to_string()),
+        }
+    }
+
+    let key = create_session_token();
+    let new_key = NewApiKey {
+        name: request.name,
+        key_hash: hash_token(&key),
+        scopes,
+        created_by: principal.administrator_id(),
+        expires_at: Utc::now() + Duration::days(request.expires_in_days),
     };
-    use diesel::RunQueryDsl;
-    use rocket::{
-        http::{Header, Status},
-        local::asynchronous::{Client, LocalResponse},
-        serde::DeserializeOwned,
+
+    match db::create_api_key(&conn, new_key).await {
+        Ok(id) => Ok(Json(ApiKeyResponse { id, key })),
+        Err(e) => {
+            eprintln!("DB error occured while trying to create API key: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Endpoint for an administrator to list API keys and when they were last used.
+#[get("/api-keys")]
+pub async fn get_api_keys(conn: DbConn, principal: Principal) -> Result<Json<Vec<ApiKey>>, Status> {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
+    match db::get_api_keys(&conn).await {
+        Ok(keys) => Ok(Json(keys)),
+        Err(e) => {
+            eprintln!("DB error occured while trying to get API keys: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Endpoint for an administrator to revoke an API key.
+#[delete("/api-key?<id>")]
+pub async fn revoke_api_key(conn: DbConn, id: i32, principal: Principal) -> Status {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
+    match db::revoke_api_key(&conn, id).await {
+        Ok(true) => Status::Ok,
+        Ok(false) => Status::NotFound,
+        Err(e) => {
+            eprintln!("DB error occured while trying to revoke API key: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Ends every session of an account, both in the session store and stateless tokens.
+async fn revoke_account_sessions(
+    conn: &DbConn,
+    session_store: &SessionStoreState,
+    stateless_tokens: &StatelessTokens,
+    account: SessionType,
+) -> anyhow::Result<()> {
+    session_store.revoke_all(account).await?;
+    stateless_tokens.revoke_all(conn, account).await
+}
+
+#[derive(Deserialize)]
+pub struct TotpLogin {
+    pre_auth_token: String,
+    /// A current TOTP code, or one of the account's unused recovery codes.
+    code: String,
+}
+
+/// Checks a TOTP code or recovery code for an account with confirmed two-factor
+/// authentication, using it up so that it cannot be replayed.
+async fn use_totp_code(conn: &DbConn, account: SessionType, code: &str) -> anyhow::Result<bool> {
+    let enrollment = match db::get_totp_enrollment(conn, account).await? {
+        Some(enrollment) if enrollment.confirmed_at.is_some() => enrollment,
+        _ => return Ok(false),
+    };
+    let secret = totp::decode_secret(&enrollment.secret)
+        .ok_or_else(|| anyhow::anyhow!("TOTP secret of {:?} is not base32", account))?;
+
+    match totp::verify(&secret, code, Utc::now().timestamp()) {
+        Some(step) => Ok(db::use_totp_step(conn, account, step).await?),
+        None => {
+            let recovery_code = code.trim().to_lowercase();
+            Ok(db::use_totp_recovery_code(conn, account, hash_token(&recovery_code)).await?)
+        }
+    }
+}
+
+/// Endpoint for finishing a two-factor login, exchanging a pre-auth token and a TOTP code
+/// for a session. A pre-auth token can only be tried once.
+#[post("/login/totp", data = "<totp_login>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn login_totp(
+    conn: DbConn,
+    totp_login: Json<TotpLogin>,
+    client: ClientInfo,
+    session_store: &State<SessionStoreState>,
+    session_config: &State<SessionConfig>,
+    stateless_tokens: &State<StatelessTokens>,
+    throttle_config: &State<LoginThrottleConfig>,
+    cookies: &CookieJar<'_>,
+    cookie_config: &State<SessionCookieConfig>,
+) -> Result<Json<LoginResult>, Status> {
+    let totp_login = totp_login.into_inner();
+
+    let account = match db::use_one_time_token(
+        &conn,
+        hash_token(&totp_login.pre_auth_token),
+        db::TOKEN_TOTP_LOGIN,
+    )
+    .await
+    {
+        Ok(Some(account)) => account,
+        Ok(None) => return Err(Status::Forbidden),
+        Err(e) => {
+            eprintln!("DB error occured while trying to use pre-auth token: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    // Wrong codes count against the username, like wrong passwords. Directory accounts
+    // without a local login only count against the client IP.
+    let username = match db::get_account_username(&conn, account).await {
+        Ok(username) => username,
+        Err(e) => {
+            eprintln!("DB error occured while trying to get username: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+    let mut throttle_keys = Vec::new();
+    if let Some(username) = username.clone() {
+        throttle_keys.push((LOCKOUT_USERNAME, username));
+    }
+    if let Some(client_ip) = client.lockout_ip {
+        throttle_keys.push((LOCKOUT_IP, client_ip.to_string()));
+    }
+
+    for (kind, value) in throttle_keys.iter() {
+        match is_locked_out(&conn, kind, value.clone()).await {
+            Ok(true) => return Err(Status::TooManyRequests),
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("DB error occured while trying to check lockout: {}", e);
+                return Err(Status::InternalServerError);
+            }
+        }
+    }
+
+    match use_totp_code(&conn, account, &totp_login.code).await {
+        Ok(true) => {}
+        Ok(false) => {
+            for (kind, value) in throttle_keys {
+                if let Err(e) = record_login_failure(&conn, throttle_config, kind, value).await {
+                    eprintln!(
+                        "DB error occured while trying to record login failure: {}",
+                        e
+                    );
+                }
+            }
+            return Err(Status::Forbidden);
+        }
+        Err(e) => {
+            eprintln!("Error occured while trying to check TOTP code: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    // The account may have been suspended since the pre-auth token was given.
+    match db::account_active(&conn, account).await {
+        Ok(true) => {}
+        Ok(false) => return Err(Status::Forbidden),
+        Err(e) => {
+            eprintln!("DB error occured while trying to check account: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    match start_session(
+        &conn,
+        account,
+        &client,
+        session_store,
+        session_config,
+        stateless_tokens,
+    )
+    .await
+    {
+        Ok(token) => {
+            if let Some(username) = username {
+                clear_username_lockout(&conn, username).await;
+            }
+            Ok(Json(session_login_result(token, cookies, cookie_config)))
+        }
+        Err(e) => {
+            eprintln!("Error occured while trying to create session: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TotpEnrollmentResponse {
+    /// The shared secret in base32, for entering into an authenticator by hand.
+    secret: String,
+    provisioning_uri: String,
+    recovery_codes: Vec<String>,
+}
+
+/// Endpoint for starting two-factor enrollment of the logged in account. The enrollment
+/// only takes effect once confirmed with a code, and replaces any unconfirmed one.
+#[post("/login/totp/enroll")]
+pub async fn enroll_totp(
+    conn: DbConn,
+    session: ActiveSession,
+    totp_config: &State<TotpConfig>,
+) -> Result<Json<TotpEnrollmentResponse>, Status> {
+    let account = session.session_type;
+    if session.impersonator.is_some() || !totp_config.can_enroll(account) {
+        return Err(Status::Forbidden);
+    }
+
+    match db::get_totp_enrollment(&conn, account).await {
+        Ok(Some(enrollment)) if enrollment.confirmed_at.is_some() => return Err(Status::Conflict),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!(
+                "DB error occured while trying to get TOTP enrollment: {}",
+                e
+            );
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    let username = match db::get_account_username(&conn, account).await {
+        Ok(Some(username)) => username,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            eprintln!("DB error occured while trying to get username: {}", e);
+            return Err(Status::InternalServerError);
+        }
     };
 
-    use super::DbConn;
+    let secret = totp::generate_secret();
+    let recovery_codes = totp::generate_recovery_codes();
+
+    if let Err(e) = db::replace_totp_enrollment(
+        &conn,
+        account,
+        totp::encode_secret(&secret),
+        recovery_codes.iter().map(|code| hash_token(code)).collect(),
+    )
+    .await
+    {
+        eprintln!(
+            "DB error occured while trying to save TOTP enrollment: {}",
+            e
+        );
+        return Err(Status::InternalServerError);
+    }
+
+    Ok(Json(TotpEnrollmentResponse {
+        secret: totp::encode_secret(&secret),
+        provisioning_uri: totp::provisioning_uri(&totp_config.issuer, &username, &secret),
+        recovery_codes,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct TotpCode {
+    code: String,
+}
+
+/// Endpoint for confirming a pending two-factor enrollment with a code from the
+/// authenticator, after which logins need a code.
+#[post("/login/totp/confirm", data = "<code>")]
+pub async fn confirm_totp(conn: DbConn, code: Json<TotpCode>, session: ActiveSession) -> Status {
+    let account = session.session_type;
+    if session.impersonator.is_some() {
+        return Status::Forbidden;
+    }
+
+    let enrollment = match db::get_totp_enrollment(&conn, account).await {
+        Ok(Some(enrollment)) if enrollment.confirmed_at.is_none() => enrollment,
+        Ok(Some(_)) => return Status::Conflict,
+        Ok(None) => return Status::NotFound,
+        Err(e) => {
+            eprintln!(
+                "DB error occured while trying to get TOTP enrollment: {}",
+                e
+            );
+            return Status::InternalServerError;
+        }
+    };
+
+    let step = match totp::decode_secret(&enrollment.secret)
+        .and_then(|secret| totp::verify(&secret, &code.code, Utc::now().timestamp()))
+    {
+        Some(step) => step,
+        None => return Status::Forbidden,
+    };
+
+    match db::confirm_totp_enrollment(&conn, account, step).await {
+        Ok(true) => Status::Ok,
+        Ok(false) => Status::Conflict,
+        Err(e) => {
+            eprintln!(
+                "DB error occured while trying to confirm TOTP enrollment: {}",
+                e
+            );
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for turning off two-factor authentication for the logged in account, given
+/// a current code or recovery code.
+#[delete("/login/totp", data = "<code>")]
+pub async fn disable_totp(conn: DbConn, code: Json<TotpCode>, session: ActiveSession) -> Status {
+    let account = session.session_type;
+    if session.impersonator.is_some() {
+        return Status::Forbidden;
+    }
+
+    match use_totp_code(&conn, account, &code.code).await {
+        Ok(true) => {}
+        Ok(false) => return Status::Forbidden,
+        Err(e) => {
+            eprintln!("Error occured while trying to check TOTP code: {}", e);
+            return Status::InternalServerError;
+        }
+    }
+
+    match db::delete_totp_enrollment(&conn, account).await {
+        Ok(_) => Status::Ok,
+        Err(e) => {
+            eprintln!(
+                "DB error occured while trying to delete TOTP enrollment: {}",
+                e
+            );
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for ending the session of the provided session token, removing the session
+/// cookies if there are any. Stateless tokens cannot be revoked one at a time, so logging
+/// out of one ends every stateless token of the account.
+#[post("/logout")]
+pub async fn logout(
+    conn: DbConn,
+    session_store: &State<SessionStoreState>,
+    stateless_tokens: &State<StatelessTokens>,
+    session_token: SessionToken,
+    session: Option<ActiveSession>,
+    cookies: &CookieJar<'_>,
+    cookie_config: &State<SessionCookieConfig>,
+) -> Status {
+    cookie_config.remove_session_cookies(cookies);
+
+    if let Some(ActiveSession {
+        session_type,
+        impersonator: Some(administrator),
+        ..
+    }) = session
+    {
+        if let Err(e) = db::record_audit_event(
+            &conn,
+            administrator,
+            db::AUDIT_IMPERSONATION_END,
+            session_type,
+        )
+        .await
+        {
+            eprintln!("DB error occured while trying to record audit event: {}", e);
+        }
+    }
+
+    let result = match session {
+        Some(session) if session.stateless => {
+            stateless_tokens
+                .revoke_all(&conn, session.session_type)
+                .await
+        }
+        _ => {
+            session_store
+                .revoke(&hash_token(&session_token.session_token))
+                .await
+        }
+    };
+
+    match result {
+        Ok(_) => Status::Ok,
+        Err(e) => {
+            eprintln!("Error occured while trying to revoke session: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for ending every session of the logged in user, including the current one.
+/// An administrator impersonating the user cannot end the user's own sessions.
+#[post("/logout/all")]
+pub async fn logout_all(
+    conn: DbConn,
+    session_store: &State<SessionStoreState>,
+    stateless_tokens: &State<StatelessTokens>,
+    session: ActiveSession,
+    cookies: &CookieJar<'_>,
+    cookie_config: &State<SessionCookieConfig>,
+) -> Status {
+    if session.impersonator.is_some() {
+        return Status::Forbidden;
+    }
+
+    cookie_config.remove_session_cookies(cookies);
+    match revoke_account_sessions(&conn, session_store, stateless_tokens, session.session_type)
+        .await
+    {
+        Ok(_) => Status::Ok,
+        Err(e) => {
+            eprintln!("Error occured while trying to revoke sessions: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for an administrator to end every session of an applicant, a professor or
+/// an administrator.
+#[post("/sessions/revoke?<applicant_id>&<professor_id>&<administrator_id>")]
+pub async fn revoke_user_sessions(
+    conn: DbConn,
+    session_store: &State<SessionStoreState>,
+    stateless_tokens: &State<StatelessTokens>,
+    applicant_id: Option<i32>,
+    professor_id: Option<i32>,
+    administrator_id: Option<i32>,
+    principal: Principal,
+) -> Status {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
+    let session_type = match (applicant_id, professor_id, administrator_id) {
+        (Some(applicant_id), None, None) => SessionType::Applicant(applicant_id),
+        (None, Some(professor_id), None) => SessionType::Professor(professor_id),
+        (None, None, Some(administrator_id)) => SessionType::Administrator(administrator_id),
+        _ => return Status::BadRequest,
+    };
+
+    match revoke_account_sessions(&conn, session_store, stateless_tokens, session_type).await {
+        Ok(_) => Status::Ok,
+        Err(e) => {
+            eprintln!("Error occured while trying to revoke sessions: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Gets the applicant or professor account picked by query parameters, along with the
+/// resource that managing it needs permission for.
+fn user_account(
+    applicant_id: Option<i32>,
+    professor_id: Option<i32>,
+) -> Option<(SessionType, Resource)> {
+    match (applicant_id, professor_id) {
+        (Some(id), None) => Some((SessionType::Applicant(id), Resource::Applicant(id))),
+        (None, Some(id)) => Some((SessionType::Professor(id), Resource::Professor(id))),
+        _ => None,
+    }
+}
+
+/// A session in the session store, as shown to its owner or an administrator.
+#[derive(Deserialize, Serialize)]
+pub struct SessionSummary {
+    /// Identifies the session for revoking it.
+    id: String,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    /// When the session expires unless it is used again.
+    expires_at: DateTime<Utc>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    /// The administrator impersonating the account with this session, if any.
+    impersonator: Option<i32>,
+    /// Whether this is the session the request was made with.
+    current: bool,
+}
+
+/// Lists the active sessions of an account, most recently used first. Stateless tokens
+/// are not kept anywhere, so they are never listed. Impersonation sessions are only
+/// listed when `include_impersonation` is set.
+async fn list_account_sessions(
+    session_store: &SessionStoreState,
+    account: SessionType,
+    current_token_hash: Option<String>,
+    include_impersonation: bool,
+) -> Result<Json<Vec<SessionSummary>>, Status> {
+    let mut sessions = match session_store.list(account).await {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("Error occured while trying to list sessions: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+    sessions.retain(|(_, session)| include_impersonation || session.impersonator.is_none());
+    sessions.sort_by_key(|(_, session)| std::cmp::Reverse(session.last_seen_at));
+
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|(token_hash, session)| SessionSummary {
+                current: current_token_hash.as_ref() == Some(&token_hash),
+                id: token_hash,
+                created_at: session.created_at,
+                last_seen_at: session.last_seen_at,
+                expires_at: session.idle_expires_at,
+                ip: session.ip,
+                user_agent: session.user_agent,
+                impersonator: session.impersonator,
+            })
+            .collect(),
+    ))
+}
+
+/// Ends one session of an account, if the account has a session with that id. Impersonation
+/// sessions are only ended when `include_impersonation` is set.
+async fn revoke_account_session(
+    session_store: &SessionStoreState,
+    account: SessionType,
+    id: String,
+    include_impersonation: bool,
+) -> Status {
+    let result: anyhow::Result<bool> = try {
+        let sessions = session_store.list(account).await?;
+        if sessions.iter().any(|(token_hash, session)| {
+            *token_hash == id && (include_impersonation || session.impersonator.is_none())
+        }) {
+            session_store.revoke(&id).await?;
+            true
+        } else {
+            false
+        }
+    };
+
+    match result {
+        Ok(true) => Status::Ok,
+        Ok(false) => Status::NotFound,
+        Err(e) => {
+            eprintln!("Error occured while trying to revoke session: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for listing where the logged in user is logged in. Administrators
+/// impersonating the user are left out, unless the request is made by one of them.
+#[get("/sessions")]
+pub async fn get_sessions(
+    session_store: &State<SessionStoreState>,
+    session_token: SessionToken,
+    session: ActiveSession,
+) -> Result<Json<Vec<SessionSummary>>, Status> {
+    list_account_sessions(
+        session_store,
+        session.session_type,
+        Some(hash_token(&session_token.session_token)),
+        session.impersonator.is_some(),
+    )
+    .await
+}
+
+/// Endpoint for ending one of the logged in user's sessions. An administrator
+/// impersonating the user cannot end the user's sessions, and the user cannot end the
+/// administrator's.
+#[delete("/session?<id>")]
+pub async fn revoke_session(
+    id: String,
+    session_store: &State<SessionStoreState>,
+    session: ActiveSession,
+) -> Status {
+    if session.impersonator.is_some() {
+        return Status::Forbidden;
+    }
+
+    revoke_account_session(session_store, session.session_type, id, false).await
+}
+
+/// Endpoint for an administrator to list the sessions of an applicant or professor.
+#[get("/sessions/user?<applicant_id>&<professor_id>")]
+pub async fn get_user_sessions(
+    applicant_id: Option<i32>,
+    professor_id: Option<i32>,
+    session_store: &State<SessionStoreState>,
+    principal: Principal,
+) -> Result<Json<Vec<SessionSummary>>, Status> {
+    let (account, resource) = user_account(applicant_id, professor_id).ok_or(Status::BadRequest)?;
+    if !principal.can(Action::AccountManage, resource) {
+        return Err(Status::Forbidden);
+    }
+
+    list_account_sessions(session_store, account, None, true).await
+}
+
+/// Endpoint for an administrator to end one session of an applicant or professor.
+#[delete("/session/user?<id>&<applicant_id>&<professor_id>")]
+pub async fn revoke_user_session(
+    id: String,
+    applicant_id: Option<i32>,
+    professor_id: Option<i32>,
+    session_store: &State<SessionStoreState>,
+    principal: Principal,
+) -> Status {
+    let (account, resource) = match user_account(applicant_id, professor_id) {
+        Some(account) => account,
+        None => return Status::BadRequest,
+    };
+    if !principal.can(Action::AccountManage, resource) {
+        return Status::Forbidden;
+    }
+
+    revoke_account_session(session_store, account, id, true).await
+}
+
+/// Endpoint for an administrator to suspend the login of an applicant or professor,
+/// ending their sessions. Their data is kept, and they can be reinstated later.
+/// Professors who only log in through LDAP have no login here to suspend, so they are
+/// not found, and have to be disabled in the directory instead.
+#[post("/user/suspend?<applicant_id>&<professor_id>")]
+pub async fn suspend_user(
+    conn: DbConn,
+    applicant_id: Option<i32>,
+    professor_id: Option<i32>,
+    session_store: &State<SessionStoreState>,
+    stateless_tokens: &State<StatelessTokens>,
+    principal: Principal,
+) -> Status {
+    let (account, resource) = match user_account(applicant_id, professor_id) {
+        Some(account) => account,
+        None => return Status::BadRequest,
+    };
+    if !principal.can(Action::AccountManage, resource) {
+        return Status::Forbidden;
+    }
+
+    match db::set_login_active(&conn, account, false).await {
+        Ok(true) => {}
+        Ok(false) => return Status::NotFound,
+        Err(e) => {
+            eprintln!("DB error occured while trying to suspend login: {}", e);
+            return Status::InternalServerError;
+        }
+    }
+
+    match revoke_account_sessions(&conn, session_store, stateless_tokens, account).await {
+        Ok(_) => Status::Ok,
+        Err(e) => {
+            eprintln!("Error occured while trying to revoke sessions: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for an administrator to let a suspended applicant or professor log in again.
+#[post("/user/reinstate?<applicant_id>&<professor_id>")]
+pub async fn reinstate_user(
+    conn: DbConn,
+    applicant_id: Option<i32>,
+    professor_id: Option<i32>,
+    principal: Principal,
+) -> Status {
+    let (account, resource) = match user_account(applicant_id, professor_id) {
+        Some(account) => account,
+        None => return Status::BadRequest,
+    };
+    if !principal.can(Action::AccountManage, resource) {
+        return Status::Forbidden;
+    }
+
+    match db::set_login_active(&conn, account, true).await {
+        Ok(true) => Status::Ok,
+        Ok(false) => Status::NotFound,
+        Err(e) => {
+            eprintln!("DB error occured while trying to reinstate login: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Most user directory entries returned at once when no limit is given.
+const DEFAULT_USER_DIRECTORY_LIMIT: i64 = 50;
+/// Most logins returned by a user directory search, whatever limit is given.
+const MAX_USER_DIRECTORY_LIMIT: i64 = 200;
+
+/// Endpoint for an administrator to search the logins of every kind of account by
+/// username, or by the name of the applicant or professor they belong to.
+#[get("/users?<query>&<limit>")]
+pub async fn search_users(
+    conn: DbConn,
+    query: String,
+    limit: Option<i64>,
+    principal: Principal,
+) -> Result<Json<Vec<db::DirectoryEntry>>, Status> {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
+    let limit = limit
+        .unwrap_or(DEFAULT_USER_DIRECTORY_LIMIT)
+        .clamp(1, MAX_USER_DIRECTORY_LIMIT);
+    match db::search_accounts(&conn, query, limit).await {
+        Ok(entries) => Ok(Json(entries)),
+        Err(e) => {
+            eprintln!("DB error occured while trying to search users: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Endpoint for an administrator to log in as an applicant or professor, to see what
+/// they see. The session is always kept in the session store so that it can be ended on
+/// its own, lasts at most `sessions.impersonation_lifetime` seconds, cannot change the
+/// account's credentials, and is recorded in the audit log.
+#[post("/impersonate?<applicant_id>&<professor_id>")]
+pub async fn impersonate(
+    conn: DbConn,
+    applicant_id: Option<i32>,
+    professor_id: Option<i32>,
+    client: ClientInfo,
+    session_store: &State<SessionStoreState>,
+    session_config: &State<SessionConfig>,
+    principal: Principal,
+) -> Result<Json<LoginResponse>, Status> {
+    let (account, resource) = user_account(applicant_id, professor_id).ok_or(Status::BadRequest)?;
+    let administrator = match principal.administrator_id() {
+        Some(administrator) if principal.can(Action::AccountManage, resource) => administrator,
+        _ => return Err(Status::Forbidden),
+    };
+
+    match db::get_account_username(&conn, account).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            eprintln!("DB error occured while trying to get username: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    let lifetime = session_config.lifetimes.for_session_type(account);
+    let mut session = Session::new(
+        account,
+        SessionLifetime {
+            idle_timeout: lifetime
+                .idle_timeout
+                .min(session_config.impersonation_lifetime),
+            absolute_lifetime: session_config.impersonation_lifetime,
+        },
+    );
+    session.ip = client.ip.map(|ip| ip.to_string());
+    session.user_agent = client.user_agent;
+    session.impersonator = Some(administrator);
+
+    if let Err(e) =
+        db::record_audit_event(&conn, administrator, db::AUDIT_IMPERSONATION_START, account).await
+    {
+        eprintln!("DB error occured while trying to record audit event: {}", e);
+        return Err(Status::InternalServerError);
+    }
+
+    let token = create_session_token();
+    match session_store.insert(&hash_token(&token), session).await {
+        Ok(_) => Ok(Json(LoginResponse {
+            session_token: token,
+        })),
+        Err(e) => {
+            eprintln!("Error occured while trying to create session: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Most audit log events returned at once when no limit is given.
+const DEFAULT_AUDIT_LOG_LIMIT: i64 = 100;
+/// Most audit log events returned at once, whatever limit is given.
+const MAX_AUDIT_LOG_LIMIT: i64 = 200;
+
+/// Endpoint for an administrator to review recent audit log events, newest first.
+#[get("/audit-log?<limit>")]
+pub async fn get_audit_log(
+    conn: DbConn,
+    limit: Option<i64>,
+    principal: Principal,
+) -> Result<Json<Vec<AuditEvent>>, Status> {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
+    let limit = limit
+        .unwrap_or(DEFAULT_AUDIT_LOG_LIMIT)
+        .clamp(1, MAX_AUDIT_LOG_LIMIT);
+    match db::get_audit_log(&conn, limit).await {
+        Ok(events) => Ok(Json(events)),
+        Err(e) => {
+            eprintln!("DB error occured while trying to get audit log: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// How long an emailed password reset token can be used for.
+const PASSWORD_RESET_TOKEN_LIFETIME_HOURS: i64 = 1;
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequest {
+    username: String,
+}
+
+/// Endpoint for requesting a password reset email. The response is the same whether or
+/// not the account exists, so that it cannot be used to discover usernames.
+#[post("/login/password-reset", data = "<request>")]
+pub async fn request_password_reset(
+    conn: DbConn,
+    request: Json<PasswordResetRequest>,
+    client: ClientInfo,
+    throttle_config: &State<LoginThrottleConfig>,
+) -> Status {
+    let username = request.into_inner().username;
+    match throttle_email_request(&conn, throttle_config, &username, &client).await {
+        Ok(false) => {}
+        Ok(true) => return Status::TooManyRequests,
+        Err(e) => {
+            eprintln!("DB error occured while trying to throttle email: {}", e);
+            return Status::InternalServerError;
+        }
+    }
+
+    let account = match db::get_account_by_username(&conn, username).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return Status::Ok,
+        Err(e) => {
+            eprintln!("DB error occured while trying to find account: {}", e);
+            return Status::InternalServerError;
+        }
+    };
+
+    let (name, email) = match db::get_account_contact(&conn, account).await {
+        Ok(Some(contact)) => contact,
+        Ok(None) => {
+            eprintln!(
+                "Password reset requested for {:?}, which has no email address",
+                account
+            );
+            return Status::Ok;
+        }
+        Err(e) => {
+            eprintln!("DB error occured while trying to get account email: {}", e);
+            return Status::InternalServerError;
+        }
+    };
+
+    let token = create_session_token();
+    let expiry = Utc::now() + Duration::hours(PASSWORD_RESET_TOKEN_LIFETIME_HOURS);
+    if let Err(e) = db::create_one_time_token(
+        &conn,
+        hash_token(&token),
+        db::TOKEN_PASSWORD_RESET,
+        account,
+        expiry,
+    )
+    .await
+    {
+        eprintln!("DB error occured while trying to create reset token: {}", e);
+        return Status::InternalServerError;
+    }
+
+    send_in_background("a password reset email", move || {
+        send_password_reset_email(&name, &email, &token)
+    });
+    Status::Ok
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetConfirm {
+    token: String,
+    password: String,
+}
+
+/// Endpoint for setting a new password with an emailed reset token. Every existing
+/// session of the account is ended.
+#[post("/login/password-reset/confirm", data = "<reset>")]
+pub async fn confirm_password_reset(
+    conn: DbConn,
+    reset: Json<PasswordResetConfirm>,
+    session_store: &State<SessionStoreState>,
+    stateless_tokens: &State<StatelessTokens>,
+    password_policy: &State<PasswordPolicy>,
+) -> Result<Status, PasswordSetError> {
+    let reset = reset.into_inner();
+    let token_hash = hash_token(&reset.token);
+
+    // The token is only used up once the new password is accepted, so that a rejected
+    // password can be corrected without requesting another email.
+    let account =
+        match db::find_one_time_token(&conn, token_hash.clone(), db::TOKEN_PASSWORD_RESET).await {
+            Ok(Some(account)) => account,
+            Ok(None) => return Err(Status::Forbidden.into()),
+            Err(e) => {
+                eprintln!("DB error occured while trying to find reset token: {}", e);
+                return Err(Status::InternalServerError.into());
+            }
+        };
+
+    check_account_password(&conn, password_policy, account, &reset.password).await?;
+
+    match db::use_one_time_token(&conn, token_hash, db::TOKEN_PASSWORD_RESET).await {
+        Ok(Some(used_account)) if used_account == account => {}
+        Ok(_) => return Err(Status::Forbidden.into()),
+        Err(e) => {
+            eprintln!("DB error occured while trying to use reset token: {}", e);
+            return Err(Status::InternalServerError.into());
+        }
+    }
+
+    let bcrypt_cost = password_policy.bcrypt_cost();
+    match db::set_account_password(&conn, account, reset.password, bcrypt_cost).await {
+        Ok(true) => {}
+        Ok(false) => return Err(Status::NotFound.into()),
+        Err(e) => {
+            eprintln!("Error occured while trying to reset password: {}", e);
+            return Err(Status::InternalServerError.into());
+        }
+    }
+
+    match revoke_account_sessions(&conn, session_store, stateless_tokens, account).await {
+        Ok(_) => Ok(Status::Ok),
+        Err(e) => {
+            eprintln!("Error occured while trying to revoke sessions: {}", e);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+/// How long an emailed login link can be used for.
+const LOGIN_LINK_TOKEN_LIFETIME_MINUTES: i64 = 15;
+
+#[derive(Deserialize)]
+pub struct LoginLinkRequest {
+    email: String,
+}
+
+/// Endpoint for an applicant to request a login link by email instead of logging in with
+/// their password. The response is the same whether or not an applicant has the address,
+/// so that it cannot be used to discover email addresses.
+#[post("/login/email-link", data = "<request>")]
+pub async fn request_login_link(
+    conn: DbConn,
+    request: Json<LoginLinkRequest>,
+    client: ClientInfo,
+    throttle_config: &State<LoginThrottleConfig>,
+) -> Status {
+    let email = request.into_inner().email;
+    match throttle_email_request(&conn, throttle_config, &email, &client).await {
+        Ok(false) => {}
+        Ok(true) => return Status::TooManyRequests,
+        Err(e) => {
+            eprintln!("DB error occured while trying to throttle email: {}", e);
+            return Status::InternalServerError;
+        }
+    }
+
+    let (applicant_id, name) = match db::get_applicant_login_by_email(&conn, email.clone()).await {
+        Ok(Some(applicant)) => applicant,
+        Ok(None) => return Status::Ok,
+        Err(e) => {
+            eprintln!("DB error occured while trying to find applicant: {}", e);
+            return Status::InternalServerError;
+        }
+    };
+
+    let token = create_session_token();
+    let expiry = Utc::now() + Duration::minutes(LOGIN_LINK_TOKEN_LIFETIME_MINUTES);
+    if let Err(e) = db::create_one_time_token(
+        &conn,
+        hash_token(&token),
+        db::TOKEN_LOGIN_LINK,
+        SessionType::Applicant(applicant_id),
+        expiry,
+    )
+    .await
+    {
+        eprintln!("DB error occured while trying to create login link: {}", e);
+        return Status::InternalServerError;
+    }
+
+    send_in_background("a login link email", move || {
+        send_login_link_email(&name, email.trim(), &token)
+    });
+    Status::Ok
+}
+
+#[derive(Deserialize)]
+pub struct LoginLinkConfirm {
+    token: String,
+}
+
+/// Endpoint for exchanging an emailed login link's token for an applicant session. The
+/// link only stands in for the password, so applicants with two-factor authentication
+/// still get a TOTP challenge.
+#[post("/login/email-link/confirm", data = "<confirm>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn confirm_login_link(
+    conn: DbConn,
+    confirm: Json<LoginLinkConfirm>,
+    client: ClientInfo,
+    session_store: &State<SessionStoreState>,
+    session_config: &State<SessionConfig>,
+    stateless_tokens: &State<StatelessTokens>,
+    totp_config: &State<TotpConfig>,
+    cookies: &CookieJar<'_>,
+    cookie_config: &State<SessionCookieConfig>,
+) -> Result<Json<LoginResult>, Status> {
+    let token_hash = hash_token(&confirm.into_inner().token);
+
+    let account = match db::use_one_time_token(&conn, token_hash, db::TOKEN_LOGIN_LINK).await {
+        Ok(Some(account @ SessionType::Applicant(_))) => account,
+        Ok(_) => return Err(Status::Forbidden),
+        Err(e) => {
+            eprintln!("DB error occured while trying to use login link: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    match db::account_active(&conn, account).await {
+        Ok(true) => {}
+        Ok(false) => return Err(Status::Forbidden),
+        Err(e) => {
+            eprintln!("DB error occured while trying to check account: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    match start_totp_challenge(&conn, account, totp_config).await {
+        Ok(Some(challenge)) => return Ok(Json(LoginResult::TotpRequired(challenge))),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Error occured while trying to start TOTP login: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    match start_session(
+        &conn,
+        account,
+        &client,
+        session_store,
+        session_config,
+        stateless_tokens,
+    )
+    .await
+    {
+        Ok(token) => Ok(Json(session_login_result(token, cookies, cookie_config))),
+        Err(e) => {
+            eprintln!("Error occured while trying to create session: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PasswordChange {
+    current_password: String,
+    new_password: String,
+}
+
+/// Endpoint for changing the logged in user's password, given their current password.
+#[put("/login/password", data = "<change>")]
+pub async fn change_password(
+    conn: DbConn,
+    change: Json<PasswordChange>,
+    password_policy: &State<PasswordPolicy>,
+    session: ActiveSession,
+) -> Result<Status, PasswordSetError> {
+    if session.impersonator.is_some() {
+        return Err(Status::Forbidden.into());
+    }
+
+    let change = change.into_inner();
+
+    let account = session.session_type;
+    match db::verify_account_password(&conn, account, change.current_password).await {
+        Ok(true) => {}
+        Ok(false) => return Err(Status::Forbidden.into()),
+        Err(e) => {
+            eprintln!("Error occured while trying to change password: {}", e);
+            return Err(Status::InternalServerError.into());
+        }
+    }
+
+    check_account_password(&conn, password_policy, account, &change.new_password).await?;
+
+    let bcrypt_cost = password_policy.bcrypt_cost();
+    match db::set_account_password(&conn, account, change.new_password, bcrypt_cost).await {
+        Ok(true) => Ok(Status::Ok),
+        Ok(false) => Err(Status::NotFound.into()),
+        Err(e) => {
+            eprintln!("Error occured while trying to change password: {}", e);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PasswordSet {
+    password: String,
+}
+
+/// Sets the password of an applicant or professor account and ends its sessions. Accounts
+/// without a login are not found.
+async fn force_set_password(
+    conn: &DbConn,
+    session_store: &SessionStoreState,
+    stateless_tokens: &StatelessTokens,
+    password_policy: &PasswordPolicy,
+    account: SessionType,
+    password: String,
+) -> Result<Status, PasswordSetError> {
+    check_account_password(conn, password_policy, account, &password).await?;
+
+    let bcrypt_cost = password_policy.bcrypt_cost();
+    match db::set_account_password(conn, account, password, bcrypt_cost).await {
+        Ok(true) => {}
+        Ok(false) => return Err(Status::NotFound.into()),
+        Err(e) => {
+            eprintln!("Error occured while trying to set password: {}", e);
+            return Err(Status::InternalServerError.into());
+        }
+    }
+
+    match revoke_account_sessions(conn, session_store, stateless_tokens, account).await {
+        Ok(_) => Ok(Status::Ok),
+        Err(e) => {
+            eprintln!("Error occured while trying to revoke sessions: {}", e);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+/// Endpoint for an administrator to set an applicant's password.
+#[put("/login/applicant/password?<applicant_id>", data = "<password>")]
+pub async fn set_applicant_password(
+    conn: DbConn,
+    applicant_id: i32,
+    password: Json<PasswordSet>,
+    session_store: &State<SessionStoreState>,
+    stateless_tokens: &State<StatelessTokens>,
+    password_policy: &State<PasswordPolicy>,
+    principal: Principal,
+) -> Result<Status, PasswordSetError> {
+    if !principal.can(Action::AccountManage, Resource::Applicant(applicant_id)) {
+        return Err(Status::Forbidden.into());
+    }
+
+    force_set_password(
+        &conn,
+        session_store,
+        stateless_tokens,
+        password_policy,
+        SessionType::Applicant(applicant_id),
+        password.into_inner().password,
+    )
+    .await
+}
+
+/// Endpoint for an administrator to set a professor's password.
+#[put("/login/professor/password?<professor_id>", data = "<password>")]
+pub async fn set_professor_password(
+    conn: DbConn,
+    professor_id: i32,
+    password: Json<PasswordSet>,
+    session_store: &State<SessionStoreState>,
+    stateless_tokens: &State<StatelessTokens>,
+    password_policy: &State<PasswordPolicy>,
+    principal: Principal,
+) -> Result<Status, PasswordSetError> {
+    if !principal.can(Action::AccountManage, Resource::Professor(professor_id)) {
+        return Err(Status::Forbidden.into());
+    }
+
+    force_set_password(
+        &conn,
+        session_store,
+        stateless_tokens,
+        password_policy,
+        SessionType::Professor(professor_id),
+        password.into_inner().password,
+    )
+    .await
+}
+
+/// Maps the result of creating a login to a response status, a username that is
+/// already in use by any kind of account, or an account that already has a login, is a
+/// conflict.
+fn account_creation_status(result: Result<(), AccountCreationError>, account: &str) -> Status {
+    match result {
+        Ok(_) => Status::Ok,
+        Err(AccountCreationError::UsernameTaken | AccountCreationError::LoginExists) => {
+            Status::Conflict
+        }
+        Err(e) => {
+            eprintln!(
+                "Error occured while trying to create {} account: {}",
+                account, e
+            );
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Error response of a request that sets a password, which lists the failed password
+/// policy rules when the password is rejected.
+#[derive(Responder)]
+pub enum PasswordSetError {
+    #[response(status = 422)]
+    Rejected(Json<PolicyViolation>),
+    Failed(Status),
+}
+
+impl From<Status> for PasswordSetError {
+    fn from(status: Status) -> Self {
+        PasswordSetError::Failed(status)
+    }
+}
+
+impl From<PolicyViolation> for PasswordSetError {
+    fn from(violation: PolicyViolation) -> Self {
+        PasswordSetError::Rejected(Json(violation))
+    }
+}
+
+/// Checks a new password for an existing account against the password policy.
+async fn check_account_password(
+    conn: &DbConn,
+    password_policy: &PasswordPolicy,
+    account: SessionType,
+    password: &str,
+) -> Result<(), PasswordSetError> {
+    let username = match db::get_account_username(conn, account).await {
+        Ok(username) => username.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("DB error occured while trying to get username: {}", e);
+            return Err(Status::InternalServerError.into());
+        }
+    };
+
+    Ok(password_policy.check(&username, password)?)
+}
+
+#[post("/login/admin", data = "<login_data>")]
+pub async fn create_admin_login(
+    conn: DbConn,
+    login_data: Json<Login>,
+    password_policy: &State<PasswordPolicy>,
+    principal: Option<Principal>,
+) -> Result<Status, PasswordSetError> {
+    let permitted = match principal {
+        Some(principal) if principal.can(Action::AccountManage, Resource::Any) => true,
+        _ => matches!(db::admin_exists(&conn).await, Ok(false)),
+    };
+    if !permitted {
+        return Err(Status::Forbidden.into());
+    }
+
+    password_policy.check(&login_data.username, &login_data.password)?;
+
+    Ok(account_creation_status(
+        db::create_admin_account(
+            &conn,
+            login_data.into_inner(),
+            password_policy.bcrypt_cost(),
+        )
+        .await,
+        "admin",
+    ))
+}
+
+/// Endpoint for listing every administrator.
+#[get("/admins")]
+pub async fn get_admins(
+    conn: DbConn,
+    principal: Principal,
+) -> Result<Json<Vec<Administrator>>, Status> {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
+    match db::get_admins(&conn).await {
+        Ok(admins) => Ok(Json(admins)),
+        Err(e) => {
+            eprintln!("DB error occured while trying to get admins: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Ends the sessions of an administrator that was disabled or deleted, turning the
+/// outcome into a status.
+async fn admin_change_status(
+    conn: &DbConn,
+    session_store: &SessionStoreState,
+    stateless_tokens: &StatelessTokens,
+    admin_id: i32,
+    change: QueryResult<db::AdminChange>,
+    action: &str,
+) -> Status {
+    match change {
+        Ok(db::AdminChange::Done) => {
+            let account = SessionType::Administrator(admin_id);
+            match revoke_account_sessions(conn, session_store, stateless_tokens, account).await {
+                Ok(_) => Status::Ok,
+                Err(e) => {
+                    eprintln!("Error occured while trying to revoke sessions: {}", e);
+                    Status::InternalServerError
+                }
+            }
+        }
+        Ok(db::AdminChange::NotFound) => Status::NotFound,
+        Ok(db::AdminChange::LastActiveAdmin) => Status::Conflict,
+        Err(e) => {
+            eprintln!("DB error occured while trying to {} admin: {}", action, e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for disabling an administrator, which ends their sessions and the sessions
+/// they are impersonating accounts with. The last active administrator cannot be
+/// disabled.
+#[post("/admin/disable?<id>")]
+pub async fn disable_admin(
+    conn: DbConn,
+    id: i32,
+    session_store: &State<SessionStoreState>,
+    stateless_tokens: &State<StatelessTokens>,
+    principal: Principal,
+) -> Status {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
+    let change = db::set_admin_active(&conn, id, false).await;
+    admin_change_status(
+        &conn,
+        session_store,
+        stateless_tokens,
+        id,
+        change,
+        "disable",
+    )
+    .await
+}
+
+/// Endpoint for letting a disabled administrator log in again.
+#[post("/admin/enable?<id>")]
+pub async fn enable_admin(conn: DbConn, id: i32, principal: Principal) -> Status {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
+    match db::set_admin_active(&conn, id, true).await {
+        Ok(db::AdminChange::Done) => Status::Ok,
+        Ok(_) => Status::NotFound,
+        Err(e) => {
+            eprintln!("DB error occured while trying to enable admin: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for deleting an administrator and ending their sessions. The last active
+/// administrator cannot be deleted.
+#[delete("/admin?<id>")]
+pub async fn delete_admin(
+    conn: DbConn,
+    id: i32,
+    session_store: &State<SessionStoreState>,
+    stateless_tokens: &State<StatelessTokens>,
+    principal: Principal,
+) -> Status {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
+    let change = db::delete_admin(&conn, id).await;
+    admin_change_status(&conn, session_store, stateless_tokens, id, change, "delete").await
+}
+
+#[post("/login/applicant?<applicant_id>", data = "<login_data>")]
+pub async fn create_applicant_login(
+    conn: DbConn,
+    login_data: Json<Login>,
+    applicant_id: i32,
+    password_policy: &State<PasswordPolicy>,
+    principal: Principal,
+) -> Result<Status, PasswordSetError> {
+    if !principal.can(Action::AccountManage, Resource::Applicant(applicant_id)) {
+        return Err(Status::Forbidden.into());
+    }
+
+    password_policy.check(&login_data.username, &login_data.password)?;
+
+    Ok(account_creation_status(
+        db::create_applicant_account(
+            &conn,
+            applicant_id,
+            login_data.into_inner(),
+            password_policy.bcrypt_cost(),
+        )
+        .await,
+        "applicant",
+    ))
+}
+
+#[post("/login/professor?<professor_id>", data = "<login_data>")]
+pub async fn create_professor_login(
+    conn: DbConn,
+    login_data: Json<Login>,
+    professor_id: i32,
+    password_policy: &State<PasswordPolicy>,
+    principal: Principal,
+) -> Result<Status, PasswordSetError> {
+    if !principal.can(Action::AccountManage, Resource::Professor(professor_id)) {
+        return Err(Status::Forbidden.into());
+    }
+
+    password_policy.check(&login_data.username, &login_data.password)?;
+
+    Ok(account_creation_status(
+        db::create_professor_account(
+            &conn,
+            professor_id,
+            login_data.into_inner(),
+            password_policy.bcrypt_cost(),
+        )
+        .await,
+        "professor",
+    ))
+}
+
+/// How long an emailed verification token can be used for.
+const VERIFICATION_TOKEN_LIFETIME_HOURS: i64 = 24;
+
+#[derive(Deserialize)]
+pub struct Registration {
+    #[serde(flatten)]
+    applicant: NewApplicant,
+    username: String,
+    password: String,
+}
+
+/// Emails an applicant a link for verifying their email address.
+async fn send_applicant_verification(
+    conn: &DbConn,
+    applicant_id: i32,
+    name: &str,
+    email: &str,
+) -> QueryResult<()> {
+    let token = create_session_token();
+    let expiry = Utc::now() + Duration::hours(VERIFICATION_TOKEN_LIFETIME_HOURS);
+    db::create_one_time_token(
+        conn,
+        hash_token(&token),
+        db::TOKEN_EMAIL_VERIFICATION,
+        SessionType::Applicant(applicant_id),
+        expiry,
+    )
+    .await?;
+
+    let (name, email) = (name.to_string(), email.to_string());
+    send_in_background("a verification email", move || {
+        send_verification_email(&name, &email, &token)
+    });
+    Ok(())
+}
+
+/// Endpoint for applicants to register themselves. The new account cannot apply until
+/// the emailed verification link is followed. Registrations are throttled like other
+/// requests that send an email.
+#[post("/register", data = "<registration>")]
+async fn register_applicant(
+    conn: DbConn,
+    registration: Json<Registration>,
+    client: ClientInfo,
+    password_policy: &State<PasswordPolicy>,
+    throttle_config: &State<LoginThrottleConfig>,
+) -> Result<Json<IdPayload>, PasswordSetError> {
+    let registration = registration.into_inner();
+    password_policy.check(&registration.username, &registration.password)?;
+
+    match throttle_email_request(
+        &conn,
+        throttle_config,
+        &registration.applicant.email,
+        &client,
+    )
+    .await
+    {
+        Ok(false) => {}
+        Ok(true) => return Err(Status::TooManyRequests.into()),
+        Err(e) => {
+            eprintln!("DB error occured while trying to throttle email: {}", e);
+            return Err(Status::InternalServerError.into());
+        }
+    }
+
+    let name = registration.applicant.name.clone();
+    let email = registration.applicant.email.clone();
+    let login = Login {
+        username: registration.username,
+        password: registration.password,
+    };
+
+    let applicant_id = match db::register_applicant(
+        &conn,
+        registration.applicant,
+        login,
+        password_policy.bcrypt_cost(),
+    )
+    .await
+    {
+        Ok(applicant_id) => applicant_id,
+        Err(AccountCreationError::UsernameTaken) => return Err(Status::Conflict.into()),
+        Err(e) => {
+            eprintln!("Error occured while trying to register applicant: {}", e);
+            return Err(Status::InternalServerError.into());
+        }
+    };
+
+    match send_applicant_verification(&conn, applicant_id, &name, &email).await {
+        Ok(_) => Ok(Json(IdPayload { id: applicant_id })),
+        Err(e) => {
+            eprintln!(
+                "DB error occured while trying to create verification token: {}",
+                e
+            );
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VerificationConfirm {
+    token: String,
+}
+
+/// Endpoint for verifying an applicant's email address with an emailed token.
+#[post("/register/verify", data = "<verification>")]
+pub async fn verify_applicant_email(
+    conn: DbConn,
+    verification: Json<VerificationConfirm>,
+) -> Status {
+    let account = match db::use_one_time_token(
+        &conn,
+        hash_token(&verification.token),
+        db::TOKEN_EMAIL_VERIFICATION,
+    )
+    .await
+    {
+        Ok(Some(account)) => account,
+        Ok(None) => return Status::Forbidden,
+        Err(e) => {
+            eprintln!(
+                "DB error occured while trying to use verification token: {}",
+                e
+            );
+            return Status::InternalServerError;
+        }
+    };
+
+    let applicant_id = match account {
+        SessionType::Applicant(applicant_id) => applicant_id,
+        _ => return Status::Forbidden,
+    };
+
+    match db::verify_applicant(&conn, applicant_id).await {
+        Ok(_) => Status::Ok,
+        Err(e) => {
+            eprintln!("DB error occured while trying to verify applicant: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for sending an unverified applicant a new verification email. The response
+/// is the same whether or not the account exists, so that it cannot be used to discover
+/// usernames.
+#[post("/register/resend-verification", data = "<request>")]
+pub async fn resend_verification(
+    conn: DbConn,
+    request: Json<PasswordResetRequest>,
+    client: ClientInfo,
+    throttle_config: &State<LoginThrottleConfig>,
+) -> Status {
+    let username = request.into_inner().username;
+    match throttle_email_request(&conn, throttle_config, &username, &client).await {
+        Ok(false) => {}
+        Ok(true) => return Status::TooManyRequests,
+        Err(e) => {
+            eprintln!("DB error occured while trying to throttle email: {}", e);
+            return Status::InternalServerError;
+        }
+    }
+
+    let applicant_id = match db::get_account_by_username(&conn, username).await {
+        Ok(Some(SessionType::Applicant(applicant_id))) => applicant_id,
+        Ok(_) => return Status::Ok,
+        Err(e) => {
+            eprintln!("DB error occured while trying to find account: {}", e);
+            return Status::InternalServerError;
+        }
+    };
+
+    let result: QueryResult<()> = try {
+        if db::applicant_unverified(&conn, applicant_id).await? {
+            let account = SessionType::Applicant(applicant_id);
+            if let Some((name, email)) = db::get_account_contact(&conn, account).await? {
+                send_applicant_verification(&conn, applicant_id, &name, &email).await?;
+            }
+        }
+    };
+
+    match result {
+        Ok(_) => Status::Ok,
+        Err(e) => {
+            eprintln!(
+                "DB error occured while trying to resend verification: {}",
+                e
+            );
+            Status::InternalServerError
+        }
+    }
+}
+
+/// How long an emailed invite can be accepted for.
+const INVITE_LIFETIME_DAYS: i64 = 7;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InviteStatus {
+    Pending,
+    Accepted,
+    Expired,
+    Cancelled,
+}
+
+#[derive(Serialize)]
+pub struct InviteSummary {
+    #[serde(flatten)]
+    invite: Invite,
+    status: InviteStatus,
+}
+
+impl From<Invite> for InviteSummary {
+    fn from(invite: Invite) -> Self {
+        let status = if invite.accepted_at.is_some() {
+            InviteStatus::Accepted
+        } else if invite.cancelled_at.is_some() {
+            InviteStatus::Cancelled
+        } else if invite.expires_at <= Utc::now() {
+            InviteStatus::Expired
+        } else {
+            InviteStatus::Pending
+        };
+
+        InviteSummary { invite, status }
+    }
+}
+
+/// Emails an invite's token to the invited account's address.
+async fn send_invite(conn: &DbConn, account: SessionType, token: &str) -> QueryResult<()> {
+    match db::get_account_contact(conn, account).await? {
+        Some((name, email)) => {
+            let token = token.to_string();
+            send_in_background("an invite email", move || {
+                send_invite_email(&name, &email, &token)
+            });
+        }
+        None => eprintln!(
+            "Invite created for {:?}, which has no email address",
+            account
+        ),
+    }
+    Ok(())
+}
+
+/// Endpoint for an administrator to invite an existing applicant or professor to set up
+/// their own login, instead of choosing a password for them.
+#[post("/invites?<applicant_id>&<professor_id>")]
+async fn create_invite(
+    conn: DbConn,
+    applicant_id: Option<i32>,
+    professor_id: Option<i32>,
+    principal: Principal,
+) -> Result<Json<IdPayload>, Status> {
+    let (account, resource) = user_account(applicant_id, professor_id).ok_or(Status::BadRequest)?;
+
+    if !principal.can(Action::AccountManage, resource) {
+        return Err(Status::Forbidden);
+    }
+
+    let result: QueryResult<Result<ID, Status>> = try {
+        if db::get_account_username(&conn, account).await?.is_some() {
+            Err(Status::Conflict)
+        } else if db::get_account_contact(&conn, account).await?.is_none() {
+            Err(Status::UnprocessableEntity)
+        } else {
+            let token = create_session_token();
+            let (kind, _) = account.to_db();
+            let invite = NewInvite {
+                token_hash: hash_token(&token),
+                session_type: kind.to_string(),
+                subject_id: account.subject_id(),
+                created_by: principal.administrator_id(),
+                expires_at: Utc::now() + Duration::days(INVITE_LIFETIME_DAYS),
+            };
+
+            let id = db::create_invite(&conn, invite).await?;
+            send_invite(&conn, account, &token).await?;
+            Ok(id)
+        }
+    };
+
+    match result {
+        Ok(Ok(id)) => Ok(Json(IdPayload { id })),
+        Ok(Err(status)) => Err(status),
+        Err(e) => {
+            eprintln!("DB error occured while trying to create invite: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Endpoint for an administrator to list invites and whether they are pending,
+/// accepted, expired or cancelled.
+#[get("/invites")]
+pub async fn get_invites(
+    conn: DbConn,
+    principal: Principal,
+) -> Result<Json<Vec<InviteSummary>>, Status> {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Err(Status::Forbidden);
+    }
+
+    match db::get_invites(&conn).await {
+        Ok(invites) => Ok(Json(invites.into_iter().map(InviteSummary::from).collect())),
+        Err(e) => {
+            eprintln!("DB error occured while trying to get invites: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Endpoint for an administrator to email a new link for a pending or expired invite.
+/// Earlier links stop working.
+#[post("/invite/resend?<id>")]
+pub async fn resend_invite(conn: DbConn, id: i32, principal: Principal) -> Status {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
+    let token = create_session_token();
+    let expiry = Utc::now() + Duration::days(INVITE_LIFETIME_DAYS);
+    let result: QueryResult<bool> = try {
+        match db::renew_invite(&conn, id, hash_token(&token), expiry).await? {
+            Some(account) => {
+                send_invite(&conn, account, &token).await?;
+                true
+            }
+            None => false,
+        }
+    };
+
+    match result {
+        Ok(true) => Status::Ok,
+        Ok(false) => Status::NotFound,
+        Err(e) => {
+            eprintln!("DB error occured while trying to resend invite: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Endpoint for an administrator to cancel an invite that has not been accepted.
+#[delete("/invite?<id>")]
+pub async fn cancel_invite(conn: DbConn, id: i32, principal: Principal) -> Status {
+    if !principal.can(Action::AccountManage, Resource::Any) {
+        return Status::Forbidden;
+    }
+
+    match db::cancel_invite(&conn, id).await {
+        Ok(true) => Status::Ok,
+        Ok(false) => Status::NotFound,
+        Err(e) => {
+            eprintln!("DB error occured while trying to cancel invite: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InviteAcceptance {
+    token: String,
+    username: String,
+    password: String,
+}
+
+/// Endpoint for an invited professor or applicant to choose their username and password.
+#[post("/invite/accept", data = "<acceptance>")]
+pub async fn accept_invite(
+    conn: DbConn,
+    acceptance: Json<InviteAcceptance>,
+    password_policy: &State<PasswordPolicy>,
+) -> Result<Status, PasswordSetError> {
+    let acceptance = acceptance.into_inner();
+    password_policy.check(&acceptance.username, &acceptance.password)?;
+
+    let login = Login {
+        username: acceptance.username,
+        password: acceptance.password,
+    };
+
+    let hash = hash_token(&acceptance.token);
+    match db::accept_invite(&conn, hash, login, password_policy.bcrypt_cost()).await {
+        Ok(Some(_)) => Ok(Status::Ok),
+        Ok(None) => Err(Status::Forbidden.into()),
+        Err(AccountCreationError::UsernameTaken | AccountCreationError::LoginExists) => {
+            Err(Status::Conflict.into())
+        }
+        Err(e) => {
+            eprintln!("Error occured while trying to accept invite: {}", e);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AdminExistsResult {
+    result: bool,
+}
+
+#[get("/admin-exists")]
+pub async fn get_admin_exists(conn: DbConn) -> Result<Json<AdminExistsResult>, Status> {
+    match db::admin_exists(&conn).await {
+        Ok(result) => Ok(Json(AdminExistsResult { result })),
+        Err(e) => {
+            eprintln!("DB error occured while trying to get admin exists: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Declaration of REST request routes.
+pub fn routes() -> Vec<Route> {
+    routes![
+        create_research_field,
+        get_research_field,
+        get_research_fields,
+        delete_research_field,
+        create_professor,
+        get_professor,
+        edit_professor,
+        delete_professor,
+        add_researched_field_to_professor,
+        get_fields_professor_researches,
+        remove_researched_field_from_professor,
+        create_applicant,
+        get_applicant,
+        get_applicants,
+        edit_applicant,
+        delete_applicant,
+        add_application_to_applicant,
+        get_profs_applicant_applied_to,
+        remove_application_from_applicant,
+        upload_applicant_cv,
+        upload_applicant_diploma,
+        upload_applicant_grade_audit,
+        get_applicant_cv,
+        get_applicant_diploma,
+        get_applicant_grade_audit,
+        get_applicants_for_professor_with_status,
+        get_professors,
+        login,
+        login_totp,
+        start_oidc_login,
+        finish_oidc_login,
+        enroll_totp,
+        confirm_totp,
+        disable_totp,
+        logout,
+        logout_all,
+        revoke_user_sessions,
+        get_sessions,
+        revoke_session,
+        get_user_sessions,
+        revoke_user_session,
+        suspend_user,
+        reinstate_user,
+        search_users,
+        impersonate,
+        get_audit_log,
+        request_password_reset,
+        confirm_password_reset,
+        request_login_link,
+        confirm_login_link,
+        change_password,
+        set_applicant_password,
+        set_professor_password,
+        get_login_lockouts,
+        clear_login_lockout,
+        create_api_key,
+        get_api_keys,
+        revoke_api_key,
+        create_admin_login,
+        get_admins,
+        disable_admin,
+        enable_admin,
+        delete_admin,
+        create_applicant_login,
+        create_professor_login,
+        register_applicant,
+        verify_applicant_email,
+        resend_verification,
+        create_invite,
+        get_invites,
+        resend_invite,
+        cancel_invite,
+        accept_invite,
+        get_admin_exists,
+        get_login_type,
+        accept_application,
+        deny_application,
+        set_application_status,
+    ]
+}
+
+/// REST endpoint tests.
+#[cfg(test)]
+mod test {
+    use std::env::set_var;
+
+    use crate::{
+        db,
+        models::{Administrator, AuditEvent, NewInvite, NewResearchField, ResearchField},
+        password_policy::{PasswordRule, PolicyViolation},
+        request_guards::state::SessionType,
+        rest::{
+            ApiKeyResponse, CookieLoginResponse, IdPayload, Login, LoginResponse, SessionSummary,
+            TotpChallenge, TotpEnrollmentResponse,
+        },
+        rocket, totp,
+    };
+    use diesel::RunQueryDsl;
+    use rocket::{
+        figment::{Figment, Provider},
+        http::{Header, Status},
+        local::asynchronous::{Client, LocalResponse},
+        serde::DeserializeOwned,
+    };
+
+    use super::DbConn;
+
+    // Checks whether the current database is a testing database.
+    fn is_testing_db<T: AsRef<str>>(url: T) -> bool {
+        let lowercase = url.as_ref().to_ascii_lowercase();
+        lowercase.contains("testing") || lowercase.contains("test")
+    }
+
+    // This resource is only accessible during testing, as only test setup functions mount it
+    #[delete("/delete")]
+    async fn reset_database(conn: DbConn) {
+        conn.run(|c| {
+            use crate::schema;
+            use schema::applicants::dsl::*;
+            use schema::professor_research_fields::dsl::*;
+            use schema::professors::dsl::*;
+            use schema::research_fields::dsl::*;
+            use schema::student_applied_to::dsl::*;
+
+            diesel::delete(student_applied_to)
+                .execute(c)
+                .expect("could not delete student_applied_to table");
+            diesel::delete(professor_research_fields)
+                .execute(c)
+                .expect("could not delete professor_research_fields table");
+            diesel::delete(applicants)
+                .execute(c)
+                .expect("could not delete applicants table");
+            diesel::delete(professors)
+                .execute(c)
+                .expect("could not delete professors table");
+            diesel::delete(research_fields)
+                .execute(c)
+                .expect("could not delete research_fields table");
+        })
+        .await;
+    }
+
+    // Configures and connects the dabatase for testing.
+    const DATABASE_URL_KEY: &'static str = "databases.db.url";
+    async fn setup() -> Client {
+        setup_with(Figment::new()).await
+    }
+
+    // Sets up a client with extra config merged over the testing profile.
+    async fn setup_with<T: Provider>(config: T) -> Client {
+        set_var("ROCKET_PROFILE", "testing");
+
+        let rocket = rocket();
+        let profile = rocket.figment().profile().clone();
+        let figment = rocket.figment().clone().merge(config).select(profile);
+        let rocket = rocket.configure(figment);
+        let db_url = rocket
+            .figment()
+            .find_value(DATABASE_URL_KEY)
+            .expect("no database url configured")
+            .into_string()
+            .expect("database url cannot be converted to string");
+
+        if !is_testing_db(&db_url) {
+            panic!(
+                "DB url {} is not a testing database url (must contain test or testing)",
+                db_url
+            );
+        }
+
+        let client = Client::tracked(rocket.mount("/testing-only", routes![reset_database]))
+            .await
+            .expect("valid rocket instance");
+
+        client.delete("/testing-only/delete").dispatch().await;
+
+        client
+    }
+
+    // Creates the administrator the tests act as, unless another test already has, and
+    // returns their login.
+    async fn create_test_admin(client: &Client) -> Login {
+        let login = Login {
+            username: "testing".to_string(),
+            password: "correct horse battery".to_string(),
+        };
+
+        client
+            .post("/rest/login/admin")
+            .json(&login)
+            .dispatch()
+            .await;
+
+        login
+    }
+
+    // Logs in as the administrator the tests act as, returning the session token.
+    async fn admin_session(client: &Client) -> String {
+        let login = create_test_admin(client).await;
+
+        let login_response = client.post("/rest/login").json(&login).dispatch().await;
+        to_json_workaround::<LoginResponse>(login_response)
+            .await
+            .session_token
+    }
+
+    // Registers an applicant with a unique username, which is also their name and the
+    // local part of their email address at example.com, returning their id and login.
+    async fn register_test_applicant(client: &Client, admin_token: &str) -> (i32, Login) {
+        let field_response = client
+            .post("/rest/research-field")
+            .header(Header::new("X-Session-Token", admin_token.to_string()))
+            .json(&NewResearchField {
+                name: "Mathematics".to_string(),
+            })
+            .dispatch()
+            .await;
+        let field_id = to_json_workaround::<IdPayload>(field_response).await.id;
+
+        let login = Login {
+            username: format!(
+                "applicant-{}",
+                chrono::Utc::now()
+                    .timestamp_nanos_opt()
+                    .expect("time is out of range")
+            ),
+            password: "analytical engine".to_string(),
+        };
+        let register_response = client
+            .post("/rest/register")
+            .json(&serde_json::json!({
+                "name": login.username,
+                "desired_field_id": field_id,
+                "phone_number": "555-0100",
+                "email": format!("{}@example.com", login.username),
+                "username": login.username,
+                "password": login.password,
+            }))
+            .dispatch()
+            .await;
+        assert_eq!(register_response.status(), Status::Ok);
+        let applicant_id = to_json_workaround::<IdPayload>(register_response).await.id;
+
+        (applicant_id, login)
+    }
+
+    // The into_json method of the async LocalResponse tends to hangs as of v0.5-rc1 (https://github.com/SergioBenitez/Rocket/issues/1893)
+    async fn to_json_workaround<T: DeserializeOwned>(response: LocalResponse<'_>) -> T {
+        let body = response
+            .into_string()
+            .await
+            .expect("response body could not be converted to string");
+
+        serde_json::from_str(&body).expect("could not deserialize response body into JSON")
+    }
+
+    // Tests the creation of a research field.
+    #[rocket::async_test]
+    async fn create_get_research_field() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let biology = NewResearchField {
+            name: "Biology".to_string(),
+        };
+
+        println!("Sending first create message...");
+        let create_response = client
+            .post("/rest/research-field")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&biology)
+            .dispatch()
+            .await;
+
+        assert_eq!(create_response.status(), Status::Ok);
+        let id = to_json_workaround::<IdPayload>(create_response).await.id;
+
+        let biology_get_response = client
+            .get(format!("/rest/research-field?id={}", id))
+            .header(Header::new("X-Session-Token", session_token))
+            .dispatch()
+            .await;
+
+        assert_eq!(biology_get_response.status(), Status::Ok);
+        assert_eq!(
+            to_json_workaround::<ResearchField>(biology_get_response).await,
+            ResearchField {
+                id,
+                name: biology.name
+            }
+        );
+    }
+
+    // Tests that a session token stops working after logging out.
+    #[rocket::async_test]
+    async fn logout_revokes_session() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let logout_response = client
+            .post("/rest/logout")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(logout_response.status(), Status::Ok);
+
+        let professors_response = client
+            .get("/rest/professors")
+            .header(Header::new("X-Session-Token", session_token))
+            .dispatch()
+            .await;
+        assert_eq!(professors_response.status(), Status::Forbidden);
+    }
+
+    // Tests that self-registered applicants can pick a research field and log in, but
+    // cannot apply until their email address is verified, and that registering and
+    // resending the verification email are throttled.
+    #[rocket::async_test]
+    async fn unverified_applicants_cannot_apply() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let field_response = client
+            .post("/rest/research-field")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&NewResearchField {
+                name: "Physics".to_string(),
+            })
+            .dispatch()
+            .await;
+        let field_id = to_json_workaround::<IdPayload>(field_response).await.id;
+
+        let professor_response = client
+            .post("/rest/professor")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&serde_json::json!({ "name": "Marie" }))
+            .dispatch()
+            .await;
+        let professor_id = to_json_workaround::<IdPayload>(professor_response).await.id;
+
+        let fields_response = client.get("/rest/research-fields").dispatch().await;
+        assert_eq!(fields_response.status(), Status::Ok);
+        let fields = to_json_workaround::<Vec<ResearchField>>(fields_response).await;
+        assert!(fields.iter().any(|field| field.id == field_id));
+
+        let (applicant_id, applicant_login) =
+            register_test_applicant(&client, &session_token).await;
+
+        // Registering counts towards the allowance of three emails per address.
+        for expected in [Status::Conflict, Status::Conflict, Status::TooManyRequests] {
+            let duplicate_response = client
+                .post("/rest/register")
+                .json(&serde_json::json!({
+                    "name": "Ada",
+                    "desired_field_id": field_id,
+                    "phone_number": "555-0100",
+                    "email": format!("{}@example.com", applicant_login.username),
+                    "username": applicant_login.username,
+                    "password": applicant_login.password,
+                }))
+                .dispatch()
+                .await;
+            assert_eq!(duplicate_response.status(), expected);
+        }
+
+        let applicant_response = client
+            .post("/rest/login")
+            .json(&applicant_login)
+            .dispatch()
+            .await;
+        assert_eq!(applicant_response.status(), Status::Ok);
+        let applicant_token = to_json_workaround::<LoginResponse>(applicant_response)
+            .await
+            .session_token;
+
+        let apply_url = format!(
+            "/rest/applicant/applications?applicant_id={}&prof_id={}",
+            applicant_id, professor_id
+        );
+        let apply_response = client
+            .post(apply_url.clone())
+            .header(Header::new("X-Session-Token", applicant_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(apply_response.status(), Status::Forbidden);
+
+        // The emailed token cannot be read back, so a known one is issued next to it.
+        let conn = DbConn::get_one(client.rocket())
+            .await
+            .expect("could not connect to database");
+        let token = format!("verification-{}", applicant_login.username);
+        db::create_one_time_token(
+            &conn,
+            super::hash_token(&token),
+            db::TOKEN_EMAIL_VERIFICATION,
+            SessionType::Applicant(applicant_id),
+            chrono::Utc::now() + chrono::Duration::minutes(1),
+        )
+        .await
+        .expect("could not create verification token");
+
+        let verify_response = client
+            .post("/rest/register/verify")
+            .json(&serde_json::json!({ "token": token }))
+            .dispatch()
+            .await;
+        assert_eq!(verify_response.status(), Status::Ok);
+
+        let verified_apply_response = client
+            .post(apply_url)
+            .header(Header::new("X-Session-Token", applicant_token))
+            .dispatch()
+            .await;
+        assert_eq!(verified_apply_response.status(), Status::Ok);
+
+        for expected in [Status::Ok, Status::Ok, Status::Ok, Status::TooManyRequests] {
+            let resend_response = client
+                .post("/rest/register/resend-verification")
+                .json(&serde_json::json!({ "username": applicant_login.username }))
+                .dispatch()
+                .await;
+            assert_eq!(resend_response.status(), expected);
+        }
+    }
+
+    // Tests that admins can be disabled, enabled and deleted, ending their sessions, and
+    // that the last active admin is kept.
+    #[rocket::async_test]
+    async fn admins_can_be_disabled_and_deleted() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let second_login = Login {
+            username: format!(
+                "admin-{}",
+                chrono::Utc::now()
+                    .timestamp_nanos_opt()
+                    .expect("time is out of range")
+            ),
+            password: "analytical engine".to_string(),
+        };
+        let create_response = client
+            .post("/rest/login/admin")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&second_login)
+            .dispatch()
+            .await;
+        assert_eq!(create_response.status(), Status::Ok);
+
+        let admins_response = client
+            .get("/rest/admins")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        let admins = to_json_workaround::<Vec<Administrator>>(admins_response).await;
+        let second_id = admins
+            .iter()
+            .find(|admin| admin.username == second_login.username && admin.active)
+            .expect("new admin is not listed as active")
+            .id;
+
+        let second_login_response = client
+            .post("/rest/login")
+            .json(&second_login)
+            .dispatch()
+            .await;
+        let second_token = to_json_workaround::<LoginResponse>(second_login_response)
+            .await
+            .session_token;
+
+        let disable_response = client
+            .post(format!("/rest/admin/disable?id={}", second_id))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(disable_response.status(), Status::Ok);
+
+        let revoked_response = client
+            .get("/rest/admins")
+            .header(Header::new("X-Session-Token", second_token))
+            .dispatch()
+            .await;
+        assert_eq!(revoked_response.status(), Status::Forbidden);
+
+        let disabled_login_response = client
+            .post("/rest/login")
+            .json(&second_login)
+            .dispatch()
+            .await;
+        assert_eq!(disabled_login_response.status(), Status::Forbidden);
+
+        let enable_response = client
+            .post(format!("/rest/admin/enable?id={}", second_id))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(enable_response.status(), Status::Ok);
+
+        let enabled_login_response = client
+            .post("/rest/login")
+            .json(&second_login)
+            .dispatch()
+            .await;
+        assert_eq!(enabled_login_response.status(), Status::Ok);
+
+        // Other tests only ever act as the testing admin, so every other admin is
+        // disabled for a moment to leave it as the last active one.
+        let testing_id = admins
+            .iter()
+            .find(|admin| admin.username == "testing")
+            .expect("testing admin is not listed")
+            .id;
+        let other_ids: Vec<i32> = admins
+            .iter()
+            .filter(|admin| admin.active && admin.id != testing_id)
+            .map(|admin| admin.id)
+            .collect();
+        for other_id in other_ids.iter() {
+            let disable_response = client
+                .post(format!("/rest/admin/disable?id={}", other_id))
+                .header(Header::new("X-Session-Token", session_token.clone()))
+                .dispatch()
+                .await;
+            assert_eq!(disable_response.status(), Status::Ok);
+        }
+
+        let last_disable_response = client
+            .post(format!("/rest/admin/disable?id={}", testing_id))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(last_disable_response.status(), Status::Conflict);
+
+        let last_delete_response = client
+            .delete(format!("/rest/admin?id={}", testing_id))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(last_delete_response.status(), Status::Conflict);
+
+        for other_id in other_ids.iter() {
+            let enable_response = client
+                .post(format!("/rest/admin/enable?id={}", other_id))
+                .header(Header::new("X-Session-Token", session_token.clone()))
+                .dispatch()
+                .await;
+            assert_eq!(enable_response.status(), Status::Ok);
+        }
+
+        for expected in [Status::Ok, Status::NotFound] {
+            let delete_response = client
+                .delete(format!("/rest/admin?id={}", second_id))
+                .header(Header::new("X-Session-Token", session_token.clone()))
+                .dispatch()
+                .await;
+            assert_eq!(delete_response.status(), expected);
+        }
+    }
+
+    // Tests that suspended applicants cannot log in or use their sessions, and show up as
+    // suspended in the user directory.
+    #[rocket::async_test]
+    async fn suspended_users_are_locked_out() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let (applicant_id, applicant_login) =
+            register_test_applicant(&client, &session_token).await;
+
+        let applicant_login_response = client
+            .post("/rest/login")
+            .json(&applicant_login)
+            .dispatch()
+            .await;
+        let applicant_token = to_json_workaround::<LoginResponse>(applicant_login_response)
+            .await
+            .session_token;
+
+        let suspend_response = client
+            .post(format!("/rest/user/suspend?applicant_id={}", applicant_id))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(suspend_response.status(), Status::Ok);
+
+        let revoked_response = client
+            .get("/rest/sessions")
+            .header(Header::new("X-Session-Token", applicant_token))
+            .dispatch()
+            .await;
+        assert_eq!(revoked_response.status(), Status::Forbidden);
+
+        let suspended_login_response = client
+            .post("/rest/login")
+            .json(&applicant_login)
+            .dispatch()
+            .await;
+        assert_eq!(suspended_login_response.status(), Status::Forbidden);
+
+        // A limit below one is raised to one rather than finding nothing.
+        let search_response = client
+            .get(format!(
+                "/rest/users?query={}&limit=0",
+                applicant_login.username
+            ))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        let entries = to_json_workaround::<Vec<db::DirectoryEntry>>(search_response).await;
+        assert_eq!(
+            entries,
+            vec![db::DirectoryEntry {
+                account: SessionType::Applicant(applicant_id),
+                username: applicant_login.username.clone(),
+                name: Some(applicant_login.username.clone()),
+                active: false,
+            }]
+        );
+
+        let reinstate_response = client
+            .post(format!(
+                "/rest/user/reinstate?applicant_id={}",
+                applicant_id
+            ))
+            .header(Header::new("X-Session-Token", session_token))
+            .dispatch()
+            .await;
+        assert_eq!(reinstate_response.status(), Status::Ok);
+
+        let reinstated_login_response = client
+            .post("/rest/login")
+            .json(&applicant_login)
+            .dispatch()
+            .await;
+        assert_eq!(reinstated_login_response.status(), Status::Ok);
+    }
+
+    // Tests that applicants can request a login link by email, and that its token starts
+    // a session only once.
+    #[rocket::async_test]
+    async fn login_links_start_one_session() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let (applicant_id, applicant_login) =
+            register_test_applicant(&client, &session_token).await;
+        let email = format!("{}@example.com", applicant_login.username);
+        let unknown_email = format!("nobody-{}", email);
+
+        for requested_email in [email.to_uppercase(), unknown_email.clone()] {
+            let request_response = client
+                .post("/rest/login/email-link")
+                .json(&serde_json::json!({ "email": requested_email }))
+                .dispatch()
+                .await;
+            assert_eq!(request_response.status(), Status::Ok);
+        }
+
+        // Unknown addresses count towards the allowance of three emails as well.
+        for expected in [Status::Ok, Status::Ok, Status::TooManyRequests] {
+            let request_response = client
+                .post("/rest/login/email-link")
+                .json(&serde_json::json!({ "email": unknown_email }))
+                .dispatch()
+                .await;
+            assert_eq!(request_response.status(), expected);
+        }
+
+        // The emailed token cannot be read back, so the link is checked to have been
+        // issued and a known token is issued next to it.
+        let conn = DbConn::get_one(client.rocket())
+            .await
+            .expect("could not connect to database");
+        let issued = conn
+            .run(move |c| {
+                use crate::schema::one_time_tokens::dsl::*;
+                use diesel::{ExpressionMethods, QueryDsl};
+
+                one_time_tokens
+                    .filter(purpose.eq(db::TOKEN_LOGIN_LINK))
+                    .filter(subject_id.eq(applicant_id))
+                    .count()
+                    .get_result::<i64>(c)
+            })
+            .await
+            .expect("could not count login links");
+        assert_eq!(issued, 1);
+
+        let token = format!("login-link-{}", applicant_login.username);
+        db::create_one_time_token(
+            &conn,
+            super::hash_token(&token),
+            db::TOKEN_LOGIN_LINK,
+            SessionType::Applicant(applicant_id),
+            chrono::Utc::now() + chrono::Duration::minutes(1),
+        )
+        .await
+        .expect("could not create login link");
+
+        let confirm_response = client
+            .post("/rest/login/email-link/confirm")
+            .json(&serde_json::json!({ "token": token }))
+            .dispatch()
+            .await;
+        assert_eq!(confirm_response.status(), Status::Ok);
+        let applicant_token = to_json_workaround::<LoginResponse>(confirm_response)
+            .await
+            .session_token;
+
+        let login_type_response = client
+            .get("/rest/login")
+            .header(Header::new("X-Session-Token", applicant_token))
+            .dispatch()
+            .await;
+        let login_type = to_json_workaround::<serde_json::Value>(login_type_response).await;
+        assert_eq!(
+            login_type["session_type"],
+            serde_json::json!({ "Applicant": applicant_id })
+        );
+
+        let reused_response = client
+            .post("/rest/login/email-link/confirm")
+            .json(&serde_json::json!({ "token": token }))
+            .dispatch()
+            .await;
+        assert_eq!(reused_response.status(), Status::Forbidden);
+    }
+
+    // Tests that applications only move along allowed status transitions, and that the old
+    // status names still find them.
+    #[rocket::async_test]
+    async fn application_status_transitions() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let field_response = client
+            .post("/rest/research-field")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&NewResearchField {
+                name: "Botany".to_string(),
+            })
+            .dispatch()
+            .await;
+        let field_id = to_json_workaround::<IdPayload>(field_response).await.id;
+
+        let professor_response = client
+            .post("/rest/professor")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&serde_json::json!({ "name": "Barbara" }))
+            .dispatch()
+            .await;
+        let professor_id = to_json_workaround::<IdPayload>(professor_response).await.id;
+
+        let applicant_response = client
+            .post("/rest/applicant")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&serde_json::json!({
+                "name": "Rosalind",
+                "desired_field_id": field_id,
+                "phone_number": "555-0104",
+                "email": "rosalind@example.com",
+            }))
+            .dispatch()
+            .await;
+        let applicant_id = to_json_workaround::<IdPayload>(applicant_response).await.id;
+
+        let apply_response = client
+            .post(format!(
+                "/rest/applicant/applications?applicant_id={}&prof_id={}&draft=true",
+                applicant_id, professor_id
+            ))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(apply_response.status(), Status::Ok);
+
+        let applicants_with_status = |status: &'static str| {
+            let client = &client;
+            let session_token = session_token.clone();
+            async move {
+                let response = client
+                    .get(format!(
+                        "/rest/professor/applicants?id={}&status={}",
+                        professor_id, status
+                    ))
+                    .header(Header::new("X-Session-Token", session_token))
+                    .dispatch()
+                    .await;
+                assert_eq!(response.status(), Status::Ok);
+                to_json_workaround::<Vec<serde_json::Value>>(response)
+                    .await
+                    .len()
+            }
+        };
+        assert_eq!(applicants_with_status("draft").await, 1);
+        assert_eq!(applicants_with_status("PENDING").await, 0);
+
+        let set_status = |status: &'static str| {
+            let client = &client;
+            let session_token = session_token.clone();
+            async move {
+                client
+                    .put(format!(
+                        "/rest/application/status?applicant_id={}&professor_id={}&status={}",
+                        applicant_id, professor_id, status
+                    ))
+                    .header(Header::new("X-Session-Token", session_token))
+                    .dispatch()
+                    .await
+                    .status()
+            }
+        };
+        assert_eq!(set_status("submitted").await, Status::Ok);
+        assert_eq!(applicants_with_status("PENDING").await, 1);
+        assert_eq!(set_status("interview").await, Status::Ok);
+        assert_eq!(set_status("under_review").await, Status::Conflict);
+        assert_eq!(set_status("approved").await, Status::BadRequest);
+
+        let accept_response = client
+            .post(format!(
+                "/rest/professor/application/accept?applicant_id={}&professor_id={}",
+                applicant_id, professor_id
+            ))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(accept_response.status(), Status::Ok);
+        assert_eq!(applicants_with_status("ACCEPTED").await, 1);
+
+        let deny_response = client
+            .post(format!(
+                "/rest/professor/application/deny?applicant_id={}&professor_id={}",
+                applicant_id, professor_id
+            ))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(deny_response.status(), Status::Conflict);
+
+        assert_eq!(set_status("accepted").await, Status::Ok);
+        assert_eq!(applicants_with_status("accepted").await, 1);
+        assert_eq!(set_status("withdrawn").await, Status::Conflict);
+    }
+
+    // Tests that impersonation sessions are flagged, cannot change passwords or end the
+    // user's own sessions, are hidden from the user and are audited.
+    #[rocket::async_test]
+    async fn impersonation_is_flagged_and_audited() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let (applicant_id, applicant_login) =
+            register_test_applicant(&client, &session_token).await;
+
+        let impersonate_response = client
+            .post(format!("/rest/impersonate?applicant_id={}", applicant_id))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(impersonate_response.status(), Status::Ok);
+        let impersonation_token = to_json_workaround::<LoginResponse>(impersonate_response)
+            .await
+            .session_token;
+
+        let login_type_response = client
+            .get("/rest/login")
+            .header(Header::new("X-Session-Token", impersonation_token.clone()))
+            .dispatch()
+            .await;
+        let login_type = to_json_workaround::<serde_json::Value>(login_type_response).await;
+        assert_eq!(
+            login_type["session_type"],
+            serde_json::json!({ "Applicant": applicant_id })
+        );
+        assert!(login_type["impersonator"].is_i64());
+
+        let password_response = client
+            .put("/rest/login/password")
+            .header(Header::new("X-Session-Token", impersonation_token.clone()))
+            .json(&serde_json::json!({
+                "current_password": applicant_login.password,
+                "new_password": "difference engine",
+            }))
+            .dispatch()
+            .await;
+        assert_eq!(password_response.status(), Status::Forbidden);
+
+        let forbidden_response = client
+            .post(format!("/rest/impersonate?applicant_id={}", applicant_id))
+            .header(Header::new("X-Session-Token", impersonation_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(forbidden_response.status(), Status::Forbidden);
+
+        let applicant_response = client
+            .post("/rest/login")
+            .json(&applicant_login)
+            .dispatch()
+            .await;
+        let applicant_token = to_json_workaround::<LoginResponse>(applicant_response)
+            .await
+            .session_token;
+
+        let sessions_response = client
+            .get("/rest/sessions")
+            .header(Header::new("X-Session-Token", applicant_token.clone()))
+            .dispatch()
+            .await;
+        let sessions = to_json_workaround::<Vec<SessionSummary>>(sessions_response).await;
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].current);
+
+        let logout_all_response = client
+            .post("/rest/logout/all")
+            .header(Header::new("X-Session-Token", impersonation_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(logout_all_response.status(), Status::Forbidden);
+
+        let revoke_response = client
+            .delete(format!("/rest/session?id={}", sessions[0].id))
+            .header(Header::new("X-Session-Token", impersonation_token))
+            .dispatch()
+            .await;
+        assert_eq!(revoke_response.status(), Status::Forbidden);
+
+        let applicant_sessions_response = client
+            .get("/rest/sessions")
+            .header(Header::new("X-Session-Token", applicant_token))
+            .dispatch()
+            .await;
+        assert_eq!(applicant_sessions_response.status(), Status::Ok);
+
+        let audit_response = client
+            .get("/rest/audit-log")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        let events = to_json_workaround::<Vec<AuditEvent>>(audit_response).await;
+        assert!(events
+            .iter()
+            .any(|event| event.action == "IMPERSONATION_START"
+                && event.subject_id == Some(applicant_id)));
+
+        let limited_response = client
+            .get("/rest/audit-log?limit=0")
+            .header(Header::new("X-Session-Token", session_token))
+            .dispatch()
+            .await;
+        let limited_events = to_json_workaround::<Vec<AuditEvent>>(limited_response).await;
+        assert_eq!(limited_events.len(), 1);
+    }
+
+    // Tests single sign-on against a mock OIDC provider that signs in without asking,
+    // such as ghcr.io/navikt/mock-oauth2-server, with OIDC_ISSUER_URL set to its issuer.
+    #[rocket::async_test]
+    #[ignore]
+    async fn oidc_login_provisions_professor() {
+        use openidconnect::http::{HeaderMap, Method};
+        use openidconnect::reqwest::async_http_client;
+        use openidconnect::url::Url;
+        use openidconnect::HttpRequest;
+
+        let issuer_url = std::env::var("OIDC_ISSUER_URL").expect("OIDC_ISSUER_URL must be set");
+        let config = serde_json::json!({
+            "enabled": true,
+            "issuer_url": issuer_url,
+            "client_id": "admissions",
+            "client_secret": "secret",
+            "redirect_url": "http://localhost/sso-callback",
+            "provision": true,
+        });
+        let client = setup_with(("oidc", config.clone())).await;
+
+        let start_response = client.get("/rest/login/oidc").dispatch().await;
+        assert_eq!(start_response.status(), Status::SeeOther);
+        let authorization_url = start_response
+            .headers()
+            .get_one("Location")
+            .expect("no redirect to the provider")
+            .to_string();
+
+        let provider_response = async_http_client(HttpRequest {
+            url: Url::parse(&authorization_url).expect("invalid authorization url"),
+            method: Method::GET,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+        })
+        .await
+        .expect("could not reach the provider");
+        let callback_url = provider_response
+            .headers
+            .get("Location")
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| Url::parse(location).ok())
+            .expect("provider did not redirect back");
+        let query = |name: &str| {
+            callback_url
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.to_string())
+                .expect("callback is missing a parameter")
+        };
+        let callback = serde_json::json!({
+            "code": query("code"),
+            "state": query("state"),
+        });
+
+        // Another browser, without the state cookie, cannot finish the login.
+        let other_client = setup_with(("oidc", config)).await;
+        let other_response = other_client
+            .post("/rest/login/oidc/callback")
+            .json(&callback)
+            .dispatch()
+            .await;
+        assert_eq!(other_response.status(), Status::BadRequest);
+
+        let callback_response = client
+            .post("/rest/login/oidc/callback")
+            .json(&callback)
+            .dispatch()
+            .await;
+        assert_eq!(callback_response.status(), Status::Ok);
+        let session_token = to_json_workaround::<LoginResponse>(callback_response)
+            .await
+            .session_token;
+
+        let login_type_response = client
+            .get("/rest/login")
+            .header(Header::new("X-Session-Token", session_token))
+            .dispatch()
+            .await;
+        let login_type = to_json_workaround::<serde_json::Value>(login_type_response).await;
+        assert!(login_type["session_type"]["Professor"].is_i64());
+
+        let replay_response = client
+            .post("/rest/login/oidc/callback")
+            .json(&callback)
+            .dispatch()
+            .await;
+        assert_eq!(replay_response.status(), Status::BadRequest);
+    }
+
+    // Tests that single sign-on is unavailable unless it is configured.
+    #[rocket::async_test]
+    async fn oidc_login_is_disabled_by_default() {
+        let client = setup().await;
+
+        let start_response = client.get("/rest/login/oidc").dispatch().await;
+        assert_eq!(start_response.status(), Status::NotFound);
+    }
+
+    // Tests that professors without a local login can log in with a directory account
+    // mapped to them by id. See the ldap module tests for the directory this needs.
+    #[rocket::async_test]
+    #[ignore]
+    async fn ldap_professors_can_log_in() {
+        use crate::ldap::test::{add_test_user, admin, delete_test_user, test_url, TEST_BASE_DN};
+
+        let (admin_dn, admin_password) = admin();
+        let client = setup_with((
+            "ldap",
+            serde_json::json!({
+                "enabled": true,
+                "url": test_url(),
+                "bind_dn": admin_dn,
+                "bind_password": admin_password,
+                "base_dn": TEST_BASE_DN,
+            }),
+        ))
+        .await;
+
+        let session_token = admin_session(&client).await;
+
+        let professor_response = client
+            .post("/rest/professor")
+            .header(Header::new("X-Session-Token", session_token))
+            .json(&serde_json::json!({ "name": "Ada" }))
+            .dispatch()
+            .await;
+        let professor_id = to_json_workaround::<IdPayload>(professor_response).await.id;
+
+        let username = format!("ada-{}", chrono::Utc::now().timestamp_millis());
+        let dn = add_test_user(&username, "analytical engine", &professor_id.to_string()).await;
+
+        let wrong_response = client
+            .post("/rest/login")
+            .json(&Login {
+                username: username.clone(),
+                password: "difference engine".to_string(),
+            })
+            .dispatch()
+            .await;
+        let directory_response = client
+            .post("/rest/login")
+            .json(&Login {
+                username,
+                password: "analytical engine".to_string(),
+            })
+            .dispatch()
+            .await;
+        let directory_status = directory_response.status();
+        let directory_token = to_json_workaround::<LoginResponse>(directory_response)
+            .await
+            .session_token;
+        delete_test_user(&dn).await;
+
+        assert_eq!(wrong_response.status(), Status::Forbidden);
+        assert_eq!(directory_status, Status::Ok);
+
+        let login_type_response = client
+            .get("/rest/login")
+            .header(Header::new("X-Session-Token", directory_token))
+            .dispatch()
+            .await;
+        let login_type = to_json_workaround::<serde_json::Value>(login_type_response).await;
+        assert_eq!(
+            login_type["session_type"],
+            serde_json::json!({ "Professor": professor_id })
+        );
+    }
+
+    // Tests that cookie sessions work without the header, but only change state with the
+    // CSRF token.
+    #[rocket::async_test]
+    async fn cookie_sessions_require_csrf_token() {
+        let frontend = "https://frontend.example.com";
+        let client = setup_with((
+            "session_cookies",
+            serde_json::json!({ "enabled": true, "allowed_origin": frontend }),
+        ))
+        .await;
+
+        let login = create_test_admin(&client).await;
+
+        let login_response = client
+            .post("/rest/login")
+            .header(Header::new("Origin", frontend))
+            .json(&login)
+            .dispatch()
+            .await;
+        assert_eq!(login_response.status(), Status::Ok);
+        assert!(login_response.cookies().get("session").is_some());
+        let cors_headers = login_response.headers();
+        assert_eq!(
+            cors_headers.get_one("Access-Control-Allow-Origin"),
+            Some(frontend)
+        );
+        assert_eq!(
+            cors_headers.get_one("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        let csrf_token = to_json_workaround::<CookieLoginResponse>(login_response)
+            .await
+            .csrf_token;
+
+        let other_origin_response = client
+            .get("/rest/login")
+            .header(Header::new("Origin", "https://elsewhere.example.com"))
+            .dispatch()
+            .await;
+        let cors_headers = other_origin_response.headers();
+        assert_eq!(
+            cors_headers.get_one("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert!(cors_headers
+            .get_one("Access-Control-Allow-Credentials")
+            .is_none());
+
+        let login_type_response = client.get("/rest/login").dispatch().await;
+        let login_type = to_json_workaround::<serde_json::Value>(login_type_response).await;
+        assert!(login_type["session_type"]["Administrator"].is_i64());
+
+        let field = NewResearchField {
+            name: "Geology".to_string(),
+        };
+        let forged_response = client
+            .post("/rest/research-field")
+            .json(&field)
+            .dispatch()
+            .await;
+        assert_eq!(forged_response.status(), Status::Forbidden);
+
+        let field_response = client
+            .post("/rest/research-field")
+            .header(Header::new("X-CSRF-Token", csrf_token.clone()))
+            .json(&field)
+            .dispatch()
+            .await;
+        assert_eq!(field_response.status(), Status::Ok);
+
+        let logout_response = client
+            .post("/rest/logout")
+            .header(Header::new("X-CSRF-Token", csrf_token))
+            .dispatch()
+            .await;
+        assert_eq!(logout_response.status(), Status::Ok);
+
+        let logged_out_response = client.get("/rest/login").dispatch().await;
+        let logged_out = to_json_workaround::<serde_json::Value>(logged_out_response).await;
+        assert!(logged_out["session_type"].is_null());
+    }
+
+    // Tests that repeated failed logins lock out the username and the proxied client IP
+    // until an administrator clears them.
+    #[rocket::async_test]
+    async fn failed_logins_are_locked_out() {
+        let client = setup_with((
+            "login_throttle",
+            serde_json::json!({
+                "max_username_failures": 2,
+                "max_ip_failures": 3,
+                "client_ip_header": "X-Real-IP",
+            }),
+        ))
+        .await;
+
+        let session_token = admin_session(&client).await;
+
+        let suffix = chrono::Utc::now()
+            .timestamp_nanos_opt()
+            .expect("time is out of range");
+        let username = format!("guesser-{}", suffix);
+        let client_ip = format!(
+            "10.{}.{}.{}",
+            (suffix >> 16) & 0xff,
+            (suffix >> 8) & 0xff,
+            suffix & 0xff
+        );
+        let try_login = |username: String| {
+            client
+                .post("/rest/login")
+                .header(Header::new("X-Real-IP", client_ip.clone()))
+                .json(&Login {
+                    username,
+                    password: "wrong".to_string(),
+                })
+                .dispatch()
+        };
+
+        assert_eq!(
+            try_login(username.clone()).await.status(),
+            Status::Forbidden
+        );
+        assert_eq!(
+            try_login(username.clone()).await.status(),
+            Status::Forbidden
+        );
+        assert_eq!(
+            try_login(username.clone()).await.status(),
+            Status::TooManyRequests
+        );
+
+        let lockouts_response = client
+            .get("/rest/login/lockouts")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(lockouts_response.status(), Status::Ok);
+        let lockouts = to_json_workaround::<serde_json::Value>(lockouts_response).await;
+        let lockout = |kind: &str, value: &str| {
+            lockouts
+                .as_array()
+                .expect("lockouts are not a list")
+                .iter()
+                .find(|lockout| lockout["kind"] == kind && lockout["value"] == value)
+                .cloned()
+        };
+        let username_lockout = lockout("USERNAME", &username).expect("username not locked out");
+        assert_eq!(username_lockout["failures"], 2);
+        assert!(username_lockout["locked_until"].is_string());
+        let ip_lockout = lockout("IP", &client_ip).expect("client IP not counted");
+        assert_eq!(ip_lockout["failures"], 2);
+        assert!(ip_lockout["locked_until"].is_null());
+
+        let anonymous_clear_response = client
+            .delete(format!(
+                "/rest/login/lockout?kind=USERNAME&value={}",
+                username
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(anonymous_clear_response.status(), Status::Forbidden);
 
-    // Checks whether the current database is a testing database.
-    fn is_testing_db<T: AsRef<str>>(url: T) -> bool {
-        let lowercase = url.as_ref().to_ascii_lowercase();
-        lowercase.contains("testing") || lowercase.contains("test")
+        let clear_response = client
+            .delete(format!(
+                "/rest/login/lockout?kind=USERNAME&value={}",
+                username
+            ))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(clear_response.status(), Status::Ok);
+        assert_eq!(
+            try_login(username.clone()).await.status(),
+            Status::Forbidden
+        );
+
+        // The IP has now failed three times, so it is locked out for any username.
+        assert_eq!(
+            try_login(format!("{}-other", username)).await.status(),
+            Status::TooManyRequests
+        );
     }
 
-    // This resource is only accessible during testing, as only test setup functions mount it
-    #[delete("/delete")]
-    async fn reset_database(conn: DbConn) {
-        conn.run(|c| {
-            use crate::schema;
-            use schema::applicants::dsl::*;
-            use schema::professor_research_fields::dsl::*;
-            use schema::professors::dsl::*;
-            use schema::research_fields::dsl::*;
-            use schema::student_applied_to::dsl::*;
+    // Tests that password resets can only be requested a few times for a username, whether
+    // or not it exists.
+    #[rocket::async_test]
+    async fn password_reset_requests_are_throttled() {
+        let client = setup().await;
 
-            diesel::delete(student_applied_to)
-                .execute(c)
-                .expect("could not delete student_applied_to table");
-            diesel::delete(professor_research_fields)
-                .execute(c)
-                .expect("could not delete professor_research_fields table");
-            diesel::delete(applicants)
-                .execute(c)
-                .expect("could not delete applicants table");
-            diesel::delete(professors)
-                .execute(c)
-                .expect("could not delete professors table");
-            diesel::delete(research_fields)
-                .execute(c)
-                .expect("could not delete research_fields table");
-        })
-        .await;
+        let username = format!(
+            "forgetful-{}",
+            chrono::Utc::now()
+                .timestamp_nanos_opt()
+                .expect("time is out of range")
+        );
+        for expected in [Status::Ok, Status::Ok, Status::Ok, Status::TooManyRequests] {
+            let reset_response = client
+                .post("/rest/login/password-reset")
+                .json(&serde_json::json!({ "username": username }))
+                .dispatch()
+                .await;
+            assert_eq!(reset_response.status(), expected);
+        }
     }
 
-    // Configures and connects the dabatase for testing.
-    const DATABASE_URL_KEY: &'static str = "databases.db.url";
-    async fn setup() -> Client {
+    // Tests that cookies sent cross-site are refused unless they are secure.
+    #[rocket::async_test]
+    async fn cross_site_cookies_must_be_secure() {
         set_var("ROCKET_PROFILE", "testing");
 
         let rocket = rocket();
-        let db_url = rocket
+        let profile = rocket.figment().profile().clone();
+        let figment = rocket
             .figment()
-            .find_value(DATABASE_URL_KEY)
-            .expect("no database url configured")
-            .into_string()
-            .expect("database url cannot be converted to string");
-
-        if !is_testing_db(&db_url) {
-            panic!(
-                "DB url {} is not a testing database url (must contain test or testing)",
-                db_url
-            );
-        }
+            .clone()
+            .merge((
+                "session_cookies",
+                serde_json::json!({ "enabled": true, "same_site": "none", "secure": false }),
+            ))
+            .select(profile);
 
-        let client = Client::tracked(rocket.mount("/testing-only", routes![reset_database]))
+        let error = rocket
+            .configure(figment)
+            .ignite()
             .await
-            .expect("valid rocket instance");
+            .expect_err("insecure cross-site cookies were allowed");
+        assert!(matches!(
+            error.kind(),
+            rocket::error::ErrorKind::FailedFairings(_)
+        ));
+    }
 
-        client.delete("/testing-only/delete").dispatch().await;
+    // Tests that invites are listed with their status and can only be cancelled once.
+    #[rocket::async_test]
+    async fn invites_can_be_cancelled() {
+        let client = setup().await;
 
-        client
+        let session_token = admin_session(&client).await;
+
+        let professor_response = client
+            .post("/rest/professor")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&serde_json::json!({ "name": "Grace", "email": "grace@example.com" }))
+            .dispatch()
+            .await;
+        let professor_id = to_json_workaround::<IdPayload>(professor_response).await.id;
+
+        let invite_response = client
+            .post(format!("/rest/invites?professor_id={}", professor_id))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(invite_response.status(), Status::Ok);
+        let invite_id = to_json_workaround::<IdPayload>(invite_response).await.id;
+
+        let invite_status = |invites: serde_json::Value| {
+            invites
+                .as_array()
+                .expect("invites are not a list")
+                .iter()
+                .find(|invite| invite["id"] == invite_id)
+                .map(|invite| invite["status"].clone())
+        };
+
+        let invites_response = client
+            .get("/rest/invites")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        let invites = to_json_workaround::<serde_json::Value>(invites_response).await;
+        assert_eq!(invite_status(invites), Some("pending".into()));
+
+        for expected in [Status::Ok, Status::NotFound] {
+            let cancel_response = client
+                .delete(format!("/rest/invite?id={}", invite_id))
+                .header(Header::new("X-Session-Token", session_token.clone()))
+                .dispatch()
+                .await;
+            assert_eq!(cancel_response.status(), expected);
+        }
+
+        let invites_response = client
+            .get("/rest/invites")
+            .header(Header::new("X-Session-Token", session_token))
+            .dispatch()
+            .await;
+        let invites = to_json_workaround::<serde_json::Value>(invites_response).await;
+        assert_eq!(invite_status(invites), Some("cancelled".into()));
     }
 
-    // The into_json method of the async LocalResponse tends to hangs as of v0.5-rc1 (https://github.com/SergioBenitez/Rocket/issues/1893)
-    async fn to_json_workaround<T: DeserializeOwned>(response: LocalResponse<'_>) -> T {
-        let body = response
-            .into_string()
+    // Tests that an account only ever has one pending invite, and that accounts with a
+    // login cannot be given another.
+    #[rocket::async_test]
+    async fn invites_replace_pending_ones() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let professor_response = client
+            .post("/rest/professor")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&serde_json::json!({ "name": "Ada", "email": "ada@example.com" }))
+            .dispatch()
+            .await;
+        let professor_id = to_json_workaround::<IdPayload>(professor_response).await.id;
+
+        let mut invite_ids = Vec::new();
+        for _ in 0..2 {
+            let invite_response = client
+                .post(format!("/rest/invites?professor_id={}", professor_id))
+                .header(Header::new("X-Session-Token", session_token.clone()))
+                .dispatch()
+                .await;
+            assert_eq!(invite_response.status(), Status::Ok);
+            invite_ids.push(to_json_workaround::<IdPayload>(invite_response).await.id);
+        }
+
+        // The emailed token cannot be read back, so a known one is added next to the
+        // pending invite, as if an earlier one had been left pending.
+        let suffix = chrono::Utc::now()
+            .timestamp_nanos_opt()
+            .expect("time is out of range");
+        let token = format!("invite-{}", suffix);
+        let conn = DbConn::get_one(client.rocket())
             .await
-            .expect("response body could not be converted to string");
+            .expect("could not connect to database");
+        let token_hash = super::hash_token(&token);
+        conn.run(move |c| {
+            diesel::insert_into(crate::schema::invites::table)
+                .values(NewInvite {
+                    token_hash,
+                    session_type: "PROFESSOR".to_string(),
+                    subject_id: professor_id,
+                    created_by: None,
+                    expires_at: chrono::Utc::now() + chrono::Duration::minutes(1),
+                })
+                .execute(c)
+        })
+        .await
+        .expect("could not create invite");
 
-        serde_json::from_str(&body).expect("could not deserialize response body into JSON")
+        let username = format!("ada-{}", suffix);
+        let accept_response = client
+            .post("/rest/invite/accept")
+            .json(&serde_json::json!({
+                "token": token,
+                "username": username,
+                "password": "correct horse battery",
+            }))
+            .dispatch()
+            .await;
+        assert_eq!(accept_response.status(), Status::Ok);
+
+        let invites_response = client
+            .get("/rest/invites")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        let invites = to_json_workaround::<serde_json::Value>(invites_response).await;
+        let invite_status = |invite_id: i32| {
+            invites
+                .as_array()
+                .expect("invites are not a list")
+                .iter()
+                .find(|invite| invite["id"] == invite_id)
+                .map(|invite| invite["status"].clone())
+        };
+        assert_eq!(invite_status(invite_ids[0]), Some("cancelled".into()));
+        assert_eq!(invite_status(invite_ids[1]), Some("cancelled".into()));
+
+        let reinvite_response = client
+            .post(format!("/rest/invites?professor_id={}", professor_id))
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(reinvite_response.status(), Status::Conflict);
+
+        let login_response = client
+            .post(format!(
+                "/rest/login/professor?professor_id={}",
+                professor_id
+            ))
+            .header(Header::new("X-Session-Token", session_token))
+            .json(&Login {
+                username: format!("{}-again", username),
+                password: "correct horse battery".to_string(),
+            })
+            .dispatch()
+            .await;
+        assert_eq!(login_response.status(), Status::Conflict);
     }
 
-    // Tests the creation of a research field.
+    // Tests that users can see their sessions and end one of them.
     #[rocket::async_test]
-    async fn create_get_research_field() {
+    async fn sessions_can_be_listed_and_revoked() {
         let client = setup().await;
 
-        let login = Login {
-            username: "testing".to_string(),
-            password: "test".to_string(),
-        };
+        let login = create_test_admin(&client).await;
 
-        client
-            .post("/rest/login/admin")
-            .json(&login)
+        let mut session_tokens = Vec::new();
+        for _ in 0..2 {
+            let login_response = client
+                .post("/rest/login")
+                .header(Header::new("User-Agent", "session-test"))
+                .json(&login)
+                .dispatch()
+                .await;
+            session_tokens.push(
+                to_json_workaround::<LoginResponse>(login_response)
+                    .await
+                    .session_token,
+            );
+        }
+
+        let sessions_response = client
+            .get("/rest/sessions")
+            .header(Header::new("X-Session-Token", session_tokens[1].clone()))
             .dispatch()
             .await;
+        assert_eq!(sessions_response.status(), Status::Ok);
+        let sessions = to_json_workaround::<Vec<SessionSummary>>(sessions_response).await;
+        let current: Vec<&SessionSummary> =
+            sessions.iter().filter(|session| session.current).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].user_agent.as_deref(), Some("session-test"));
 
-        let login_response = client.post("/rest/login").json(&login).dispatch().await;
+        let revoke_response = client
+            .delete(format!("/rest/session?id={}", current[0].id))
+            .header(Header::new("X-Session-Token", session_tokens[0].clone()))
+            .dispatch()
+            .await;
+        assert_eq!(revoke_response.status(), Status::Ok);
+
+        for (session_token, expected) in session_tokens
+            .into_iter()
+            .zip([Status::Ok, Status::Forbidden])
+        {
+            let sessions_response = client
+                .get("/rest/sessions")
+                .header(Header::new("X-Session-Token", session_token))
+                .dispatch()
+                .await;
+            assert_eq!(sessions_response.status(), expected);
+        }
+    }
 
-        let session_token = to_json_workaround::<LoginResponse>(login_response).await;
+    // Tests that API keys can only take the actions in their scopes, and stop working once
+    // revoked.
+    #[rocket::async_test]
+    async fn api_keys_are_scoped() {
+        let client = setup().await;
 
-        let biology = NewResearchField {
-            name: "Biology".to_string(),
-        };
+        let session_token = admin_session(&client).await;
+
+        let manage_key_response = client
+            .post("/rest/api-keys")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&serde_json::json!({ "name": "admin", "scopes": ["account:manage"] }))
+            .dispatch()
+            .await;
+        assert_eq!(manage_key_response.status(), Status::UnprocessableEntity);
+
+        let key_response = client
+            .post("/rest/api-keys")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&serde_json::json!({ "name": "reader", "scopes": ["professor:read"] }))
+            .dispatch()
+            .await;
+        assert_eq!(key_response.status(), Status::Ok);
+        let key = to_json_workaround::<ApiKeyResponse>(key_response).await;
+
+        let read_response = client
+            .get("/rest/professors")
+            .header(Header::new("X-Api-Key", key.key.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(read_response.status(), Status::Ok);
 
-        println!("Sending first create message...");
         let create_response = client
             .post("/rest/research-field")
-            .header(Header::new(
-                "X-Session-Token",
-                session_token.session_token.clone(),
+            .header(Header::new("X-Api-Key", key.key.clone()))
+            .json(&NewResearchField {
+                name: "Chemistry".to_string(),
+            })
+            .dispatch()
+            .await;
+        assert_eq!(create_response.status(), Status::Forbidden);
+
+        let revoke_response = client
+            .delete(format!("/rest/api-key?id={}", key.id))
+            .header(Header::new("X-Session-Token", session_token))
+            .dispatch()
+            .await;
+        assert_eq!(revoke_response.status(), Status::Ok);
+
+        let revoked_response = client
+            .get("/rest/professors")
+            .header(Header::new("X-Api-Key", key.key))
+            .dispatch()
+            .await;
+        assert_eq!(revoked_response.status(), Status::Forbidden);
+    }
+
+    // Tests that the password of an account without a login cannot be set.
+    #[rocket::async_test]
+    async fn setting_password_needs_a_login() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let professor_response = client
+            .post("/rest/professor")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .json(&serde_json::json!({ "name": "Emmy" }))
+            .dispatch()
+            .await;
+        let professor_id = to_json_workaround::<IdPayload>(professor_response).await.id;
+
+        let password_response = client
+            .put(format!(
+                "/rest/login/professor/password?professor_id={}",
+                professor_id
             ))
-            .json(&biology)
+            .header(Header::new("X-Session-Token", session_token))
+            .json(&serde_json::json!({ "password": "correct horse battery" }))
             .dispatch()
             .await;
+        assert_eq!(password_response.status(), Status::NotFound);
+    }
 
-        assert_eq!(create_response.status(), Status::Ok);
-        let id = to_json_workaround::<IdPayload>(create_response).await.id;
+    // Tests that resetting the password of an account without a login is not found.
+    #[rocket::async_test]
+    async fn password_reset_needs_a_login() {
+        let client = setup().await;
 
-        let biology_get_response = client
-            .get(format!("/rest/research-field?id={}", id))
-            .header(Header::new("X-Session-Token", session_token.session_token))
+        let session_token = admin_session(&client).await;
+
+        let professor_response = client
+            .post("/rest/professor")
+            .header(Header::new("X-Session-Token", session_token))
+            .json(&serde_json::json!({ "name": "Sophie" }))
             .dispatch()
             .await;
+        let professor_id = to_json_workaround::<IdPayload>(professor_response).await.id;
 
-        assert_eq!(biology_get_response.status(), Status::Ok);
+        let conn = DbConn::get_one(client.rocket())
+            .await
+            .expect("could not connect to database");
+        let token = format!("reset-{}", professor_id);
+        db::create_one_time_token(
+            &conn,
+            super::hash_token(&token),
+            db::TOKEN_PASSWORD_RESET,
+            SessionType::Professor(professor_id),
+            chrono::Utc::now() + chrono::Duration::minutes(1),
+        )
+        .await
+        .expect("could not create reset token");
+
+        let reset_response = client
+            .post("/rest/login/password-reset/confirm")
+            .json(&serde_json::json!({
+                "token": token,
+                "password": "correct horse battery",
+            }))
+            .dispatch()
+            .await;
+        assert_eq!(reset_response.status(), Status::NotFound);
+    }
+
+    // Tests that a username already in use cannot be given to another account.
+    #[rocket::async_test]
+    async fn duplicate_username_conflicts() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let create_response = client
+            .post("/rest/login/admin")
+            .header(Header::new("X-Session-Token", session_token))
+            .json(&Login {
+                username: "testing".to_string(),
+                password: "another horse battery".to_string(),
+            })
+            .dispatch()
+            .await;
+        assert_eq!(create_response.status(), Status::Conflict);
+    }
+
+    // Tests that a password breaking the policy is rejected with the rules it failed.
+    #[rocket::async_test]
+    async fn weak_passwords_are_rejected() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let create_response = client
+            .post("/rest/login/admin")
+            .header(Header::new("X-Session-Token", session_token))
+            .json(&Login {
+                username: "weak-admin".to_string(),
+                password: "weak-admin".to_string(),
+            })
+            .dispatch()
+            .await;
+        assert_eq!(create_response.status(), Status::UnprocessableEntity);
         assert_eq!(
-            to_json_workaround::<ResearchField>(biology_get_response).await,
-            ResearchField {
-                id,
-                name: biology.name
+            to_json_workaround::<PolicyViolation>(create_response).await,
+            PolicyViolation {
+                failed_rules: vec![PasswordRule::NoUsername]
             }
         );
     }
+
+    // Tests that an enrolled administrator needs a code after their password, that
+    // recovery codes only work once, that a suspended account cannot finish logging in,
+    // and that wrong codes lock the username out.
+    #[rocket::async_test]
+    async fn totp_login_requires_code() {
+        let client = setup().await;
+
+        let session_token = admin_session(&client).await;
+
+        let totp_login = Login {
+            username: format!(
+                "totp-{}",
+                chrono::Utc::now()
+                    .timestamp_nanos_opt()
+                    .expect("time is out of range")
+            ),
+            password: "correct horse battery".to_string(),
+        };
+        let create_response = client
+            .post("/rest/login/admin")
+            .header(Header::new("X-Session-Token", session_token))
+            .json(&totp_login)
+            .dispatch()
+            .await;
+        assert_eq!(create_response.status(), Status::Ok);
+
+        let login_response = client
+            .post("/rest/login")
+            .json(&totp_login)
+            .dispatch()
+            .await;
+        let session_token = to_json_workaround::<LoginResponse>(login_response)
+            .await
+            .session_token;
+
+        let enroll_response = client
+            .post("/rest/login/totp/enroll")
+            .header(Header::new("X-Session-Token", session_token.clone()))
+            .dispatch()
+            .await;
+        let enrollment = to_json_workaround::<TotpEnrollmentResponse>(enroll_response).await;
+
+        let secret = totp::decode_secret(&enrollment.secret).expect("secret is not base32");
+        let code = totp::code_at(&secret, totp::time_step(chrono::Utc::now().timestamp()));
+        let confirm_response = client
+            .post("/rest/login/totp/confirm")
+            .header(Header::new("X-Session-Token", session_token))
+            .json(&serde_json::json!({ "code": format!("{:06}", code) }))
+            .dispatch()
+            .await;
+        assert_eq!(confirm_response.status(), Status::Ok);
+
+        for expected in [Status::Ok, Status::Forbidden] {
+            let login_response = client
+                .post("/rest/login")
+                .json(&totp_login)
+                .dispatch()
+                .await;
+            let challenge = to_json_workaround::<TotpChallenge>(login_response).await;
+
+            let totp_response = client
+                .post("/rest/login/totp")
+                .json(&serde_json::json!({
+                    "pre_auth_token": challenge.pre_auth_token,
+                    "code": enrollment.recovery_codes[0],
+                }))
+                .dispatch()
+                .await;
+            assert_eq!(totp_response.status(), expected);
+        }
+
+        // An account suspended after its password was checked cannot finish logging in.
+        let admin_token = admin_session(&client).await;
+        let admins_response = client
+            .get("/rest/admins")
+            .header(Header::new("X-Session-Token", admin_token.clone()))
+            .dispatch()
+            .await;
+        let totp_admin_id = to_json_workaround::<Vec<Administrator>>(admins_response)
+            .await
+            .iter()
+            .find(|admin| admin.username == totp_login.username)
+            .expect("new admin is not listed")
+            .id;
+
+        let login_response = client
+            .post("/rest/login")
+            .json(&totp_login)
+            .dispatch()
+            .await;
+        let challenge = to_json_workaround::<TotpChallenge>(login_response).await;
+
+        let disable_response = client
+            .post(format!("/rest/admin/disable?id={}", totp_admin_id))
+            .header(Header::new("X-Session-Token", admin_token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(disable_response.status(), Status::Ok);
+
+        let suspended_response = client
+            .post("/rest/login/totp")
+            .json(&serde_json::json!({
+                "pre_auth_token": challenge.pre_auth_token,
+                "code": enrollment.recovery_codes[1],
+            }))
+            .dispatch()
+            .await;
+        assert_eq!(suspended_response.status(), Status::Forbidden);
+
+        let enable_response = client
+            .post(format!("/rest/admin/enable?id={}", totp_admin_id))
+            .header(Header::new("X-Session-Token", admin_token))
+            .dispatch()
+            .await;
+        assert_eq!(enable_response.status(), Status::Ok);
+
+        // Wrong codes lock the username out, even though the password was right.
+        for _ in 0..4 {
+            let login_response = client
+                .post("/rest/login")
+                .json(&totp_login)
+                .dispatch()
+                .await;
+            let challenge = to_json_workaround::<TotpChallenge>(login_response).await;
+
+            let totp_response = client
+                .post("/rest/login/totp")
+                .json(&serde_json::json!({
+                    "pre_auth_token": challenge.pre_auth_token,
+                    "code": "000000",
+                }))
+                .dispatch()
+                .await;
+            assert_eq!(totp_response.status(), Status::Forbidden);
+        }
+
+        let locked_response = client
+            .post("/rest/login")
+            .json(&totp_login)
+            .dispatch()
+            .await;
+        assert_eq!(locked_response.status(), Status::TooManyRequests);
+    }
 }
@@ -1,11 +1,46 @@
 table! {
-    admin_logins (username) {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    account_usernames (username) {
+        username -> Text,
+        account_kind -> Text,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    admin_logins (id) {
         username -> Text,
         bcrypt_hash -> Bpchar,
+        id -> Int4,
+        active -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    api_keys (id) {
+        id -> Int4,
+        name -> Text,
+        key_hash -> Text,
+        scopes -> Array<Text>,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
     applicant_blobs (id) {
         id -> Int4,
         data_blob -> Bytea,
@@ -13,14 +48,22 @@ table! {
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
     applicant_logins (id) {
         id -> Int4,
         username -> Text,
         bcrypt_hash -> Bpchar,
+        verified -> Bool,
+        active -> Bool,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
     applicants (id) {
         id -> Int4,
         desired_field_id -> Int4,
@@ -34,14 +77,93 @@ table! {
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    audit_log (id) {
+        id -> Int4,
+        administrator_id -> Nullable<Int4>,
+        action -> Text,
+        session_type -> Nullable<Text>,
+        subject_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    invites (id) {
+        id -> Int4,
+        token_hash -> Text,
+        session_type -> Text,
+        subject_id -> Int4,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        accepted_at -> Nullable<Timestamptz>,
+        cancelled_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    login_lockouts (kind, value) {
+        kind -> Text,
+        value -> Text,
+        failures -> Int4,
+        last_failure_at -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    oidc_logins (state) {
+        state -> Text,
+        nonce -> Text,
+        pkce_verifier -> Text,
+        started_at -> Timestamptz,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    one_time_tokens (token_hash) {
+        token_hash -> Text,
+        purpose -> Text,
+        session_type -> Text,
+        subject_id -> Int4,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
     professor_logins (id) {
         id -> Int4,
         username -> Text,
         bcrypt_hash -> Bpchar,
+        oidc_subject -> Nullable<Text>,
+        active -> Bool,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
     professor_research_fields (prof_id, field_id) {
         prof_id -> Int4,
         field_id -> Int4,
@@ -49,13 +171,20 @@ table! {
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
     professors (id) {
         id -> Int4,
         name -> Text,
+        email -> Nullable<Text>,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
     research_fields (id) {
         id -> Int4,
         name -> Text,
@@ -63,15 +192,77 @@ table! {
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    session_generations (session_type, subject_id) {
+        session_type -> Text,
+        subject_id -> Int4,
+        generation -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    sessions (token_hash) {
+        token_hash -> Text,
+        session_type -> Text,
+        subject_id -> Int4,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        idle_timeout -> Int4,
+        idle_expires_at -> Timestamptz,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        impersonator -> Nullable<Int4>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
     student_applied_to (applicant_id, prof_id) {
         applicant_id -> Int4,
         prof_id -> Int4,
-        status -> Text,
+        status -> ApplicationStatusType,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    totp_enrollments (session_type, subject_id) {
+        session_type -> Text,
+        subject_id -> Int4,
+        secret -> Text,
+        created_at -> Timestamptz,
+        confirmed_at -> Nullable<Timestamptz>,
+        last_used_step -> Nullable<Int8>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::application_status::ApplicationStatusType;
+
+    totp_recovery_codes (code_hash) {
+        code_hash -> Text,
+        session_type -> Text,
+        subject_id -> Int4,
+        used_at -> Nullable<Timestamptz>,
     }
 }
 
+joinable!(api_keys -> admin_logins (created_by));
 joinable!(applicant_logins -> applicants (id));
 joinable!(applicants -> research_fields (desired_field_id));
+joinable!(audit_log -> admin_logins (administrator_id));
+joinable!(invites -> admin_logins (created_by));
 joinable!(professor_logins -> professors (id));
 joinable!(professor_research_fields -> professors (prof_id));
 joinable!(professor_research_fields -> research_fields (field_id));
@@ -79,13 +270,24 @@ joinable!(student_applied_to -> applicants (applicant_id));
 joinable!(student_applied_to -> professors (prof_id));
 
 allow_tables_to_appear_in_same_query!(
+    account_usernames,
     admin_logins,
+    api_keys,
     applicant_blobs,
     applicant_logins,
     applicants,
+    audit_log,
+    invites,
+    login_lockouts,
+    oidc_logins,
+    one_time_tokens,
     professor_logins,
     professor_research_fields,
     professors,
     research_fields,
+    session_generations,
+    sessions,
     student_applied_to,
+    totp_enrollments,
+    totp_recovery_codes,
 );
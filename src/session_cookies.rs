@@ -0,0 +1,132 @@
+//! Sessions kept in cookies, so the frontend never has to store the session token where
+//! scripts can read it:
+//!
+//! ```toml
+//! [default.session_cookies]
+//! enabled = true
+//! secure = true
+//! same_site = "strict" # or "lax", or "none" for a frontend on another site
+//! allowed_origin = "https://frontend.example.com"
+//! ```
+//!
+//! When enabled, logging in sets the session token in a private, HttpOnly `session`
+//! cookie instead of returning it, along with a `csrf_token` cookie the frontend can read.
+//! Requests authenticated by the cookie that may change state must send the CSRF token
+//! back in the `X-CSRF-Token` header. The CSRF token is derived from the session token,
+//! so it cannot be forged by setting the `csrf_token` cookie. The `X-Session-Token` header
+//! keeps working either way, and is used over the cookie when both are sent.
+//!
+//! Browsers only send the cookies cross-origin to an origin CORS allows by name, so a
+//! frontend served from another origin has to be set as `allowed_origin`. A frontend on
+//! another site also needs `same_site = "none"`, which browsers only accept on secure
+//! cookies.
+
+use crate::request_guards::hash_token;
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{Cookie, CookieJar, Method, SameSite};
+use serde::Deserialize;
+
+pub const SESSION_COOKIE_NAME: &str = "session";
+pub const CSRF_COOKIE_NAME: &str = "csrf_token";
+pub const CSRF_HEADER_NAME: &str = "X-CSRF-Token";
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CookieSameSite {
+    #[default]
+    Strict,
+    Lax,
+    None,
+}
+
+/// The `session_cookies` section of the Rocket config.
+#[derive(Deserialize, Debug)]
+pub struct SessionCookieConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Whether the cookies are only sent over HTTPS, which should only be turned off
+    /// for local development.
+    #[serde(default = "default_secure")]
+    pub secure: bool,
+    #[serde(default)]
+    pub same_site: CookieSameSite,
+    /// The origin of the frontend, which CORS responses allow to send the cookies.
+    #[serde(default)]
+    pub allowed_origin: Option<String>,
+}
+
+fn default_secure() -> bool {
+    true
+}
+
+/// Gets the CSRF token that has to accompany a session token sent as a cookie.
+pub fn csrf_token(session_token: &str) -> String {
+    hash_token(&format!("csrf:{}", session_token))
+}
+
+/// Checks whether requests with a method may change state, and so need a CSRF token
+/// when they are authenticated by cookie.
+pub fn needs_csrf_token(method: Method) -> bool {
+    !matches!(method, Method::Get | Method::Head | Method::Options)
+}
+
+impl SessionCookieConfig {
+    pub fn same_site(&self) -> SameSite {
+        match self.same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        }
+    }
+
+    /// Sets the cookies of a new session, returning its CSRF token.
+    pub fn add_session_cookies(&self, cookies: &CookieJar<'_>, session_token: &str) -> String {
+        let csrf_token = csrf_token(session_token);
+
+        cookies.add_private(
+            Cookie::build(SESSION_COOKIE_NAME, session_token.to_string())
+                .http_only(true)
+                .secure(self.secure)
+                .same_site(self.same_site())
+                .path("/")
+                .finish(),
+        );
+        cookies.add(
+            Cookie::build(CSRF_COOKIE_NAME, csrf_token.clone())
+                .http_only(false)
+                .secure(self.secure)
+                .same_site(self.same_site())
+                .path("/")
+                .finish(),
+        );
+
+        csrf_token
+    }
+
+    /// Removes the cookies of a session that has ended.
+    pub fn remove_session_cookies(&self, cookies: &CookieJar<'_>) {
+        cookies.remove_private(Cookie::named(SESSION_COOKIE_NAME));
+        cookies.remove(Cookie::named(CSRF_COOKIE_NAME));
+    }
+}
+
+/// Reads the `session_cookies` config and manages `SessionCookieConfig`.
+pub fn fairing() -> impl Fairing {
+    AdHoc::try_on_ignite("Session Cookies", |rocket| async move {
+        match rocket
+            .figment()
+            .focus("session_cookies")
+            .extract::<SessionCookieConfig>()
+        {
+            Ok(config) if config.same_site == CookieSameSite::None && !config.secure => {
+                eprintln!("Invalid session cookie config: same_site = \"none\" requires secure");
+                Err(rocket)
+            }
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(e) => {
+                eprintln!("Invalid session cookie config: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
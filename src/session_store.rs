@@ -0,0 +1,842 @@
+//! Defines where login sessions are kept. Sessions are keyed by the hash of their
+//! token, and the backend holding them is chosen with the `sessions.store` key of the
+//! Rocket config:
+//!
+//! ```toml
+//! [default.sessions]
+//! store = "redis" # one of "memory", "postgres" (the default) or "redis"
+//! redis_url = "redis://127.0.0.1/"
+//! sweep_interval = 600
+//! impersonation_lifetime = 900
+//!
+//! [default.sessions.lifetimes.administrator]
+//! idle_timeout = 900
+//! absolute_lifetime = 28800
+//! ```
+//!
+//! A session expires once it has gone unused for its idle timeout, or once its absolute
+//! lifetime has passed, whichever comes first. Every use of a session pushes its idle
+//! expiry back, and expired sessions are swept periodically after launch.
+
+use crate::models::{NewSession, Session as SessionRow};
+use crate::request_guards::state::SessionType;
+use crate::schema;
+use crate::SessionStoreState;
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use redis::aio::ConnectionManager as RedisConnectionManager;
+use redis::AsyncCommands;
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::{Build, Rocket};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+/// A login session as seen by the session stores.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    pub session_type: SessionType,
+    pub created_at: DateTime<Utc>,
+    /// The end of the session's absolute lifetime.
+    pub expires_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// How long, in seconds, the session may go unused before it expires.
+    pub idle_timeout: i32,
+    /// When the session expires unless it is used again, never after `expires_at`.
+    pub idle_expires_at: DateTime<Utc>,
+    /// The address of the client that logged in.
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// The administrator acting as the account, if this is an impersonation session.
+    #[serde(default)]
+    pub impersonator: Option<i32>,
+}
+
+impl Session {
+    /// Creates a session that starts now and lasts as long as the given lifetime allows.
+    pub fn new(session_type: SessionType, lifetime: SessionLifetime) -> Session {
+        let now = Utc::now();
+        let expires_at = now + Duration::seconds(lifetime.absolute_lifetime as i64);
+
+        Session {
+            session_type,
+            created_at: now,
+            expires_at,
+            last_seen_at: now,
+            idle_timeout: lifetime.idle_timeout as i32,
+            idle_expires_at: std::cmp::min(
+                now + Duration::seconds(lifetime.idle_timeout as i64),
+                expires_at,
+            ),
+            ip: None,
+            user_agent: None,
+            impersonator: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now >= self.idle_expires_at || now >= self.expires_at
+    }
+
+    /// Marks the session as used at `now`, sliding its idle expiry forward.
+    pub fn renew(&mut self, now: DateTime<Utc>) {
+        self.last_seen_at = now;
+        self.idle_expires_at = std::cmp::min(
+            now + Duration::seconds(self.idle_timeout as i64),
+            self.expires_at,
+        );
+    }
+}
+
+/// A backend that keeps track of login sessions.
+#[rocket::async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Gets the unexpired session for a token hash and renews it.
+    async fn get(&self, token_hash: &str) -> anyhow::Result<Option<Session>>;
+
+    /// Stores a new session for a token hash.
+    async fn insert(&self, token_hash: &str, session: Session) -> anyhow::Result<()>;
+
+    /// Removes the session for a token hash, if there is one.
+    async fn revoke(&self, token_hash: &str) -> anyhow::Result<()>;
+
+    /// Gets every unexpired session of the given type and subject, along with its token
+    /// hash.
+    async fn list(&self, session_type: SessionType) -> anyhow::Result<Vec<(String, Session)>>;
+
+    /// Removes every session of the given type and subject, returning how many were removed.
+    async fn revoke_all(&self, session_type: SessionType) -> anyhow::Result<usize>;
+
+    /// Removes every expired session, returning how many were removed.
+    async fn sweep(&self) -> anyhow::Result<usize>;
+}
+
+/// Keeps sessions in process memory, so they are lost on restart and not shared
+/// between instances. Lookups only lock the shard holding the session.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: DashMap<String, Session>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> MemorySessionStore {
+        MemorySessionStore::default()
+    }
+}
+
+#[rocket::async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn get(&self, token_hash: &str) -> anyhow::Result<Option<Session>> {
+        let now = Utc::now();
+        self.sessions
+            .remove_if(token_hash, |_, session| session.is_expired(now));
+
+        Ok(self.sessions.get_mut(token_hash).map(|mut session| {
+            session.renew(now);
+            session.clone()
+        }))
+    }
+
+    async fn insert(&self, token_hash: &str, session: Session) -> anyhow::Result<()> {
+        self.sessions.insert(token_hash.to_string(), session);
+        Ok(())
+    }
+
+    async fn revoke(&self, token_hash: &str) -> anyhow::Result<()> {
+        self.sessions.remove(token_hash);
+        Ok(())
+    }
+
+    async fn list(&self, session_type: SessionType) -> anyhow::Result<Vec<(String, Session)>> {
+        let now = Utc::now();
+
+        Ok(self
+            .sessions
+            .iter()
+            .filter(|entry| entry.session_type == session_type && !entry.is_expired(now))
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect())
+    }
+
+    async fn revoke_all(&self, session_type: SessionType) -> anyhow::Result<usize> {
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|_, session| session.session_type != session_type);
+
+        Ok(before.saturating_sub(self.sessions.len()))
+    }
+
+    async fn sweep(&self) -> anyhow::Result<usize> {
+        let now = Utc::now();
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| !session.is_expired(now));
+
+        Ok(before.saturating_sub(self.sessions.len()))
+    }
+}
+
+type PgPool = Pool<ConnectionManager<PgConnection>>;
+
+/// Keeps sessions in the `sessions` table, so they survive restarts and are shared by
+/// every instance using the database.
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    /// Opens a connection pool of the given size for the database at `url`.
+    pub async fn connect(url: String, pool_size: u32) -> anyhow::Result<PgSessionStore> {
+        let pool = rocket::tokio::task::spawn_blocking(move || {
+            Pool::builder()
+                .max_size(pool_size)
+                .build(ConnectionManager::<PgConnection>::new(url))
+        })
+        .await??;
+
+        Ok(PgSessionStore { pool })
+    }
+
+    /// Runs a blocking database operation on a pooled connection.
+    async fn run<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&PgConnection) -> QueryResult<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let pool = self.pool.clone();
+
+        rocket::tokio::task::spawn_blocking(move || {
+            let c = pool.get()?;
+            Ok(f(&c)?)
+        })
+        .await?
+    }
+}
+
+fn session_from_row(row: SessionRow) -> Option<Session> {
+    Some(Session {
+        session_type: SessionType::from_db(&row.session_type, row.subject_id)?,
+        created_at: row.created_at,
+        expires_at: row.expires_at,
+        last_seen_at: row.last_seen_at,
+        idle_timeout: row.idle_timeout,
+        idle_expires_at: row.idle_expires_at,
+        ip: row.ip,
+        user_agent: row.user_agent,
+        impersonator: row.impersonator,
+    })
+}
+
+#[rocket::async_trait]
+impl SessionStore for PgSessionStore {
+    async fn get(&self, token_hash: &str) -> anyhow::Result<Option<Session>> {
+        use schema::sessions::dsl::{idle_expires_at, last_seen_at, sessions};
+
+        let session_token_hash = token_hash.to_string();
+        let now = Utc::now();
+        let session = self
+            .run(move |c| {
+                diesel::delete(
+                    sessions
+                        .find(&session_token_hash)
+                        .filter(idle_expires_at.le(now)),
+                )
+                .execute(c)?;
+
+                let session = sessions
+                    .find(&session_token_hash)
+                    .first::<SessionRow>(c)
+                    .optional()?
+                    .and_then(session_from_row);
+
+                match session {
+                    Some(mut session) => {
+                        session.renew(now);
+                        diesel::update(sessions.find(&session_token_hash))
+                            .set((
+                                last_seen_at.eq(session.last_seen_at),
+                                idle_expires_at.eq(session.idle_expires_at),
+                            ))
+                            .execute(c)?;
+                        Ok(Some(session))
+                    }
+                    None => Ok(None),
+                }
+            })
+            .await?;
+
+        Ok(session)
+    }
+
+    async fn insert(&self, token_hash: &str, session: Session) -> anyhow::Result<()> {
+        use schema::sessions;
+
+        let (kind, subject_id) = session.session_type.to_db();
+        let new_session = NewSession {
+            token_hash: token_hash.to_string(),
+            session_type: kind.to_string(),
+            subject_id,
+            created_at: session.created_at,
+            expires_at: session.expires_at,
+            last_seen_at: session.last_seen_at,
+            idle_timeout: session.idle_timeout,
+            idle_expires_at: session.idle_expires_at,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            impersonator: session.impersonator,
+        };
+
+        self.run(move |c| {
+            diesel::insert_into(sessions::table)
+                .values(&new_session)
+                .execute(c)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn revoke(&self, token_hash: &str) -> anyhow::Result<()> {
+        use schema::sessions::dsl::sessions;
+
+        let session_token_hash = token_hash.to_string();
+        self.run(move |c| diesel::delete(sessions.find(session_token_hash)).execute(c))
+            .await?;
+        Ok(())
+    }
+
+    async fn list(&self, session_type: SessionType) -> anyhow::Result<Vec<(String, Session)>> {
+        use schema::sessions::dsl::{
+            idle_expires_at, session_type as db_session_type, sessions, subject_id,
+        };
+
+        let (kind, subject) = session_type.to_db();
+        let rows = self
+            .run(move |c| {
+                let subject_sessions = sessions
+                    .filter(db_session_type.eq(kind))
+                    .filter(idle_expires_at.gt(Utc::now()));
+                subject_sessions
+                    .filter(subject_id.eq(subject))
+                    .load::<SessionRow>(c)
+            })
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let token_hash = row.token_hash.clone();
+                session_from_row(row).map(|session| (token_hash, session))
+            })
+            .collect())
+    }
+
+    async fn revoke_all(&self, session_type: SessionType) -> anyhow::Result<usize> {
+        use schema::sessions::dsl::{session_type as db_session_type, sessions, subject_id};
+
+        let (kind, subject) = session_type.to_db();
+        self.run(move |c| {
+            diesel::delete(
+                sessions
+                    .filter(db_session_type.eq(kind))
+                    .filter(subject_id.eq(subject)),
+            )
+            .execute(c)
+        })
+        .await
+    }
+
+    async fn sweep(&self) -> anyhow::Result<usize> {
+        use schema::sessions::dsl::{idle_expires_at, sessions};
+
+        self.run(|c| diesel::delete(sessions.filter(idle_expires_at.le(Utc::now()))).execute(c))
+            .await
+    }
+}
+
+/// Keeps sessions in Redis as JSON values that expire along with the session. Each
+/// subject also has a set of its token hashes so that its sessions can be revoked
+/// together, and `sweep` prunes the hashes of sessions Redis has already expired.
+pub struct RedisSessionStore {
+    connection: RedisConnectionManager,
+}
+
+impl RedisSessionStore {
+    pub async fn connect(url: &str) -> anyhow::Result<RedisSessionStore> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_tokio_connection_manager().await?;
+
+        Ok(RedisSessionStore { connection })
+    }
+
+    fn session_key(token_hash: &str) -> String {
+        format!("session:{}", token_hash)
+    }
+
+    /// Seconds until an unexpired session expires, rounded up so Redis never drops it early.
+    fn ttl(session: &Session, now: DateTime<Utc>) -> usize {
+        let remaining = session.idle_expires_at - now;
+        (remaining.num_seconds() + 1).max(1) as usize
+    }
+
+    fn subject_key(session_type: SessionType) -> String {
+        let (kind, subject_id) = session_type.to_db();
+        format!("sessions:{}:{}", kind, subject_id)
+    }
+}
+
+#[rocket::async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn get(&self, token_hash: &str) -> anyhow::Result<Option<Session>> {
+        let mut connection = self.connection.clone();
+        let key = RedisSessionStore::session_key(token_hash);
+
+        let value: Option<String> = connection.get(&key).await?;
+        let mut session = match value.map(|v| serde_json::from_str::<Session>(&v)) {
+            Some(Ok(session)) => session,
+            // Sessions that no longer deserialize were written by an incompatible version
+            Some(Err(_)) | None => return Ok(None),
+        };
+
+        let now = Utc::now();
+        if session.is_expired(now) {
+            connection.del::<_, ()>(&key).await?;
+            return Ok(None);
+        }
+
+        session.renew(now);
+        redis::cmd("SET")
+            .arg(&key)
+            .arg(serde_json::to_string(&session)?)
+            .arg("EX")
+            .arg(RedisSessionStore::ttl(&session, now))
+            .arg("XX")
+            .query_async::<_, ()>(&mut connection)
+            .await?;
+
+        Ok(Some(session))
+    }
+
+    async fn insert(&self, token_hash: &str, session: Session) -> anyhow::Result<()> {
+        let mut connection = self.connection.clone();
+        let now = Utc::now();
+        if session.is_expired(now) {
+            return Ok(());
+        }
+
+        redis::pipe()
+            .atomic()
+            .set_ex(
+                RedisSessionStore::session_key(token_hash),
+                serde_json::to_string(&session)?,
+                RedisSessionStore::ttl(&session, now),
+            )
+            .sadd(
+                RedisSessionStore::subject_key(session.session_type),
+                token_hash,
+            )
+            .query_async::<_, ()>(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke(&self, token_hash: &str) -> anyhow::Result<()> {
+        let mut connection = self.connection.clone();
+        let key = RedisSessionStore::session_key(token_hash);
+
+        let value: Option<String> = connection.get(&key).await?;
+        if let Some(Ok(session)) = value.map(|v| serde_json::from_str::<Session>(&v)) {
+            connection
+                .srem::<_, _, ()>(
+                    RedisSessionStore::subject_key(session.session_type),
+                    token_hash,
+                )
+                .await?;
+        }
+
+        connection.del::<_, ()>(&key).await?;
+        Ok(())
+    }
+
+    async fn list(&self, session_type: SessionType) -> anyhow::Result<Vec<(String, Session)>> {
+        let mut connection = self.connection.clone();
+
+        let token_hashes: Vec<String> = connection
+            .smembers(RedisSessionStore::subject_key(session_type))
+            .await?;
+        if token_hashes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let session_keys: Vec<String> = token_hashes
+            .iter()
+            .map(|token_hash| RedisSessionStore::session_key(token_hash))
+            .collect();
+        let values: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(session_keys)
+            .query_async(&mut connection)
+            .await?;
+
+        let now = Utc::now();
+        Ok(token_hashes
+            .into_iter()
+            .zip(values)
+            .filter_map(|(token_hash, value)| {
+                let session = serde_json::from_str::<Session>(&value?).ok()?;
+                (!session.is_expired(now)).then_some((token_hash, session))
+            })
+            .collect())
+    }
+
+    async fn revoke_all(&self, session_type: SessionType) -> anyhow::Result<usize> {
+        let mut connection = self.connection.clone();
+        let subject_key = RedisSessionStore::subject_key(session_type);
+
+        let token_hashes: Vec<String> = connection.smembers(&subject_key).await?;
+        let session_keys: Vec<String> = token_hashes
+            .iter()
+            .map(|token_hash| RedisSessionStore::session_key(token_hash))
+            .collect();
+
+        if session_keys.is_empty() {
+            connection.del::<_, ()>(&subject_key).await?;
+            return Ok(0);
+        }
+
+        let (removed,): (usize,) = redis::pipe()
+            .atomic()
+            .del(&subject_key)
+            .ignore()
+            .del(session_keys)
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(removed)
+    }
+
+    async fn sweep(&self) -> anyhow::Result<usize> {
+        let mut connection = self.connection.clone();
+
+        let subject_keys: Vec<String> = {
+            let mut iter = connection.scan_match::<_, String>("sessions:*").await?;
+            let mut keys = Vec::new();
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            keys
+        };
+
+        let mut pruned = 0;
+        for subject_key in subject_keys {
+            let token_hashes: Vec<String> = connection.smembers(&subject_key).await?;
+            for token_hash in token_hashes {
+                let exists: bool = connection
+                    .exists(RedisSessionStore::session_key(&token_hash))
+                    .await?;
+                if !exists {
+                    connection
+                        .srem::<_, _, ()>(&subject_key, &token_hash)
+                        .await?;
+                    pruned += 1;
+                }
+            }
+        }
+
+        Ok(pruned)
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionBackend {
+    Memory,
+    Postgres,
+    Redis,
+}
+
+/// The `sessions` section of the Rocket config.
+#[derive(Deserialize, Debug)]
+pub struct SessionConfig {
+    #[serde(default = "default_session_backend")]
+    pub store: SessionBackend,
+    #[serde(default)]
+    pub redis_url: Option<String>,
+    /// Size of the connection pool used by the Postgres store.
+    #[serde(default = "default_postgres_pool_size")]
+    pub pool_size: u32,
+    /// Seconds between sweeps of expired sessions.
+    #[serde(default = "default_sweep_interval")]
+    pub sweep_interval: u64,
+    #[serde(default)]
+    pub lifetimes: SessionLifetimes,
+    /// Seconds an administrator may impersonate an account for.
+    #[serde(default = "default_impersonation_lifetime")]
+    pub impersonation_lifetime: u32,
+}
+
+/// How long a session may last, in seconds.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct SessionLifetime {
+    pub idle_timeout: u32,
+    pub absolute_lifetime: u32,
+}
+
+/// The session lifetime for each kind of session.
+#[derive(Deserialize, Debug)]
+pub struct SessionLifetimes {
+    #[serde(default = "default_user_lifetime")]
+    pub applicant: SessionLifetime,
+    #[serde(default = "default_user_lifetime")]
+    pub professor: SessionLifetime,
+    #[serde(default = "default_administrator_lifetime")]
+    pub administrator: SessionLifetime,
+}
+
+impl SessionLifetimes {
+    pub fn for_session_type(&self, session_type: SessionType) -> SessionLifetime {
+        match session_type {
+            SessionType::Applicant(_) => self.applicant,
+            SessionType::Professor(_) => self.professor,
+            SessionType::Administrator(_) => self.administrator,
+        }
+    }
+}
+
+impl Default for SessionLifetimes {
+    fn default() -> SessionLifetimes {
+        SessionLifetimes {
+            applicant: default_user_lifetime(),
+            professor: default_user_lifetime(),
+            administrator: default_administrator_lifetime(),
+        }
+    }
+}
+
+fn default_session_backend() -> SessionBackend {
+    SessionBackend::Postgres
+}
+
+fn default_postgres_pool_size() -> u32 {
+    4
+}
+
+fn default_sweep_interval() -> u64 {
+    60 * 10
+}
+
+fn default_impersonation_lifetime() -> u32 {
+    60 * 15
+}
+
+fn default_user_lifetime() -> SessionLifetime {
+    SessionLifetime {
+        idle_timeout: 60 * 60 * 24 * 7,
+        absolute_lifetime: 60 * 60 * 24 * 30,
+    }
+}
+
+fn default_administrator_lifetime() -> SessionLifetime {
+    SessionLifetime {
+        idle_timeout: 60 * 30,
+        absolute_lifetime: 60 * 60 * 12,
+    }
+}
+
+/// Connects to the session backend chosen in the Rocket config.
+async fn connect(
+    rocket: &Rocket<Build>,
+    config: &SessionConfig,
+) -> anyhow::Result<SessionStoreState> {
+    let store: SessionStoreState = match config.store {
+        SessionBackend::Memory => Arc::new(MemorySessionStore::new()),
+        SessionBackend::Postgres => {
+            let db_config = rocket_sync_db_pools::Config::from("db", rocket)?;
+            Arc::new(PgSessionStore::connect(db_config.url, config.pool_size).await?)
+        }
+        SessionBackend::Redis => {
+            let url = config
+                .redis_url
+                .clone()
+                .ok_or_else(|| anyhow!("sessions.redis_url must be set to use redis"))?;
+            Arc::new(RedisSessionStore::connect(&url).await?)
+        }
+    };
+
+    Ok(store)
+}
+
+/// Connects to the configured session backend and manages it as `SessionStoreState`,
+/// along with the `SessionConfig` it was set up from.
+pub fn fairing() -> impl Fairing {
+    AdHoc::try_on_ignite("Session Store", |rocket| async move {
+        let config = match rocket
+            .figment()
+            .focus("sessions")
+            .extract::<SessionConfig>()
+        {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Invalid session config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        match connect(&rocket, &config).await {
+            Ok(store) => Ok(rocket.manage(store).manage(config)),
+            Err(e) => {
+                eprintln!("Could not set up session store: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+/// Periodically removes expired sessions from the session store once launched.
+pub fn sweeper() -> impl Fairing {
+    AdHoc::on_liftoff("Session Sweeper", |rocket| {
+        Box::pin(async move {
+            let store = rocket.state::<SessionStoreState>().cloned();
+            let sweep_interval = rocket.state::<SessionConfig>().map(|c| c.sweep_interval);
+
+            if let (Some(store), Some(sweep_interval)) = (store, sweep_interval) {
+                rocket::tokio::spawn(async move {
+                    let mut interval =
+                        rocket::tokio::time::interval(StdDuration::from_secs(sweep_interval));
+                    loop {
+                        interval.tick().await;
+                        if let Err(e) = store.sweep().await {
+                            eprintln!("Error occured while trying to sweep sessions: {}", e);
+                        }
+                    }
+                });
+            }
+        })
+    })
+}
+
+/// Session store tests. The Redis tests need a local redis-server, and are run with
+/// `REDIS_URL=redis://127.0.0.1/ cargo test -- --ignored`.
+#[cfg(test)]
+mod test {
+    use super::{MemorySessionStore, RedisSessionStore, Session, SessionLifetime, SessionStore};
+    use crate::request_guards::state::SessionType;
+    use chrono::{Duration, Utc};
+
+    const HOUR: SessionLifetime = SessionLifetime {
+        idle_timeout: 60 * 60,
+        absolute_lifetime: 60 * 60,
+    };
+
+    // Creates a session that has already expired.
+    fn expired_session(session_type: SessionType) -> Session {
+        let mut session = Session::new(session_type, HOUR);
+        session.expires_at = Utc::now() - Duration::hours(1);
+        session.idle_expires_at = session.expires_at;
+        session
+    }
+
+    // Runs the behaviour every store must have against the given store.
+    async fn check_store(store: &dyn SessionStore) {
+        let session = Session::new(SessionType::Applicant(1), HOUR);
+        store.insert("live", session).await.expect("insert failed");
+
+        let expired = expired_session(SessionType::Professor(2));
+        store
+            .insert("expired", expired)
+            .await
+            .expect("insert failed");
+
+        match store.get("live").await.expect("get failed") {
+            Some(Session {
+                session_type: SessionType::Applicant(1),
+                ..
+            }) => {}
+            other => panic!("unexpected session {:?}", other),
+        }
+        assert!(store.get("expired").await.expect("get failed").is_none());
+        assert!(store.get("missing").await.expect("get failed").is_none());
+
+        store.revoke("live").await.expect("revoke failed");
+        assert!(store.get("live").await.expect("get failed").is_none());
+
+        for token_hash in ["first", "second"] {
+            let session = Session::new(SessionType::Applicant(3), HOUR);
+            store
+                .insert(token_hash, session)
+                .await
+                .expect("insert failed");
+        }
+        let other = Session::new(SessionType::Applicant(4), HOUR);
+        store.insert("other", other).await.expect("insert failed");
+
+        let mut listed: Vec<String> = store
+            .list(SessionType::Applicant(3))
+            .await
+            .expect("list failed")
+            .into_iter()
+            .map(|(token_hash, _)| token_hash)
+            .collect();
+        listed.sort();
+        assert_eq!(listed, ["first", "second"]);
+
+        assert_eq!(
+            store
+                .revoke_all(SessionType::Applicant(3))
+                .await
+                .expect("revoke all failed"),
+            2
+        );
+        assert!(store.get("first").await.expect("get failed").is_none());
+        assert!(store.get("other").await.expect("get failed").is_some());
+    }
+
+    #[rocket::async_test]
+    async fn memory_store() {
+        check_store(&MemorySessionStore::new()).await;
+    }
+
+    #[rocket::async_test]
+    async fn memory_store_sweep() {
+        let store = MemorySessionStore::new();
+        let expired = expired_session(SessionType::Administrator(1));
+        store
+            .insert("expired", expired)
+            .await
+            .expect("insert failed");
+
+        assert_eq!(store.sweep().await.expect("sweep failed"), 1);
+        assert_eq!(store.sweep().await.expect("sweep failed"), 0);
+    }
+
+    // Tests that renewing slides the idle expiry without passing the absolute expiry.
+    #[test]
+    fn renew_is_capped_by_absolute_lifetime() {
+        let mut session = Session::new(
+            SessionType::Applicant(1),
+            SessionLifetime {
+                idle_timeout: 60,
+                absolute_lifetime: 90,
+            },
+        );
+
+        let later = session.created_at + Duration::seconds(45);
+        session.renew(later);
+        assert_eq!(session.idle_expires_at, session.expires_at);
+        assert!(!session.is_expired(later));
+        assert!(session.is_expired(session.expires_at));
+    }
+
+    #[rocket::async_test]
+    #[ignore]
+    async fn redis_store() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
+        let store = RedisSessionStore::connect(&url)
+            .await
+            .expect("could not connect to redis");
+
+        check_store(&store).await;
+    }
+}
@@ -0,0 +1,245 @@
+//! Stateless session tokens, an alternative to keeping sessions in the session store.
+//! When enabled, logging in issues a token holding the session type, its expiry and the
+//! account's revocation generation, encrypted and authenticated with Rocket's
+//! `secret_key`. Verifying one needs no session lookup, only the account's current
+//! generation, which is cached for `generation_cache_ttl` seconds. Revoking sessions
+//! bumps the generation, so every earlier token of the account stops working.
+//!
+//! ```toml
+//! [default.stateless_tokens]
+//! enabled = true
+//! generation_cache_ttl = 30
+//! ```
+//!
+//! Stateless tokens cannot be renewed, so they last for the absolute lifetime of their
+//! session type rather than sliding with use.
+
+use crate::db::{self, DbConn};
+use crate::request_guards::state::SessionType;
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use cookie::{Cookie, CookieJar, Key};
+use dashmap::DashMap;
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::{Build, Rocket};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration as StdDuration, Instant};
+
+/// Name the claims are encrypted under, which binds the ciphertext to this use.
+const TOKEN_NAME: &str = "session";
+
+/// The `stateless_tokens` section of the Rocket config.
+#[derive(Deserialize, Debug)]
+pub struct StatelessTokenConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Seconds a revocation generation read from the database is trusted for.
+    #[serde(default = "default_generation_cache_ttl")]
+    pub generation_cache_ttl: u64,
+}
+
+fn default_generation_cache_ttl() -> u64 {
+    30
+}
+
+/// What a stateless token says about its session.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Claims {
+    pub session_type: SessionType,
+    pub expires_at: DateTime<Utc>,
+    pub generation: i32,
+}
+
+pub struct StatelessTokens {
+    enabled: bool,
+    key: Key,
+    generation_cache_ttl: StdDuration,
+    generations: DashMap<SessionType, (i32, Instant)>,
+}
+
+impl StatelessTokens {
+    pub fn new(config: &StatelessTokenConfig, key: Key) -> StatelessTokens {
+        StatelessTokens {
+            enabled: config.enabled,
+            key,
+            generation_cache_ttl: StdDuration::from_secs(config.generation_cache_ttl),
+            generations: DashMap::new(),
+        }
+    }
+
+    /// Whether logins should issue stateless tokens.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Encrypts a set of claims into a token.
+    pub fn encode(&self, claims: &Claims) -> anyhow::Result<String> {
+        let mut jar = CookieJar::new();
+        jar.private_mut(&self.key)
+            .add(Cookie::new(TOKEN_NAME, serde_json::to_string(claims)?));
+
+        Ok(jar
+            .get(TOKEN_NAME)
+            .map(|cookie| cookie.value().to_string())
+            .unwrap_or_default())
+    }
+
+    /// Decrypts the claims of a token, if it is a stateless token made with our key.
+    pub fn decode(&self, token: &str) -> Option<Claims> {
+        let cookie = CookieJar::new()
+            .private(&self.key)
+            .decrypt(Cookie::new(TOKEN_NAME, token.to_string()))?;
+
+        serde_json::from_str(cookie.value()).ok()
+    }
+
+    /// Issues a token for a new session of an account.
+    pub async fn issue(
+        &self,
+        conn: &DbConn,
+        session_type: SessionType,
+        lifetime: Duration,
+    ) -> anyhow::Result<String> {
+        let claims = Claims {
+            session_type,
+            expires_at: Utc::now() + lifetime,
+            generation: self.generation(conn, session_type).await?,
+        };
+
+        self.encode(&claims)
+    }
+
+    /// Gets the cached revocation generation of an account, if it is fresh enough.
+    pub fn cached_generation(&self, account: SessionType) -> Option<i32> {
+        self.generations
+            .get(&account)
+            .filter(|entry| entry.1.elapsed() < self.generation_cache_ttl)
+            .map(|entry| entry.0)
+    }
+
+    /// Gets the current revocation generation of an account.
+    pub async fn generation(&self, conn: &DbConn, account: SessionType) -> anyhow::Result<i32> {
+        if let Some(generation) = self.cached_generation(account) {
+            return Ok(generation);
+        }
+
+        let generation = db::get_session_generation(conn, account).await?;
+        self.generations
+            .insert(account, (generation, Instant::now()));
+        Ok(generation)
+    }
+
+    /// Revokes every stateless token issued to an account.
+    pub async fn revoke_all(&self, conn: &DbConn, account: SessionType) -> anyhow::Result<()> {
+        let generation = db::bump_session_generation(conn, account).await?;
+        self.generations
+            .insert(account, (generation, Instant::now()));
+        Ok(())
+    }
+}
+
+/// Builds the token key from Rocket's `secret_key`, which is either 64 bytes used as is
+/// or 32 bytes that a key is derived from, in base64 or hex.
+fn secret_key(rocket: &Rocket<Build>) -> anyhow::Result<Key> {
+    let secret_key = match rocket.figment().extract_inner::<String>("secret_key") {
+        Ok(secret_key) => secret_key,
+        Err(_) => {
+            eprintln!("No secret_key is set, stateless tokens will not survive a restart");
+            return Ok(Key::generate());
+        }
+    };
+
+    let bytes = base64::decode(&secret_key)
+        .ok()
+        .or_else(|| decode_hex(&secret_key))
+        .ok_or_else(|| anyhow!("secret_key is neither base64 nor hex"))?;
+
+    match bytes.len() {
+        64 => Ok(Key::from(&bytes)),
+        32 => Ok(Key::derive_from(&bytes)),
+        length => Err(anyhow!(
+            "secret_key must be 32 or 64 bytes, found {} bytes",
+            length
+        )),
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match std::str::from_utf8(pair) {
+            Ok(pair) if pair.len() == 2 => u8::from_str_radix(pair, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Reads the `stateless_tokens` config and manages `StatelessTokens`.
+pub fn fairing() -> impl Fairing {
+    AdHoc::try_on_ignite("Stateless Tokens", |rocket| async move {
+        let config = match rocket
+            .figment()
+            .focus("stateless_tokens")
+            .extract::<StatelessTokenConfig>()
+        {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Invalid stateless token config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        match secret_key(&rocket) {
+            Ok(key) => Ok(rocket.manage(StatelessTokens::new(&config, key))),
+            Err(e) => {
+                eprintln!("Could not set up stateless tokens: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+/// Stateless token tests.
+#[cfg(test)]
+mod test {
+    use super::{Claims, StatelessTokenConfig, StatelessTokens};
+    use crate::request_guards::state::SessionType;
+    use chrono::{Duration, Utc};
+    use cookie::Key;
+
+    fn new_tokens() -> StatelessTokens {
+        StatelessTokens::new(
+            &StatelessTokenConfig {
+                enabled: true,
+                generation_cache_ttl: 30,
+            },
+            Key::generate(),
+        )
+    }
+
+    // Tests that claims survive a round trip and that tampered or foreign tokens do not.
+    #[test]
+    fn tokens_round_trip_and_reject_tampering() {
+        let tokens = new_tokens();
+        let claims = Claims {
+            session_type: SessionType::Professor(3),
+            expires_at: Utc::now() + Duration::hours(1),
+            generation: 2,
+        };
+
+        let token = tokens.encode(&claims).expect("could not encode claims");
+        assert_eq!(tokens.decode(&token), Some(claims));
+
+        let mut tampered = token.into_bytes();
+        let last = tampered.len() / 2;
+        tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).expect("token is not utf8");
+        assert_eq!(tokens.decode(&tampered), None);
+
+        let other_key_token = new_tokens()
+            .encode(&claims)
+            .expect("could not encode claims");
+        assert_eq!(tokens.decode(&other_key_token), None);
+        assert_eq!(tokens.decode("not a token"), None);
+    }
+}
@@ -0,0 +1,195 @@
+//! RFC 6238 time-based one-time passwords, used as a second login factor. Administrators
+//! may always enroll, and professors may when `professors` is set:
+//!
+//! ```toml
+//! [default.totp]
+//! issuer = "SYSC4806 Admissions"
+//! professors = true
+//! pre_auth_lifetime = 300
+//! ```
+//!
+//! Accounts with a confirmed enrollment log in in two steps. `POST /rest/login` answers
+//! a correct password with a short-lived pre-auth token, which is exchanged for a session
+//! at `POST /rest/login/totp` along with a current code or an unused recovery code.
+
+use crate::request_guards::state::SessionType;
+use hmac::{Hmac, Mac};
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rocket::fairing::{AdHoc, Fairing};
+use serde::Deserialize;
+use sha1::Sha1;
+
+/// Seconds each code is valid for.
+pub const TOTP_PERIOD: i64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+const SECRET_LENGTH: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/// The `totp` section of the Rocket config.
+#[derive(Deserialize, Debug)]
+pub struct TotpConfig {
+    /// Name shown for the account in authenticator apps.
+    #[serde(default = "default_issuer")]
+    pub issuer: String,
+    /// Whether professors may enroll as well as administrators.
+    #[serde(default)]
+    pub professors: bool,
+    /// Seconds a pre-auth token can be exchanged for a session.
+    #[serde(default = "default_pre_auth_lifetime")]
+    pub pre_auth_lifetime: u32,
+}
+
+fn default_issuer() -> String {
+    "SYSC4806 Admissions".to_string()
+}
+
+fn default_pre_auth_lifetime() -> u32 {
+    60 * 5
+}
+
+impl TotpConfig {
+    /// Checks whether an account may enroll in two-factor authentication.
+    pub fn can_enroll(&self, account: SessionType) -> bool {
+        match account {
+            SessionType::Administrator(_) => true,
+            SessionType::Professor(_) => self.professors,
+            SessionType::Applicant(_) => false,
+        }
+    }
+}
+
+/// Generates a new random shared secret.
+pub fn generate_secret() -> Vec<u8> {
+    let mut rng = rand_chacha::ChaCha12Rng::from_entropy();
+    let mut secret = vec![0; SECRET_LENGTH];
+    rng.fill_bytes(&mut secret);
+    secret
+}
+
+/// Generates a set of single-use recovery codes.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand_chacha::ChaCha12Rng::from_entropy();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut code = [0; RECOVERY_CODE_LENGTH];
+            rng.fill_bytes(&mut code);
+            encode_secret(&code)[..RECOVERY_CODE_LENGTH].to_lowercase()
+        })
+        .collect()
+}
+
+/// Encodes a secret in the unpadded base32 that authenticator apps expect.
+pub fn encode_secret(secret: &[u8]) -> String {
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+pub fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+/// Builds the `otpauth://` URI that authenticator apps enroll from, usually shown as a
+/// QR code.
+pub fn provisioning_uri(issuer: &str, account_name: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account_name),
+        encode_secret(secret),
+        percent_encode(issuer),
+        TOTP_DIGITS,
+        TOTP_PERIOD
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Gets the time step a unix timestamp falls in.
+pub fn time_step(unix_time: i64) -> i64 {
+    unix_time.div_euclid(TOTP_PERIOD)
+}
+
+/// Computes the code for a time step, as in RFC 4226.
+pub fn code_at(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Checks a code against the current time step and one step either side of it, to allow
+/// for clock drift. Returns the time step the code matched.
+pub fn verify(secret: &[u8], code: &str, unix_time: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    let step = time_step(unix_time);
+    (step - 1..=step + 1).find(|&candidate| code_at(secret, candidate) == code)
+}
+
+/// Reads the `totp` config and manages it as `TotpConfig`.
+pub fn fairing() -> impl Fairing {
+    AdHoc::try_on_ignite("TOTP", |rocket| async move {
+        match rocket.figment().focus("totp").extract::<TotpConfig>() {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(e) => {
+                eprintln!("Invalid TOTP config: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+/// TOTP tests.
+#[cfg(test)]
+mod test {
+    use super::{code_at, time_step, verify};
+
+    // The SHA1 test vectors from appendix B of RFC 6238, truncated to six digits.
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        let secret = b"12345678901234567890";
+
+        assert_eq!(code_at(secret, time_step(59)), 287082);
+        assert_eq!(code_at(secret, time_step(1111111109)), 81804);
+        assert_eq!(code_at(secret, time_step(1234567890)), 5924);
+        assert_eq!(code_at(secret, time_step(2000000000)), 279037);
+    }
+
+    // Tests that codes are accepted one step either side of now, and no further.
+    #[test]
+    fn verify_allows_one_step_of_drift() {
+        let secret = b"12345678901234567890";
+        let now = 1111111109;
+        let step = time_step(now);
+
+        let code = |step| format!("{:06}", code_at(secret, step));
+        assert_eq!(verify(secret, &code(step), now), Some(step));
+        assert_eq!(verify(secret, &code(step - 1), now), Some(step - 1));
+        assert_eq!(verify(secret, &code(step + 1), now), Some(step + 1));
+        assert_eq!(verify(secret, &code(step + 2), now), None);
+        assert_eq!(verify(secret, "12345", now), None);
+    }
+}